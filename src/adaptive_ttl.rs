@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+use serde::Deserialize;
+
+/// Picks a short TTL right after the address changed and ramps it up linearly to `max`
+/// once the address has been stable for `ramp_seconds`.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AdaptiveTtl {
+    pub min: i64,
+    pub max: i64,
+    pub ramp_seconds: u64,
+}
+
+impl AdaptiveTtl {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.min <= 0 || self.min > self.max {
+            return Err(anyhow!(
+                "Adaptive TTL bounds must satisfy 0 < min <= max, got {} and {}.",
+                self.min,
+                self.max
+            ));
+        }
+        Ok(())
+    }
+
+    /// The TTL for an address that has been stable for `stable_for`. `None` means it
+    /// never changed while we were watching, so it gets the full TTL.
+    pub fn ttl(&self, stable_for: Option<Duration>) -> i64 {
+        let Some(stable_for) = stable_for else {
+            return self.max;
+        };
+        if self.ramp_seconds == 0 {
+            return self.max;
+        }
+        let progress =
+            stable_for.as_secs().min(self.ramp_seconds) as f64 / self.ramp_seconds as f64;
+        self.min + ((self.max - self.min) as f64 * progress) as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: AdaptiveTtl = AdaptiveTtl {
+        min: 60,
+        max: 3600,
+        ramp_seconds: 1000,
+    };
+
+    #[test]
+    fn ramps_from_min_to_max() {
+        assert_eq!(TTL.ttl(Some(Duration::ZERO)), 60);
+        assert_eq!(TTL.ttl(Some(Duration::from_secs(500))), 1830);
+        assert_eq!(TTL.ttl(Some(Duration::from_secs(1000))), 3600);
+        assert_eq!(TTL.ttl(Some(Duration::from_secs(50_000))), 3600);
+    }
+
+    #[test]
+    fn never_changed_gets_the_full_ttl() {
+        assert_eq!(TTL.ttl(None), 3600);
+    }
+
+    #[test]
+    fn rejects_inverted_bounds() {
+        let ttl = AdaptiveTtl { min: 600, ..TTL };
+        assert!(ttl.validate().is_ok());
+        assert!(AdaptiveTtl { min: 4000, ..TTL }.validate().is_err());
+        assert!(AdaptiveTtl { min: 0, ..TTL }.validate().is_err());
+    }
+}
@@ -0,0 +1,134 @@
+//! Wakes the daemon loop as soon as the kernel reports an address change, so the new
+//! address after an ISP reconnect is published without waiting for the next interval.
+
+use std::time::Duration;
+
+#[cfg(not(target_os = "linux"))]
+use anyhow::anyhow;
+use anyhow::Error;
+#[cfg(target_os = "linux")]
+use netlink_sys::{protocols::NETLINK_ROUTE, AsyncSocket, AsyncSocketExt, SocketAddr, TokioSocket};
+use tokio::time;
+
+/// Long enough for a reconnect to finish assigning both address families.
+pub const DEFAULT_DEBOUNCE_SECONDS: u64 = 5;
+
+#[cfg(target_os = "linux")]
+const RTMGRP_IPV4_IFADDR: u32 = 0x10;
+#[cfg(target_os = "linux")]
+const RTMGRP_IPV6_IFADDR: u32 = 0x100;
+#[cfg(target_os = "linux")]
+const RTM_NEWADDR: u16 = 20;
+#[cfg(target_os = "linux")]
+const RTM_DELADDR: u16 = 21;
+/// `struct nlmsghdr` is 16 bytes, followed by `struct ifaddrmsg` whose interface index
+/// sits at offset 4.
+#[cfg(target_os = "linux")]
+const NLMSG_HEADER_LEN: usize = 16;
+
+/// Subscribed to rtnetlink's IPv4 and IPv6 address notifications.
+#[cfg(target_os = "linux")]
+pub struct AddressWatch {
+    socket: TokioSocket,
+    interface: Option<String>,
+}
+
+#[cfg(target_os = "linux")]
+impl AddressWatch {
+    /// Watches `interface`, or every interface when it's `None`.
+    pub fn new(interface: Option<String>) -> Result<Self, Error> {
+        let mut socket = TokioSocket::new(NETLINK_ROUTE)?;
+        socket
+            .socket_mut()
+            .bind(&SocketAddr::new(0, RTMGRP_IPV4_IFADDR | RTMGRP_IPV6_IFADDR))?;
+        Ok(Self { socket, interface })
+    }
+
+    /// Waits until an address is added to or removed from the watched interface.
+    pub async fn changed(&mut self) -> Result<(), Error> {
+        loop {
+            let (messages, _) = self.socket.recv_from_full().await?;
+            let index = match &self.interface {
+                Some(name) => match interface_index(name) {
+                    Some(index) => Some(index),
+                    // The interface is gone, e.g. a PPP link being torn down. The change
+                    // that brings it back is the one worth waiting for.
+                    None => continue,
+                },
+                None => None,
+            };
+            if address_messages(&messages).any(|changed| index.is_none_or(|i| i == changed)) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Interface indexes of the address notifications in one netlink datagram.
+#[cfg(target_os = "linux")]
+fn address_messages(mut buf: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    std::iter::from_fn(move || loop {
+        if buf.len() < NLMSG_HEADER_LEN {
+            return None;
+        }
+        let len = u32::from_ne_bytes(buf[0..4].try_into().unwrap()) as usize;
+        let kind = u16::from_ne_bytes(buf[4..6].try_into().unwrap());
+        if len < NLMSG_HEADER_LEN || len > buf.len() {
+            return None;
+        }
+        let message = &buf[..len];
+        // Messages are padded to 4 bytes.
+        buf = &buf[len.next_multiple_of(4).min(buf.len())..];
+        if (kind == RTM_NEWADDR || kind == RTM_DELADDR) && message.len() >= NLMSG_HEADER_LEN + 8 {
+            let index = &message[NLMSG_HEADER_LEN + 4..NLMSG_HEADER_LEN + 8];
+            return Some(u32::from_ne_bytes(index.try_into().unwrap()));
+        }
+    })
+}
+
+/// Looked up on every change, since PPP interfaces come back with a new index.
+#[cfg(target_os = "linux")]
+fn interface_index(name: &str) -> Option<u32> {
+    std::fs::read_to_string(format!("/sys/class/net/{name}/ifindex"))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub struct AddressWatch;
+
+#[cfg(not(target_os = "linux"))]
+impl AddressWatch {
+    pub fn new(_interface: Option<String>) -> Result<Self, Error> {
+        Err(anyhow!(
+            "--watch-address-changes is only supported on Linux."
+        ))
+    }
+
+    pub async fn changed(&mut self) -> Result<(), Error> {
+        std::future::pending().await
+    }
+}
+
+pub fn default_debounce_seconds() -> u64 {
+    DEFAULT_DEBOUNCE_SECONDS
+}
+
+/// Resolves once an address change has been followed by `debounce` without further
+/// changes, so the burst of notifications a reconnect causes triggers a single update.
+/// Never resolves without a watch.
+pub async fn next_change(
+    watch: &mut Option<AddressWatch>,
+    debounce: Duration,
+) -> Result<(), Error> {
+    let Some(watch) = watch else {
+        return std::future::pending().await;
+    };
+    watch.changed().await?;
+    while let Ok(changed) = time::timeout(debounce, watch.changed()).await {
+        changed?;
+    }
+    Ok(())
+}
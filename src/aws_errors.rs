@@ -0,0 +1,159 @@
+use std::fmt::Display;
+
+use anyhow::Error;
+use aws_sdk_route53::error::{ProvideErrorMetadata, SdkError};
+use log::error;
+
+/// The action and resource named in an AWS AccessDenied message.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DeniedPermission {
+    pub action: String,
+    pub resource: Option<String>,
+}
+
+impl Display for DeniedPermission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.resource {
+            Some(resource) => write!(
+                f,
+                "Denied: {} on {resource}; add this to your IAM policy.",
+                self.action
+            ),
+            None => write!(f, "Denied: {}; add this to your IAM policy.", self.action),
+        }
+    }
+}
+
+/// Extracts the denied action and resource from messages like
+/// `User: ... is not authorized to perform: route53:ChangeResourceRecordSets on resource: arn:... because ...`.
+pub fn parse_access_denied(message: &str) -> Option<DeniedPermission> {
+    let (_, rest) = message.split_once("perform: ")?;
+    let action = rest.split_whitespace().next()?.to_owned();
+    let resource = rest
+        .split_once("on resource: ")
+        .and_then(|(_, rest)| rest.split_whitespace().next())
+        .map(|resource| resource.to_owned());
+    Some(DeniedPermission { action, resource })
+}
+
+/// Whether Route53 asked the caller to slow down: `Throttling` past its limit of five
+/// requests per second per account, `PriorRequestNotComplete` while an earlier change to
+/// the same zone is still being applied.
+pub fn is_throttled<E: ProvideErrorMetadata>(err: &E) -> bool {
+    matches!(
+        err.code(),
+        Some("Throttling" | "ThrottlingException" | "PriorRequestNotComplete")
+    )
+}
+
+/// An AWS error that may go away when the call is retried: a timeout, a failed
+/// connection, a garbled response or AWS asking us to slow down or failing on its side.
+#[derive(Debug)]
+pub struct TransientError(Box<dyn std::error::Error + Send + Sync>);
+
+impl Display for TransientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for TransientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+fn is_transient<E: ProvideErrorMetadata, R>(err: &SdkError<E, R>) -> bool {
+    match err {
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => {
+            true
+        }
+        SdkError::ServiceError(_) => {
+            is_throttled(err)
+                || matches!(
+                    err.code(),
+                    Some("InternalError" | "InternalFailure" | "ServiceUnavailable")
+                )
+        }
+        _ => false,
+    }
+}
+
+/// Converts an AWS SDK error, logging a targeted message when it is an AccessDenied
+/// error naming the missing permission and marking it as a [`TransientError`] when a
+/// retry may succeed.
+pub fn explain_denied<E, R>(err: SdkError<E, R>) -> Error
+where
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+    R: std::fmt::Debug + Send + Sync + 'static,
+{
+    let denied = matches!(err.code(), Some("AccessDenied" | "AccessDeniedException"))
+        .then(|| err.message().and_then(parse_access_denied))
+        .flatten();
+    match denied {
+        Some(denied) => {
+            error!("{denied}");
+            Error::new(err).context(denied.to_string())
+        }
+        None if is_transient(&err) => Error::new(TransientError(Box::new(err))),
+        None => err.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{http_server, route53_client};
+
+    const DENIED: &str = "User: arn:aws:iam::123456789012:user/ddns is not authorized to perform: \
+                          route53:ChangeResourceRecordSets on resource: \
+                          arn:aws:route53:::hostedzone/Z1 because no identity-based policy \
+                          allows the route53:ChangeResourceRecordSets action";
+
+    /// Fails `GetHostedZone` with a 403 carrying `code` and `message`.
+    async fn denied_error(code: &str, message: &str) -> Error {
+        let body = format!(
+            "<ErrorResponse xmlns=\"https://route53.amazonaws.com/doc/2013-04-01/\"><Error>\
+             <Type>Sender</Type><Code>{code}</Code><Message>{message}</Message></Error>\
+             <RequestId>r</RequestId></ErrorResponse>"
+        );
+        let (url, _) = http_server(move |_| (403, body.clone())).await;
+        let err = route53_client(&url)
+            .get_hosted_zone()
+            .id("Z1")
+            .send()
+            .await
+            .unwrap_err();
+        explain_denied(err)
+    }
+
+    #[test]
+    fn parses_the_action_and_resource() {
+        assert_eq!(
+            parse_access_denied(DENIED),
+            Some(DeniedPermission {
+                action: "route53:ChangeResourceRecordSets".to_owned(),
+                resource: Some("arn:aws:route53:::hostedzone/Z1".to_owned()),
+            })
+        );
+        assert_eq!(parse_access_denied("Access Denied"), None);
+    }
+
+    #[tokio::test]
+    async fn names_the_missing_permission() {
+        let err = denied_error("AccessDenied", DENIED).await;
+        assert_eq!(
+            err.to_string(),
+            "Denied: route53:ChangeResourceRecordSets on arn:aws:route53:::hostedzone/Z1; \
+             add this to your IAM policy."
+        );
+        assert!(!crate::retry::is_transient(&err));
+    }
+
+    #[tokio::test]
+    async fn keeps_an_unrecognized_message() {
+        let err = denied_error("AccessDenied", "Access Denied").await;
+        assert!(!err.to_string().starts_with("Denied:"), "{err}");
+        assert!(format!("{err:?}").contains("Access Denied"), "{err:?}");
+    }
+}
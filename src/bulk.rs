@@ -0,0 +1,151 @@
+use std::net::IpAddr;
+
+use anyhow::{anyhow, Context, Error};
+use log::{error, info};
+use serde::Deserialize;
+
+use crate::{
+    canonical_name, detect_publishable, primary_provider,
+    providers::{RecordSpec, RecordType},
+    secondary_providers, HostedZoneConfig, UpdateContext,
+};
+
+/// Value that asks for the detected address of the entry's family.
+const DETECT: &str = "detect";
+
+/// One desired record read by `--bulk-stdin`.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct BulkEntry {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub record_type: String,
+    pub value: String,
+    #[serde(default)]
+    pub ttl: Option<i64>,
+}
+
+impl BulkEntry {
+    fn to_record(&self, zone: &HostedZoneConfig, detected: &[IpAddr]) -> Result<RecordSpec, Error> {
+        let name = canonical_name(&self.name);
+        let zone_name = canonical_name(&zone.zone_name);
+        if name != zone_name && !name.ends_with(&format!(".{zone_name}")) {
+            return Err(anyhow!("{name} is not inside {zone_name}"));
+        }
+        let record_type: RecordType = self.record_type.parse()?;
+        let ip = if self.value.eq_ignore_ascii_case(DETECT) {
+            *detected
+                .iter()
+                .find(|ip| RecordType::for_ip(ip) == record_type)
+                .ok_or(anyhow!("no {record_type} address was detected"))?
+        } else {
+            let ip: IpAddr = self
+                .value
+                .parse()
+                .with_context(|| format!("invalid address {:?}", self.value))?;
+            if RecordType::for_ip(&ip) != record_type {
+                return Err(anyhow!("{ip} can't be used for a {record_type} record"));
+            }
+            ip
+        };
+        let ttl = self.ttl.unwrap_or(zone.ttl_seconds);
+        if ttl <= 0 {
+            return Err(anyhow!("TTL must be positive, got {ttl}"));
+        }
+        Ok(RecordSpec::for_ip(&name, ip, ttl))
+    }
+}
+
+/// Reads a JSON array of [`BulkEntry`] from stdin and writes them as one batch. Nothing is
+/// written unless every entry is valid.
+pub async fn apply_stdin(zone: &HostedZoneConfig, ctx: &UpdateContext) -> Result<(), Error> {
+    let entries: Vec<BulkEntry> = serde_json::from_reader(std::io::stdin().lock())
+        .context("Failed to parse the bulk records on stdin")?;
+    let detected = if entries
+        .iter()
+        .any(|entry| entry.value.eq_ignore_ascii_case(DETECT))
+    {
+        detect_publishable(zone, ctx).await?.0
+    } else {
+        Vec::new()
+    };
+
+    let mut records = Vec::with_capacity(entries.len());
+    let mut invalid = 0;
+    for (index, entry) in entries.iter().enumerate() {
+        match entry.to_record(zone, &detected) {
+            Ok(record) => records.push(record),
+            Err(e) => {
+                error!("Bulk entry {index} ({}): {e:#}", entry.name);
+                invalid += 1;
+            }
+        }
+    }
+    if invalid > 0 {
+        return Err(anyhow!(
+            "{invalid} of {} bulk entries are invalid, nothing was written.",
+            entries.len()
+        ));
+    }
+
+    primary_provider(zone, ctx)
+        .await?
+        .upsert_all(&records)
+        .await?;
+    for provider in secondary_providers(zone, &ctx.clients) {
+        provider
+            .upsert_all(&records)
+            .await
+            .with_context(|| format!("Failed to apply bulk records to {}", provider.name()))?;
+    }
+    info!(
+        "Applied {} bulk records to {}",
+        records.len(),
+        zone.zone_name
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn entry(name: &str, record_type: &str, value: &str) -> BulkEntry {
+        BulkEntry {
+            name: name.to_owned(),
+            record_type: record_type.to_owned(),
+            value: value.to_owned(),
+            ttl: None,
+        }
+    }
+
+    #[test]
+    fn fills_in_detected_addresses_by_family() {
+        let zone = test_util::zone("");
+        let detected = [
+            "8.8.8.8".parse().unwrap(),
+            "2001:4860::8888".parse().unwrap(),
+        ];
+        let record = entry("VPN.example.com.", "AAAA", "detect")
+            .to_record(&zone, &detected)
+            .unwrap();
+        assert_eq!(record.name, "vpn.example.com");
+        assert_eq!(record.value, "2001:4860::8888");
+        assert_eq!(record.ttl, 300);
+    }
+
+    #[test]
+    fn rejects_entries_outside_the_zone_or_family() {
+        let zone = test_util::zone("");
+        assert!(entry("home.example.org", "A", "8.8.8.8")
+            .to_record(&zone, &[])
+            .is_err());
+        assert!(entry("home.example.com", "A", "2001:4860::8888")
+            .to_record(&zone, &[])
+            .is_err());
+        assert!(entry("home.example.com", "AAAA", "detect")
+            .to_record(&zone, &["8.8.8.8".parse().unwrap()])
+            .is_err());
+    }
+}
@@ -0,0 +1,180 @@
+use std::{fmt::Display, str::FromStr, sync::Arc, time::SystemTime};
+
+use anyhow::{anyhow, Error};
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier},
+    Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// SHA-256 fingerprint of a DER encoded certificate, written as hex with optional colons.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct CertPin([u8; 32]);
+
+impl FromStr for CertPin {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex: String = s.chars().filter(|c| *c != ':').collect();
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(anyhow!(
+                "Certificate pin {s:?} must be a SHA-256 fingerprint of 64 hex digits."
+            ));
+        }
+        let mut fingerprint = [0; 32];
+        for (byte, pair) in fingerprint.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(pair)?, 16)
+                .map_err(|e| anyhow!("Certificate pin {s:?} is not hex: {e}"))?;
+        }
+        Ok(Self(fingerprint))
+    }
+}
+
+impl TryFrom<String> for CertPin {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for CertPin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the usual WebPKI validation and then requires the server's leaf certificate
+/// to match one of the pins.
+struct PinnedVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    pins: Vec<CertPin>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+        let fingerprint = CertPin(Sha256::digest(&end_entity.0).into());
+        if !self.pins.contains(&fingerprint) {
+            return Err(rustls::Error::General(format!(
+                "Certificate fingerprint {fingerprint} of {server_name:?} matches no pin."
+            )));
+        }
+        Ok(verified)
+    }
+}
+
+/// TLS settings for AWS calls that only accept certificates matching one of `pins`.
+pub fn pinned_tls_config(pins: &[CertPin]) -> ClientConfig {
+    let mut roots = RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject,
+            anchor.spki,
+            anchor.name_constraints,
+        )
+    }));
+    ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(PinnedVerifier {
+            inner: Arc::new(WebPkiVerifier::new(roots, None)),
+            pins: pins.to_vec(),
+        }))
+        .with_no_client_auth()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stands in for WebPKI validation, which needs a certificate from a public CA.
+    struct Verdict(bool);
+
+    impl ServerCertVerifier for Verdict {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &Certificate,
+            _intermediates: &[Certificate],
+            _server_name: &ServerName,
+            _scts: &mut dyn Iterator<Item = &[u8]>,
+            _ocsp_response: &[u8],
+            _now: SystemTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            match self.0 {
+                true => Ok(ServerCertVerified::assertion()),
+                false => Err(rustls::Error::InvalidCertificate(
+                    rustls::CertificateError::UnknownIssuer,
+                )),
+            }
+        }
+    }
+
+    fn verify(
+        valid: bool,
+        pins: &[&str],
+        leaf: &[u8],
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verifier = PinnedVerifier {
+            inner: Arc::new(Verdict(valid)),
+            pins: pins.iter().map(|pin| pin.parse().unwrap()).collect(),
+        };
+        verifier.verify_server_cert(
+            &Certificate(leaf.to_vec()),
+            &[],
+            &ServerName::try_from("route53.amazonaws.com").unwrap(),
+            &mut std::iter::empty(),
+            &[],
+            SystemTime::now(),
+        )
+    }
+
+    #[test]
+    fn accepts_only_a_pinned_leaf() {
+        let leaf = b"leaf certificate";
+        let pin = hex(&Sha256::digest(leaf));
+        let other = hex(&Sha256::digest(b"another certificate"));
+
+        assert!(verify(true, &[&other, &pin], leaf).is_ok());
+        let err = verify(true, &[&other], leaf).unwrap_err();
+        assert!(err.to_string().contains("matches no pin"), "{err}");
+        // A pin doesn't make up for a certificate that fails validation.
+        assert!(verify(false, &[&pin], leaf).is_err());
+    }
+
+    #[test]
+    fn parses_fingerprints_with_or_without_colons() {
+        let pin = "ab".repeat(32);
+        let with_colons = vec!["AB"; 32].join(":");
+        assert_eq!(pin.parse::<CertPin>().unwrap().to_string(), pin);
+        assert_eq!(
+            with_colons.parse::<CertPin>().unwrap(),
+            pin.parse().unwrap()
+        );
+        assert!("ab".repeat(31).parse::<CertPin>().is_err());
+        assert!("zz".repeat(32).parse::<CertPin>().is_err());
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+}
@@ -0,0 +1,143 @@
+//! Recognizes a public address that belongs to the ISP's carrier-grade NAT rather than to
+//! this network, which inbound connections can't reach.
+
+use std::net::IpAddr;
+
+use anyhow::{anyhow, Error};
+use clap::ValueEnum;
+use log::{debug, info, warn};
+use serde::Deserialize;
+
+use crate::{desktop::ChangeNotification, detect, ip_validation, HostedZoneConfig, UpdateContext};
+
+/// What to do with an IPv4 address that is behind carrier-grade NAT. Without an action
+/// configured, it is refused unless private addresses are allowed.
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CgnatAction {
+    /// Fail the update.
+    Fail,
+    /// Warn and leave the A record alone.
+    Skip,
+    /// Warn and publish the address anyway.
+    Warn,
+    /// Publish the address without warning.
+    Update,
+}
+
+/// Whether `ip` may be published, given the zone's `on_cgnat` action. Besides the
+/// 100.64.0.0/10 range, an IPv4 address that differs from the router's WAN address, as
+/// read from `cgnat_check_source`, counts as carrier-grade NAT. Warnings and
+/// notifications are only repeated when the address changes.
+pub async fn allows(
+    zone: &HostedZoneConfig,
+    ctx: &UpdateContext,
+    ip: IpAddr,
+) -> Result<bool, Error> {
+    let reason = if ip_validation::is_cgnat(ip) {
+        "it is in the carrier-grade NAT range 100.64.0.0/10".to_owned()
+    } else if let (IpAddr::V4(_), Some(source)) = (ip, &zone.cgnat_check_source) {
+        match detect::router_wan_address(zone, &ctx.clients, source).await {
+            Ok(wan) if wan != ip => {
+                format!("the router's WAN address from {source} is {wan}")
+            }
+            Ok(_) => {
+                ctx.state.lock().unwrap().cgnat_address = None;
+                return Ok(true);
+            }
+            Err(e) => {
+                warn!("Failed to read the router's WAN address from {source}: {e:?}");
+                return Ok(true);
+            }
+        }
+    } else {
+        ctx.state.lock().unwrap().cgnat_address = None;
+        return Ok(true);
+    };
+    let action = zone.cgnat_action();
+    let changed = ctx.state.lock().unwrap().record_cgnat_address(ip);
+    match action {
+        CgnatAction::Fail => {
+            return Err(anyhow!(
+                "Detected address {ip} is behind carrier-grade NAT: {reason}. Refusing to \
+                 publish it, pass --on-cgnat to choose otherwise."
+            ))
+        }
+        CgnatAction::Update => {
+            if changed {
+                info!("Publishing {ip} although {reason}.");
+            }
+            return Ok(true);
+        }
+        _ if !changed => {
+            debug!("{ip} is still behind carrier-grade NAT: {reason}.");
+            return Ok(action == CgnatAction::Warn);
+        }
+        CgnatAction::Skip => warn!(
+            "Detected address {ip} is behind carrier-grade NAT: {reason}. Inbound connections \
+             can't reach this host, skipping the A record update."
+        ),
+        CgnatAction::Warn => warn!(
+            "Detected address {ip} is behind carrier-grade NAT: {reason}. Inbound connections \
+             will most likely not reach this host even though DNS points at it."
+        ),
+    }
+    if zone.desktop_notify {
+        ctx.notifier.notify(ChangeNotification {
+            summary: "Behind carrier-grade NAT".to_owned(),
+            body: format!("{ip} is not reachable from the internet."),
+        });
+    }
+    Ok(action == CgnatAction::Warn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    #[tokio::test]
+    async fn warns_only_when_the_address_changes() {
+        let zone = test_util::zone("on_cgnat = \"warn\"");
+        let ctx = test_util::context();
+        let cgnat = |ip: &str| ip.parse::<IpAddr>().unwrap();
+
+        assert!(allows(&zone, &ctx, cgnat("100.64.1.1")).await.unwrap());
+        assert!(!ctx
+            .state
+            .lock()
+            .unwrap()
+            .record_cgnat_address(cgnat("100.64.1.1")));
+        assert!(allows(&zone, &ctx, cgnat("100.64.1.1")).await.unwrap());
+        assert!(ctx
+            .state
+            .lock()
+            .unwrap()
+            .record_cgnat_address(cgnat("100.64.2.2")));
+
+        // Leaving carrier-grade NAT resets it, so coming back is warned about again.
+        assert!(allows(&zone, &ctx, cgnat("8.8.8.8")).await.unwrap());
+        assert_eq!(ctx.state.lock().unwrap().cgnat_address, None);
+    }
+
+    #[tokio::test]
+    async fn refuses_unless_private_addresses_are_allowed() {
+        let ip = "100.64.1.1".parse().unwrap();
+        let mut zone = test_util::zone("");
+        let err = allows(&zone, &test_util::context(), ip).await.unwrap_err();
+        assert!(err.to_string().contains("--on-cgnat"), "{err}");
+        zone.allow_private_addresses = true;
+        assert!(allows(&zone, &test_util::context(), ip).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn skip_keeps_refusing_after_the_first_warning() {
+        let zone = test_util::zone("on_cgnat = \"skip\"");
+        let ctx = test_util::context();
+        for _ in 0..2 {
+            assert!(!allows(&zone, &ctx, "100.64.1.1".parse().unwrap())
+                .await
+                .unwrap());
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use std::{collections::HashMap, time::Duration};
+
+use tokio::time::Instant;
+
+/// Tracks when each record was last written so that writes to the same record
+/// are spaced at least a zone's minimum change interval apart.
+#[derive(Clone, Debug, Default)]
+pub struct ChangeLimiter {
+    last_write: HashMap<String, Instant>,
+}
+
+impl ChangeLimiter {
+    /// How long a write to `record` must be deferred to keep writes `min_interval`
+    /// apart. Zero if it may be written now.
+    pub fn remaining(&self, record: &str, min_interval: Duration) -> Duration {
+        match self.last_write.get(record) {
+            Some(last) => min_interval.saturating_sub(last.elapsed()),
+            None => Duration::ZERO,
+        }
+    }
+
+    pub fn record_write(&mut self, record: &str) {
+        self.last_write.insert(record.to_owned(), Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn defers_a_second_change_within_the_interval() {
+        let interval = Duration::from_secs(600);
+        let mut limiter = ChangeLimiter::default();
+        assert_eq!(
+            limiter.remaining("home.example.com", interval),
+            Duration::ZERO
+        );
+        limiter.record_write("home.example.com");
+
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert_eq!(
+            limiter.remaining("home.example.com", interval),
+            Duration::from_secs(540)
+        );
+        assert_eq!(
+            limiter.remaining("www.example.com", interval),
+            Duration::ZERO
+        );
+
+        tokio::time::advance(Duration::from_secs(540)).await;
+        assert_eq!(
+            limiter.remaining("home.example.com", interval),
+            Duration::ZERO
+        );
+    }
+}
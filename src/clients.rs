@@ -0,0 +1,76 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::Error;
+use aws_sdk_route53::Client;
+use tokio::sync::OnceCell;
+
+use crate::{
+    detect,
+    providers::cloudflare::{CloudflareConfig, CloudflareProvider},
+    route53_client, HostedZoneConfig,
+};
+
+/// The clients of one zone, built on first use and kept for every later update so TLS
+/// sessions and resolved credentials survive between daemon iterations. The hosted zone
+/// id is kept alongside, since it doesn't change either.
+#[derive(Clone, Debug, Default)]
+pub struct ZoneClients {
+    route53: Arc<OnceCell<Client>>,
+    /// IP discovery clients keyed by the local address they are bound to.
+    ip: Arc<Mutex<HashMap<IpAddr, reqwest::Client>>>,
+    hosted_zone_id: Arc<Mutex<Option<(String, Instant)>>>,
+    /// The Cloudflare mirror, kept so its zone id is only looked up once.
+    cloudflare: Arc<OnceLock<Arc<CloudflareProvider>>>,
+}
+
+impl ZoneClients {
+    pub async fn route53(&self, zone: &HostedZoneConfig) -> Client {
+        self.route53
+            .get_or_init(|| route53_client(zone))
+            .await
+            .clone()
+    }
+
+    pub fn cloudflare(
+        &self,
+        config: &CloudflareConfig,
+        zone_name: &str,
+    ) -> Arc<CloudflareProvider> {
+        self.cloudflare
+            .get_or_init(|| Arc::new(CloudflareProvider::new(config.clone(), zone_name)))
+            .clone()
+    }
+
+    pub fn ip(
+        &self,
+        zone: &HostedZoneConfig,
+        local_address: IpAddr,
+    ) -> Result<reqwest::Client, Error> {
+        let mut clients = self.ip.lock().unwrap();
+        if let Some(client) = clients.get(&local_address) {
+            return Ok(client.clone());
+        }
+        let client = detect::ip_client(zone, local_address)?;
+        clients.insert(local_address, client.clone());
+        Ok(client)
+    }
+
+    /// The hosted zone id found earlier, unless it was found more than `max_age` ago.
+    pub fn hosted_zone_id(&self, max_age: Option<Duration>) -> Option<String> {
+        let cached = self.hosted_zone_id.lock().unwrap();
+        let (id, found_at) = cached.as_ref()?;
+        match max_age {
+            Some(max_age) if found_at.elapsed() >= max_age => None,
+            _ => Some(id.clone()),
+        }
+    }
+
+    pub fn cache_hosted_zone_id(&self, id: String) {
+        *self.hosted_zone_id.lock().unwrap() = Some((id, Instant::now()));
+    }
+}
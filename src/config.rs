@@ -0,0 +1,137 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Error};
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    credential_provider::{AwsCredentials, WebIdentityConfig},
+    ssm,
+    vault::VaultConfig,
+    HostedZoneConfig,
+};
+
+/// The zones read from `--config`, one `[[zones]]` table each. Every zone has its own
+/// credentials, profile and role, so one daemon can update zones in several AWS accounts.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub aws: AwsDefaults,
+    pub zones: Vec<HostedZoneConfig>,
+}
+
+/// The `[aws]` table: the region and base credentials of every zone that doesn't set its
+/// own. Zones then usually only differ in their `assume_role`.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct AwsDefaults {
+    #[serde(default)]
+    pub region: String,
+    #[serde(default)]
+    pub aws_credentials: Option<AwsCredentials>,
+    #[serde(default)]
+    pub aws_profile: Option<String>,
+    #[serde(default)]
+    pub web_identity: Option<WebIdentityConfig>,
+    #[serde(default)]
+    pub aws_credentials_secret: Option<String>,
+    #[serde(default)]
+    pub vault: Option<VaultConfig>,
+    #[serde(default)]
+    pub aws_keyring_entry: Option<String>,
+}
+
+impl AwsDefaults {
+    fn apply(&self, zone: &mut HostedZoneConfig) {
+        if zone.region.is_empty() {
+            zone.region = self.region.clone();
+        }
+        if zone.aws_credentials.is_none()
+            && zone.aws_profile.is_none()
+            && zone.web_identity.is_none()
+            && zone.aws_credentials_secret.is_none()
+            && zone.vault.is_none()
+            && zone.aws_keyring_entry.is_none()
+        {
+            zone.aws_credentials = self.aws_credentials.clone();
+            zone.aws_profile = self.aws_profile.clone();
+            zone.web_identity = self.web_identity.clone();
+            zone.aws_credentials_secret = self.aws_credentials_secret.clone();
+            zone.vault = self.vault.clone();
+            zone.aws_keyring_entry = self.aws_keyring_entry.clone();
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file, replacing `ssm://` references with their parameters.
+    pub async fn load(path: &Path) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let invalid = || format!("Invalid config file {}", path.display());
+        let mut config: Config = if text.contains(ssm::REFERENCE_PREFIX) {
+            let mut value: toml::Value = toml::from_str(&text).with_context(invalid)?;
+            let region = value
+                .get("aws")
+                .and_then(|aws| aws.get("region"))
+                .and_then(toml::Value::as_str)
+                .filter(|region| !region.is_empty() && !region.starts_with(ssm::REFERENCE_PREFIX))
+                .map(str::to_owned);
+            ssm::resolve_references(&mut value, region.as_deref())
+                .await
+                .with_context(|| format!("Failed to resolve {}", path.display()))?;
+            value.try_into().with_context(invalid)?
+        } else {
+            toml::from_str(&text).with_context(invalid)?
+        };
+        if config.zones.is_empty() {
+            return Err(anyhow!("{} does not define any zones.", path.display()));
+        }
+        for (index, zone) in config.zones.iter_mut().enumerate() {
+            config.aws.apply(zone);
+            zone.source = Some(ZoneSource {
+                path: path.to_owned(),
+                index,
+            });
+        }
+        Ok(config)
+    }
+}
+
+/// The config file a zone was read from and its position in `zones`, so settings that
+/// change while the daemon runs, like rotated credentials, can be read again.
+#[derive(Clone, Debug)]
+pub struct ZoneSource {
+    pub path: PathBuf,
+    pub index: usize,
+}
+
+impl ZoneSource {
+    pub async fn reload(&self) -> Result<HostedZoneConfig, Error> {
+        Config::load(&self.path)
+            .await?
+            .zones
+            .into_iter()
+            .nth(self.index)
+            .ok_or(anyhow!(
+                "{} no longer has zone #{}.",
+                self.path.display(),
+                self.index + 1
+            ))
+    }
+}
+
+/// Accepts either a single string or a list of strings, so `record_name = "home"` keeps
+/// working next to `record_name = ["home", "vpn"]`.
+pub fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(name) => vec![name],
+        OneOrMany::Many(names) => names,
+    })
+}
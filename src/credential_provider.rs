@@ -1,14 +1,20 @@
-use std::time::SystemTime;
+use std::{path::PathBuf, time::SystemTime};
 
 // If you are loading credentials dynamically, you can provide your own implementation of
 // [`ProvideCredentials`](crate::provider::ProvideCredentials). Generally, this is best done by
 // defining an inherent `async fn` on your structure, then calling that method directly from
 // the trait implementation.
 // ```rust
-use aws_credential_types::{provider::ProvideCredentials, Credentials};
+use anyhow::{anyhow, Error};
+use aws_credential_types::{
+    provider::{error::CredentialsError, ProvideCredentials},
+    Credentials,
+};
 
 use serde::Deserialize;
 
+use crate::config::ZoneSource;
+
 #[derive(Deserialize, Clone)]
 pub struct AwsCredentials {
     pub access_key_id: String,
@@ -56,3 +62,78 @@ impl ProvideCredentials for AwsCredentials {
         )))
     }
 }
+
+/// Keys from a config file zone, read from the file again every time the SDK's credential
+/// cache wants fresh ones: once `expires_after` is near, or every 15 minutes without it.
+/// Whatever rotates temporary keys only has to rewrite them in the config file.
+#[derive(Debug)]
+pub struct ReloadingCredentials {
+    source: ZoneSource,
+}
+
+impl ReloadingCredentials {
+    pub fn new(source: ZoneSource) -> Self {
+        Self { source }
+    }
+
+    async fn credentials(&self) -> Result<Credentials, Error> {
+        let credentials = self.source.reload().await?.aws_credentials.ok_or(anyhow!(
+            "{} no longer has credentials for zone #{}.",
+            self.source.path.display(),
+            self.source.index + 1
+        ))?;
+        if let Some(expiry) = credentials.expires_after {
+            if expiry <= SystemTime::now() {
+                return Err(anyhow!(
+                    "The credentials in {} expired at {}.",
+                    self.source.path.display(),
+                    humantime::format_rfc3339_seconds(expiry)
+                ));
+            }
+        }
+        Ok(Credentials::new(
+            credentials.access_key_id,
+            credentials.secret_access_key,
+            credentials.session_token,
+            credentials.expires_after,
+            "ConfigFileProvider",
+        ))
+    }
+}
+
+impl ProvideCredentials for ReloadingCredentials {
+    fn provide_credentials<'a>(
+        &'a self,
+    ) -> aws_credential_types::provider::future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        aws_credential_types::provider::future::ProvideCredentials::new(async {
+            self.credentials()
+                .await
+                .map_err(CredentialsError::provider_error)
+        })
+    }
+}
+
+/// A role assumed per zone on top of the base credentials, so one set of credentials can
+/// reach hosted zones in many member accounts of an organization.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct AssumeRoleConfig {
+    pub role_arn: String,
+    #[serde(default)]
+    pub external_id: Option<String>,
+    /// Defaults to the zone's `role_session_name`.
+    #[serde(default)]
+    pub session_name: Option<String>,
+}
+
+/// An OIDC token file exchanged for the role's credentials with
+/// `sts:AssumeRoleWithWebIdentity`, as set up by EKS IAM Roles for Service Accounts.
+/// Kubernetes rotates the token in place, so the file is read again on every refresh.
+#[derive(Deserialize, Clone, Debug)]
+pub struct WebIdentityConfig {
+    pub token_file: PathBuf,
+    pub role_arn: String,
+}
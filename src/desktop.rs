@@ -0,0 +1,109 @@
+use std::{
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use log::info;
+use tokio::task::JoinHandle;
+
+/// A desktop notification announcing a public address change.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangeNotification {
+    pub summary: String,
+    pub body: String,
+}
+
+impl ChangeNotification {
+    pub fn new(record: &str, old_ip: Option<IpAddr>, new_ip: IpAddr) -> Self {
+        let body = match old_ip {
+            Some(old_ip) => format!("{old_ip} -> {new_ip}"),
+            None => format!("Now {new_ip}"),
+        };
+        Self {
+            summary: format!("Public IP changed for {record}"),
+            body,
+        }
+    }
+}
+
+/// Shows notifications without blocking the update on the notification server, keeping
+/// track of them so shutdown can wait until they are out.
+#[derive(Clone, Debug, Default)]
+pub struct Notifier {
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl Notifier {
+    pub fn notify(&self, notification: ChangeNotification) {
+        let task = tokio::task::spawn_blocking(move || show(&notification));
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(task);
+    }
+
+    /// Waits until every notification sent so far has been shown.
+    pub async fn flush(&self) {
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        for task in tasks {
+            let _ = task.await;
+        }
+    }
+}
+
+/// Shows the notification on the desktop, falling back to a log line when built
+/// without the `desktop-notify` feature or when no notification server is available.
+fn show(notification: &ChangeNotification) {
+    #[cfg(feature = "desktop-notify")]
+    {
+        let shown = notify_rust::Notification::new()
+            .appname("route53_dd")
+            .summary(&notification.summary)
+            .body(&notification.body)
+            .show();
+        match shown {
+            Ok(_) => return,
+            Err(e) => log::debug!("Failed to show desktop notification: {e:?}"),
+        }
+    }
+    info!("{}: {}", notification.summary, notification.body);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shows_the_old_and_new_address() {
+        let changed = ChangeNotification::new(
+            "home.example.com",
+            Some("8.8.4.4".parse().unwrap()),
+            "8.8.8.8".parse().unwrap(),
+        );
+        assert_eq!(
+            changed,
+            ChangeNotification {
+                summary: "Public IP changed for home.example.com".to_owned(),
+                body: "8.8.4.4 -> 8.8.8.8".to_owned(),
+            }
+        );
+
+        let first =
+            ChangeNotification::new("home.example.com", None, "2001:db8::1".parse().unwrap());
+        assert_eq!(first.summary, "Public IP changed for home.example.com");
+        assert_eq!(first.body, "Now 2001:db8::1");
+    }
+
+    #[tokio::test]
+    async fn flush_waits_for_every_notification() {
+        let notifier = Notifier::default();
+        for ip in ["8.8.8.8", "8.8.4.4"] {
+            notifier.notify(ChangeNotification::new(
+                "home.example.com",
+                None,
+                ip.parse().unwrap(),
+            ));
+        }
+        notifier.flush().await;
+        assert!(notifier.tasks.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,804 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    future::Future,
+    net::{IpAddr, Ipv6Addr},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Error};
+use futures::future::join_all;
+use igd_next::SearchOptions;
+use log::{debug, info, warn};
+use tokio::time;
+
+use crate::{
+    clients::ZoneClients, doh::DohResolver, interface, ip_extract::IpExtract, ip_source::IpSource,
+    ip_validation, ipv6_prefix, nat_pmp, providers::RecordType, state::SharedState, stun,
+    HostedZoneConfig,
+};
+
+/// Tried in order until one answers, so a single service being down doesn't fail the update.
+pub const DEFAULT_IP_PROVIDERS: [&str; 4] = [
+    "https://ifconfig.me/ip",
+    "https://api.ipify.org",
+    "https://icanhazip.com",
+    "https://ident.me",
+];
+
+pub const DEFAULT_IP_PROVIDER_TIMEOUT_SECONDS: u64 = 10;
+
+/// Where EC2 serves instance metadata, the same default the AWS SDKs use.
+pub const DEFAULT_IMDS_ENDPOINT: &str = "http://169.254.169.254";
+/// IMDS answers within milliseconds on EC2, anywhere else the request just hangs.
+const IMDS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// An address is at most 45 characters, anything much longer is not an answer.
+pub const DEFAULT_IP_RESPONSE_MAX_BYTES: usize = 256;
+
+pub fn default_ip_providers() -> Vec<String> {
+    DEFAULT_IP_PROVIDERS.map(str::to_owned).to_vec()
+}
+
+pub fn default_ip_provider_timeout_seconds() -> u64 {
+    DEFAULT_IP_PROVIDER_TIMEOUT_SECONDS
+}
+
+pub fn default_ip_response_max_bytes() -> usize {
+    DEFAULT_IP_RESPONSE_MAX_BYTES
+}
+
+pub fn default_imds_endpoint() -> String {
+    DEFAULT_IMDS_ENDPOINT.to_owned()
+}
+
+/// Addresses found during one detection pass.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Detected {
+    pub ipv4: Option<IpAddr>,
+    pub ipv6: Option<Ipv6Addr>,
+    /// Why detection failed for a family while the other one succeeded.
+    pub failures: Vec<String>,
+}
+
+impl Detected {
+    pub fn is_empty(&self) -> bool {
+        self.ipv4.is_none() && self.ipv6.is_none()
+    }
+}
+
+impl Display for Detected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ipv4 = self.ipv4.map(|ip| ip.to_string());
+        let ipv6 = self.ipv6.map(|ip| ip.to_string());
+        write!(
+            f,
+            "ipv4 {}, ipv6 {}",
+            ipv4.as_deref().unwrap_or("none"),
+            ipv6.as_deref().unwrap_or("none")
+        )
+    }
+}
+
+/// Runs the detection of one address family, failing it once
+/// `detect_timeout_total_seconds` run out, or by default once every source it asks could
+/// have timed out.
+async fn within_deadline<T>(
+    zone: &HostedZoneConfig,
+    family: RecordType,
+    detection: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    let deadline = match zone.detect_timeout_total_seconds {
+        Some(secs) => Duration::from_secs(secs),
+        None => {
+            let sources = providers_for(zone, family).len().max(2) as u64;
+            Duration::from_secs(zone.ip_provider_timeout_seconds * sources)
+        }
+    };
+    time::timeout(deadline, detection)
+        .await
+        .unwrap_or_else(|_| Err(anyhow!("{family} detection timed out after {deadline:?}.")))
+}
+
+/// Detects the enabled address families concurrently, each within its own deadline so a
+/// slow one can't hold up the other. A family that fails only fails the detection when
+/// nothing else was found, otherwise it is recorded in `failures`.
+pub async fn detect_addresses(
+    zone: &HostedZoneConfig,
+    state: &SharedState,
+    clients: &ZoneClients,
+    detected: &mut Detected,
+) -> Result<(), Error> {
+    let Detected { ipv4, ipv6, .. } = detected;
+    let (found_ipv4, found_ipv6) = tokio::join!(
+        async {
+            if zone.ipv4 {
+                let detection = detect_ipv4(zone, state, clients);
+                *ipv4 = Some(within_deadline(zone, RecordType::A, detection).await?);
+            }
+            Ok::<_, Error>(())
+        },
+        async {
+            if zone.ipv6 {
+                let detection = detect_ipv6(zone, state, clients);
+                *ipv6 = within_deadline(zone, RecordType::Aaaa, detection).await?;
+            }
+            Ok::<_, Error>(())
+        },
+    );
+    match (found_ipv4, found_ipv6) {
+        (Ok(()), Ok(())) => Ok(()),
+        (Err(ipv4), Err(ipv6)) => Err(anyhow!(
+            "Detection failed for both address families. IPv4: {ipv4:#}. IPv6: {ipv6:#}"
+        )),
+        (Err(e), Ok(())) | (Ok(()), Err(e)) if detected.is_empty() => Err(e),
+        (Err(e), Ok(())) => {
+            warn!("IPv4 detection failed, publishing IPv6 only: {e:?}");
+            detected.failures.push(format!("IPv4: {e:#}"));
+            Ok(())
+        }
+        (Ok(()), Err(e)) => {
+            warn!("IPv6 detection failed, publishing IPv4 only: {e:?}");
+            detected.failures.push(format!("IPv6: {e:#}"));
+            Ok(())
+        }
+    }
+}
+
+async fn detect_ipv4(
+    zone: &HostedZoneConfig,
+    state: &SharedState,
+    clients: &ZoneClients,
+) -> Result<IpAddr, Error> {
+    let client = clients.ip(zone, "0.0.0.0".parse()?)?;
+    let imds = if zone.ip_from_imds {
+        detect_from(&client, zone, state, RecordType::A, &IpSource::Imds)
+            .await
+            .inspect_err(|e| warn!("IMDS unavailable, falling back to HTTP detection: {e:?}"))
+            .ok()
+    } else {
+        None
+    };
+    let ipv4 = match imds {
+        Some(ipv4) => ipv4,
+        None => detect_ip(&client, zone, state, RecordType::A).await?,
+    };
+    debug!("Found ipv4 address: {ipv4:?}");
+    Ok(ipv4)
+}
+
+/// The global IPv6 address to publish, or `None` when the host has none.
+async fn detect_ipv6(
+    zone: &HostedZoneConfig,
+    state: &SharedState,
+    clients: &ZoneClients,
+) -> Result<Option<Ipv6Addr>, Error> {
+    let client = clients.ip(zone, "::".parse()?)?;
+    let found = detect_ip(&client, zone, state, RecordType::Aaaa).await?;
+    debug!("Found ipv6 address: {found:?}");
+    let mut ipv6 = match found {
+        IpAddr::V6(ipv6) => ip_validation::select_global_ipv6([ipv6], zone.allow_ula),
+        IpAddr::V4(_) => None,
+    };
+    if let (Some(detected), Some(prefix_length), Some(suffix)) =
+        (ipv6, zone.ipv6_prefix_length, zone.ipv6_suffix)
+    {
+        let composed = ipv6_prefix::with_suffix(detected, prefix_length, suffix);
+        debug!("Composed {composed} from the /{prefix_length} prefix of {detected}");
+        ipv6 = Some(composed);
+    }
+    if ipv6.is_none() {
+        info!("No global IPv6 address available, skipping AAAA update.");
+    }
+    Ok(ipv6)
+}
+
+/// Reads the instance's public IPv4 address from the EC2 instance metadata served at
+/// `endpoint`, using IMDSv2.
+async fn fetch_imds_ipv4(endpoint: &str) -> Result<IpAddr, Error> {
+    let base = format!("{}/latest", endpoint.trim_end_matches('/'));
+    let client = reqwest::Client::builder().timeout(IMDS_TIMEOUT).build()?;
+    let token = client
+        .put(format!("{base}/api/token"))
+        .header("X-aws-ec2-metadata-token-ttl-seconds", "60")
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    Ok(client
+        .get(format!("{base}/meta-data/public-ipv4"))
+        .header("X-aws-ec2-metadata-token", token)
+        .send()
+        .await?
+        .error_for_status()
+        .context("The instance has no public IPv4 address")?
+        .text()
+        .await?
+        .trim()
+        .parse()?)
+}
+
+/// Asks the router for its WAN address with the UPnP IGD `GetExternalIPAddress` action.
+async fn fetch_upnp_ipv4(timeout: Duration) -> Result<IpAddr, Error> {
+    let options = SearchOptions {
+        timeout: Some(timeout),
+        ..Default::default()
+    };
+    let gateway = igd_next::aio::tokio::search_gateway(options)
+        .await
+        .context("No UPnP gateway answered")?;
+    Ok(gateway.get_external_ip().await?)
+}
+
+/// Detects the public address once through each of the zone's source addresses, e.g. one
+/// per uplink, and returns the distinct results. Links that fail are left out.
+pub async fn detect_per_source(
+    zone: &HostedZoneConfig,
+    state: &SharedState,
+    clients: &ZoneClients,
+) -> Result<Vec<IpAddr>, Error> {
+    let mut found = Vec::with_capacity(zone.source_addresses.len());
+    for source in &zone.source_addresses {
+        if (source.is_ipv4() && !zone.ipv4) || (source.is_ipv6() && !zone.ipv6) {
+            continue;
+        }
+        let client = clients.ip(zone, *source)?;
+        let ip = match detect_ip(&client, zone, state, RecordType::for_ip(source)).await {
+            Ok(ip) => ip,
+            Err(e) => {
+                warn!("Detection via {source} failed, leaving it out: {e:?}");
+                continue;
+            }
+        };
+        if ip.is_ipv4() != source.is_ipv4() {
+            return Err(anyhow!(
+                "Detection via {source} returned {ip}, which is of the other address family."
+            ));
+        }
+        if let IpAddr::V6(ipv6) = ip {
+            if ip_validation::select_global_ipv6([ipv6], zone.allow_ula).is_none() {
+                info!("{ip} found via {source} is not a global address, leaving it out.");
+                continue;
+            }
+        }
+        if found.contains(&ip) {
+            warn!("{source} shares its public address {ip} with another source address.");
+            continue;
+        }
+        info!("Found address {ip} via {source}");
+        found.push(ip);
+    }
+    if found.is_empty() {
+        return Err(anyhow!("No source address yielded a public address."));
+    }
+    Ok(found)
+}
+
+/// Builds the client used for IP discovery, bound to one address family.
+/// Connecting and the whole request are bounded by the provider timeout, so a hanging
+/// connection can't stall a cycle.
+pub fn ip_client(zone: &HostedZoneConfig, local_address: IpAddr) -> Result<reqwest::Client, Error> {
+    let timeout = Duration::from_secs(zone.ip_provider_timeout_seconds);
+    let mut builder = reqwest::Client::builder()
+        .local_address(local_address)
+        .connect_timeout(timeout)
+        .timeout(timeout);
+    if let Some(url) = &zone.ip_doh_resolver {
+        builder = builder.dns_resolver(Arc::new(DohResolver::new(url)?));
+    }
+    Ok(builder.build()?)
+}
+
+/// Detects the public address with the zone's IP provider, recording the outcome in
+/// `state`. With a primary IP source configured, the backup source is only asked while
+/// the primary fails, and every switch between the two is logged.
+pub async fn detect_ip(
+    client: &reqwest::Client,
+    zone: &HostedZoneConfig,
+    state: &SharedState,
+    family: RecordType,
+) -> Result<IpAddr, Error> {
+    let Some(primary) = &zone.primary_ip_source else {
+        return match zone.ip_consensus {
+            Some(quorum) => detect_by_consensus(client, zone, state, family, quorum).await,
+            None => detect_from_providers(client, zone, state, family).await,
+        };
+    };
+    let (result, active) = match detect_from(client, zone, state, family, primary).await {
+        Ok(ip) => (Ok(ip), primary),
+        Err(e) => match &zone.backup_ip_source {
+            Some(backup) => {
+                warn!("Primary IP source {primary} failed: {e:?}");
+                (
+                    detect_from(client, zone, state, family, backup).await,
+                    backup,
+                )
+            }
+            None => (Err(e), primary),
+        },
+    };
+    if let Ok(ip) = &result {
+        let family = RecordType::for_ip(ip).to_string();
+        let mut state = state.lock().unwrap();
+        let previous = state
+            .active_ip_sources
+            .insert(family.clone(), active.to_string());
+        match previous {
+            Some(previous) if previous != active.to_string() && active == primary => {
+                info!("Primary IP source {primary} recovered for {family}, failing back.")
+            }
+            Some(previous) if previous != active.to_string() => {
+                warn!("Failed over from {previous} to backup IP source {active} for {family}.")
+            }
+            None if active != primary => {
+                warn!("Failed over from {primary} to backup IP source {active} for {family}.")
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+/// The providers asked for `family`: its dedicated URL if one is set, otherwise every
+/// configured IP provider.
+pub(crate) fn providers_for(zone: &HostedZoneConfig, family: RecordType) -> &[String] {
+    let url = match family {
+        RecordType::Aaaa => &zone.ipv6_url,
+        _ => &zone.ipv4_url,
+    };
+    match url {
+        Some(url) => std::slice::from_ref(url),
+        None => &zone.ip_provider,
+    }
+}
+
+/// Asks the zone's IP providers in order and returns the first answer.
+async fn detect_from_providers(
+    client: &reqwest::Client,
+    zone: &HostedZoneConfig,
+    state: &SharedState,
+    family: RecordType,
+) -> Result<IpAddr, Error> {
+    let mut last_error = anyhow!("No IP provider configured.");
+    for url in providers_for(zone, family) {
+        let provider = IpSource::Http(url.clone());
+        match detect_from(client, zone, state, family, &provider).await {
+            Ok(ip) => return Ok(ip),
+            Err(e) => {
+                warn!("IP provider {url} failed: {e:?}");
+                last_error = e;
+            }
+        }
+    }
+    Err(last_error.context("Every IP provider failed"))
+}
+
+/// Asks a single IP source for the public address, recording the outcome in `state`.
+async fn detect_from(
+    client: &reqwest::Client,
+    zone: &HostedZoneConfig,
+    state: &SharedState,
+    family: RecordType,
+    source: &IpSource,
+) -> Result<IpAddr, Error> {
+    let result = query_source(client, zone, family, source).await;
+    let name = source.to_string();
+    let mut state = state.lock().unwrap();
+    match &result {
+        Ok(ip) => {
+            state.record_provider_success(&name);
+            state.record_ip(*ip);
+        }
+        Err(e) => state.record_provider_failure(&name, e),
+    }
+    result
+}
+
+/// Asks every IP provider at once and returns the address at least `quorum` of them
+/// agree on, so one misbehaving provider can't decide what gets published.
+async fn detect_by_consensus(
+    client: &reqwest::Client,
+    zone: &HostedZoneConfig,
+    state: &SharedState,
+    family: RecordType,
+    quorum: usize,
+) -> Result<IpAddr, Error> {
+    let queries = providers_for(zone, family).iter().map(|url| async move {
+        let source = IpSource::Http(url.clone());
+        let result = query_source(client, zone, family, &source).await;
+        (source.to_string(), result)
+    });
+    let mut votes: BTreeMap<IpAddr, Vec<String>> = BTreeMap::new();
+    for (name, result) in join_all(queries).await {
+        let mut state = state.lock().unwrap();
+        match result {
+            Ok(ip) => {
+                state.record_provider_success(&name);
+                votes.entry(ip).or_default().push(name);
+            }
+            Err(e) => {
+                warn!("IP provider {name} failed: {e:?}");
+                state.record_provider_failure(&name, &e);
+            }
+        }
+    }
+    let (ip, agreeing) = votes
+        .iter()
+        .max_by_key(|(_, names)| names.len())
+        .ok_or(anyhow!("No IP provider answered."))?;
+    if agreeing.len() < quorum {
+        return Err(anyhow!(
+            "Only {} IP providers agree on {ip}, {quorum} are needed. Answers: {votes:?}",
+            agreeing.len()
+        ));
+    }
+    if votes
+        .iter()
+        .any(|(other, names)| other != ip && names.len() == agreeing.len())
+    {
+        return Err(anyhow!("IP providers are split evenly: {votes:?}"));
+    }
+    if votes.len() > 1 {
+        warn!("IP providers disagree, going with {ip}. Answers: {votes:?}");
+    }
+    state.lock().unwrap().record_ip(*ip);
+    Ok(*ip)
+}
+
+/// Asks a single IP source for the public address.
+async fn query_source(
+    client: &reqwest::Client,
+    zone: &HostedZoneConfig,
+    family: RecordType,
+    source: &IpSource,
+) -> Result<IpAddr, Error> {
+    let timeout = Duration::from_secs(zone.ip_provider_timeout_seconds);
+    match source {
+        IpSource::Http(url) => {
+            // The default providers answer in plain text, so only a URL of the user's
+            // own is read with --ip-extract.
+            let custom = [&zone.ipv4_url, &zone.ipv6_url]
+                .into_iter()
+                .any(|own| own.as_deref() == Some(url.as_str()));
+            let plain = IpExtract::Plain;
+            let fetch = fetch_ip(
+                client,
+                url,
+                zone.ip_from_header.as_deref(),
+                if custom { &zone.ip_extract } else { &plain },
+                zone.ip_response_max_bytes,
+            );
+            time::timeout(timeout, fetch)
+                .await
+                .unwrap_or_else(|_| Err(anyhow!("{url} did not answer within {timeout:?}.")))
+        }
+        IpSource::Imds if family == RecordType::Aaaa => Err(anyhow!(
+            "IMDS only reports the instance's public IPv4 address."
+        )),
+        IpSource::Imds => fetch_imds_ipv4(&zone.imds_endpoint).await,
+        IpSource::Upnp | IpSource::NatPmp | IpSource::Pcp if family == RecordType::Aaaa => {
+            Err(anyhow!("{source} only reports the router's IPv4 address."))
+        }
+        IpSource::Upnp => fetch_upnp_ipv4(timeout).await,
+        IpSource::NatPmp => nat_pmp::nat_pmp_address(zone.nat_gateway, timeout).await,
+        IpSource::Pcp => nat_pmp::pcp_address(zone.nat_gateway, timeout).await,
+        IpSource::FritzBox => {
+            let fritzbox = zone.fritzbox.clone().unwrap_or_default();
+            fritzbox.external_address(family, timeout).await
+        }
+        IpSource::Mikrotik => {
+            let mikrotik = zone
+                .mikrotik
+                .as_ref()
+                .ok_or(anyhow!("The mikrotik IP source needs --mikrotik-url."))?;
+            mikrotik.external_address(family, timeout).await
+        }
+        IpSource::Opnsense | IpSource::Pfsense => {
+            let firewall = zone
+                .firewall
+                .as_ref()
+                .ok_or(anyhow!("The {source} IP source needs --firewall-url."))?;
+            if *source == IpSource::Opnsense {
+                firewall.opnsense_address(family, timeout).await
+            } else {
+                firewall.pfsense_address(family, timeout).await
+            }
+        }
+        IpSource::Interface | IpSource::Lan => {
+            let name = zone
+                .interface
+                .as_deref()
+                .ok_or(anyhow!("The {source} IP source needs --interface."))?;
+            if *source == IpSource::Lan {
+                interface::lan_address(name, family)
+            } else {
+                interface::interface_address(name, family, zone.allow_ula, zone.ipv6_prefer)
+            }
+        }
+        IpSource::Stun => stun::external_address(&zone.stun_servers, family, timeout).await,
+        IpSource::Dns(echo) => echo.external_address(family, timeout).await,
+    }
+}
+
+/// Reads the router's WAN address from `source`, to compare it with the public address.
+pub async fn router_wan_address(
+    zone: &HostedZoneConfig,
+    clients: &ZoneClients,
+    source: &IpSource,
+) -> Result<IpAddr, Error> {
+    let client = clients.ip(zone, "0.0.0.0".parse()?)?;
+    query_source(&client, zone, RecordType::A, source).await
+}
+
+pub async fn fetch_ip(
+    client: &reqwest::Client,
+    url: &str,
+    header: Option<&str>,
+    extract: &IpExtract,
+    max_bytes: usize,
+) -> Result<IpAddr, Error> {
+    let mut response = client.get(url).send().await?.error_for_status()?;
+    match header {
+        Some(header) => {
+            let value = response
+                .headers()
+                .get(header)
+                .ok_or(anyhow!("{url} did not return a {header} header."))?
+                .to_str()?;
+            parse_header_ip(value).with_context(|| format!("Invalid {header} header {value:?}"))
+        }
+        None => {
+            let mut body = Vec::new();
+            while let Some(chunk) = response.chunk().await? {
+                if body.len() + chunk.len() > max_bytes {
+                    return Err(anyhow!(
+                        "{url} returned more than {max_bytes} bytes, refusing to read further."
+                    ));
+                }
+                body.extend_from_slice(&chunk);
+            }
+            extract
+                .extract(std::str::from_utf8(&body)?)
+                .with_context(|| format!("Failed to read the address from {url}"))
+        }
+    }
+}
+
+/// Parses an address from a header value, taking the first entry of
+/// `X-Forwarded-For` style lists.
+fn parse_header_ip(value: &str) -> Result<IpAddr, Error> {
+    let first = value.split(',').next().unwrap_or_default().trim();
+    Ok(first.parse()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+    use crate::{state::DaemonState, test_util};
+
+    #[test]
+    fn describes_both_families_in_one_line() {
+        let mut detected = Detected {
+            ipv4: Some("8.8.8.8".parse().unwrap()),
+            ipv6: Some("2001:4860::8888".parse().unwrap()),
+            failures: Vec::new(),
+        };
+        assert_eq!(detected.to_string(), "ipv4 8.8.8.8, ipv6 2001:4860::8888");
+        detected.ipv6 = None;
+        assert_eq!(detected.to_string(), "ipv4 8.8.8.8, ipv6 none");
+        assert_eq!(Detected::default().to_string(), "ipv4 none, ipv6 none");
+    }
+
+    #[tokio::test]
+    async fn reads_the_address_from_a_response_header() {
+        let (url, _) = test_util::http_server_with_headers(|request| {
+            let headers = match request.target.as_str() {
+                "/garbled" => vec![("X-Client-IP".to_owned(), "unknown".to_owned())],
+                "/bare" => Vec::new(),
+                _ => vec![("X-Client-IP".to_owned(), "9.9.9.9, 10.0.0.1".to_owned())],
+            };
+            (200, headers, "8.8.8.8".to_owned())
+        })
+        .await;
+        let zone = test_util::zone(&format!(
+            "ip_provider = [\"{url}/ip\"]\nip_from_header = \"x-client-ip\""
+        ));
+        let client = reqwest::Client::new();
+
+        let ip = detect_ip(&client, &zone, &DaemonState::shared(), RecordType::A)
+            .await
+            .unwrap();
+        assert_eq!(ip, "9.9.9.9".parse::<IpAddr>().unwrap());
+
+        let fetch = |path: &str| {
+            let url = format!("{url}{path}");
+            let client = &client;
+            async move {
+                let extract = IpExtract::default();
+                fetch_ip(client, &url, Some("x-client-ip"), &extract, 64)
+                    .await
+                    .unwrap_err()
+                    .to_string()
+            }
+        };
+        assert!(fetch("/bare")
+            .await
+            .contains("did not return a x-client-ip header"));
+        assert!(fetch("/garbled")
+            .await
+            .contains("Invalid x-client-ip header"));
+    }
+
+    #[tokio::test]
+    async fn fails_over_to_the_backup_and_back() {
+        let primary_down = Arc::new(AtomicBool::new(true));
+        let down = primary_down.clone();
+        let (url, _) = test_util::http_server(move |request| match request.target.as_str() {
+            "/primary" if down.load(Ordering::SeqCst) => (503, String::new()),
+            "/primary" => (200, "8.8.8.8".to_owned()),
+            _ => (200, "8.8.4.4".to_owned()),
+        })
+        .await;
+        let zone = test_util::zone(&format!(
+            "primary_ip_source = \"{url}/primary\"\nbackup_ip_source = \"{url}/backup\""
+        ));
+        let state = DaemonState::shared();
+        let client = reqwest::Client::new();
+        let active = || state.lock().unwrap().active_ip_sources["A"].clone();
+
+        let ip = detect_ip(&client, &zone, &state, RecordType::A)
+            .await
+            .unwrap();
+        assert_eq!(ip, "8.8.4.4".parse::<IpAddr>().unwrap());
+        assert_eq!(active(), format!("{url}/backup"));
+
+        primary_down.store(false, Ordering::SeqCst);
+        let ip = detect_ip(&client, &zone, &state, RecordType::A)
+            .await
+            .unwrap();
+        assert_eq!(ip, "8.8.8.8".parse::<IpAddr>().unwrap());
+        assert_eq!(active(), format!("{url}/primary"));
+    }
+
+    #[tokio::test]
+    async fn refuses_an_oversized_body() {
+        let (url, _) = test_util::http_server(|request| match request.target.as_str() {
+            "/large" => (200, format!("8.8.8.8{}", " ".repeat(300))),
+            _ => (200, "8.8.8.8\n".to_owned()),
+        })
+        .await;
+        let client = reqwest::Client::new();
+        let extract = IpExtract::default();
+        let fetch = |path: &str| {
+            let url = format!("{url}{path}");
+            let (client, extract) = (&client, &extract);
+            async move { fetch_ip(client, &url, None, extract, DEFAULT_IP_RESPONSE_MAX_BYTES).await }
+        };
+
+        assert_eq!(
+            fetch("/small").await.unwrap(),
+            "8.8.8.8".parse::<IpAddr>().unwrap()
+        );
+        let err = fetch("/large").await.unwrap_err();
+        assert!(err.to_string().contains("more than 256 bytes"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn extracts_only_from_the_users_own_url() {
+        let (url, _) = test_util::http_server(|request| match request.target.as_str() {
+            "/json" => (200, r#"{"ip": "8.8.4.4"}"#.to_owned()),
+            _ => (200, "8.8.8.8\n".to_owned()),
+        })
+        .await;
+        let client = reqwest::Client::new();
+        let state = DaemonState::shared();
+        let zone = test_util::zone(&format!(
+            "ip_provider = [\"{url}/plain\"]\nip_extract = \"json:/ip\""
+        ));
+        let ip = detect_ip(&client, &zone, &state, RecordType::A)
+            .await
+            .unwrap();
+        assert_eq!(ip, "8.8.8.8".parse::<IpAddr>().unwrap());
+
+        let zone = HostedZoneConfig {
+            ipv4_url: Some(format!("{url}/json")),
+            ..zone
+        };
+        let ip = detect_ip(&client, &zone, &state, RecordType::A)
+            .await
+            .unwrap();
+        assert_eq!(ip, "8.8.4.4".parse::<IpAddr>().unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_stalled_family_fails_at_its_deadline() {
+        // Accepts connections but never answers.
+        let stalled = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", stalled.local_addr().unwrap());
+        // Asked one after the other, each provider alone could take a minute.
+        let zone = test_util::zone(&format!(
+            "ip_provider = [\"{url}/a\", \"{url}/b\", \"{url}/c\"]\n\
+             detect_timeout_total_seconds = 1\nip_provider_timeout_seconds = 60",
+        ));
+        let ctx = test_util::context();
+
+        let started = std::time::Instant::now();
+        let mut detected = Detected::default();
+        let err = detect_addresses(&zone, &ctx.state, &ctx.clients, &mut detected)
+            .await
+            .unwrap_err();
+        let elapsed = started.elapsed();
+        assert!(err.to_string().contains("A detection timed out"), "{err}");
+        assert!(
+            elapsed >= Duration::from_secs(1) && elapsed < Duration::from_secs(3),
+            "{elapsed:?}"
+        );
+        assert!(detected.is_empty());
+    }
+
+    #[tokio::test]
+    async fn reads_the_public_address_from_imds() {
+        let (url, requests) = test_util::http_server(|request| {
+            match (request.method.as_str(), request.target.as_str()) {
+                ("PUT", "/latest/api/token") => (200, "token1".to_owned()),
+                ("GET", "/latest/meta-data/public-ipv4")
+                    if request.header("x-aws-ec2-metadata-token") == Some("token1") =>
+                {
+                    (200, "54.1.2.3".to_owned())
+                }
+                ("GET", "/provider") => (200, "8.8.8.8".to_owned()),
+                _ => (401, String::new()),
+            }
+        })
+        .await;
+        let zone = test_util::zone(&format!(
+            "ip_from_imds = true\nimds_endpoint = \"{url}\"\nip_provider = [\"{url}/provider\"]"
+        ));
+        let ctx = test_util::context();
+
+        let mut detected = Detected::default();
+        detect_addresses(&zone, &ctx.state, &ctx.clients, &mut detected)
+            .await
+            .unwrap();
+        assert_eq!(detected.ipv4, Some("54.1.2.3".parse().unwrap()));
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2, "{requests:?}");
+        assert_eq!(
+            requests[0].header("x-aws-ec2-metadata-token-ttl-seconds"),
+            Some("60")
+        );
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_http_without_imds() {
+        let (url, _) = test_util::http_server(|request| match request.target.as_str() {
+            "/provider" => (200, "8.8.8.8".to_owned()),
+            _ => (404, String::new()),
+        })
+        .await;
+        let zone = test_util::zone(&format!(
+            "ip_from_imds = true\nimds_endpoint = \"{url}\"\nip_provider = [\"{url}/provider\"]"
+        ));
+        let ctx = test_util::context();
+
+        let mut detected = Detected::default();
+        detect_addresses(&zone, &ctx.state, &ctx.clients, &mut detected)
+            .await
+            .unwrap();
+        assert_eq!(detected.ipv4, Some("8.8.8.8".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn imds_has_no_ipv6_address() {
+        let zone = test_util::zone("");
+        let client = reqwest::Client::new();
+        let err = query_source(&client, &zone, RecordType::Aaaa, &IpSource::Imds)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("IPv4"), "{err}");
+    }
+}
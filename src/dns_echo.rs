@@ -0,0 +1,95 @@
+use std::{
+    fmt::Display,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Error};
+use hickory_client::{
+    client::{AsyncClient, ClientHandle},
+    rr::{DNSClass, Name, RecordType as HickoryType},
+    udp::UdpClientStream,
+};
+use tokio::net::UdpSocket;
+
+use crate::providers::RecordType;
+
+/// Name servers that answer a special name with the address the query came from, so
+/// the public address can be found without HTTP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DnsEcho {
+    /// `myip.opendns.com` A/AAAA at resolver1.opendns.com.
+    OpenDns,
+    /// `o-o.myaddr.l.google.com` TXT at ns1.google.com.
+    Google,
+}
+
+impl DnsEcho {
+    fn query(self, family: RecordType) -> (&'static str, HickoryType, &'static str) {
+        match (self, family) {
+            (Self::OpenDns, RecordType::Aaaa) => (
+                "myip.opendns.com.",
+                HickoryType::AAAA,
+                "[2620:119:35::35]:53",
+            ),
+            (Self::OpenDns, _) => ("myip.opendns.com.", HickoryType::A, "208.67.222.222:53"),
+            (Self::Google, RecordType::Aaaa) => (
+                "o-o.myaddr.l.google.com.",
+                HickoryType::TXT,
+                "[2001:4860:4802:32::a]:53",
+            ),
+            (Self::Google, _) => (
+                "o-o.myaddr.l.google.com.",
+                HickoryType::TXT,
+                "216.239.32.10:53",
+            ),
+        }
+    }
+
+    /// Asks the name server over `family` for the address the query came from.
+    pub async fn external_address(
+        self,
+        family: RecordType,
+        timeout: Duration,
+    ) -> Result<IpAddr, Error> {
+        let (name, record_type, server) = self.query(family);
+        let server: SocketAddr = server.parse()?;
+        let stream = UdpClientStream::<UdpSocket>::with_timeout(server, timeout);
+        let (mut client, background) = AsyncClient::connect(stream).await?;
+        tokio::spawn(background);
+        let response = client
+            .query(Name::from_ascii(name)?, DNSClass::IN, record_type)
+            .await?;
+        response
+            .answers()
+            .iter()
+            .filter(|answer| answer.record_type() == record_type)
+            .filter_map(|answer| answer.data())
+            .find_map(|data| data.to_string().trim_matches('"').parse().ok())
+            .ok_or(anyhow!("{server} returned no address for {name}."))
+    }
+}
+
+impl FromStr for DnsEcho {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "opendns" => Ok(Self::OpenDns),
+            "google" => Ok(Self::Google),
+            _ => Err(anyhow!(
+                "DNS IP source {s:?} must be \"opendns\" or \"google\"."
+            )),
+        }
+    }
+}
+
+impl Display for DnsEcho {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::OpenDns => write!(f, "opendns"),
+            Self::Google => write!(f, "google"),
+        }
+    }
+}
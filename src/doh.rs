@@ -0,0 +1,147 @@
+use std::net::{IpAddr, SocketAddr};
+
+use anyhow::{anyhow, Error};
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    Url,
+};
+use serde::Deserialize;
+
+const RECORD_TYPE_A: u16 = 1;
+const RECORD_TYPE_AAAA: u16 = 28;
+
+#[derive(Deserialize)]
+struct DohResponse {
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DohAnswer>,
+}
+
+#[derive(Deserialize)]
+struct DohAnswer {
+    #[serde(rename = "type")]
+    record_type: u16,
+    data: String,
+}
+
+/// Resolves hostnames with a DNS-over-HTTPS JSON API (e.g. `https://cloudflare-dns.com/dns-query`)
+/// instead of the system resolver.
+#[derive(Clone, Debug)]
+pub struct DohResolver {
+    url: Url,
+    client: reqwest::Client,
+}
+
+impl DohResolver {
+    pub fn new(url: &str) -> Result<Self, Error> {
+        let url: Url = url.parse()?;
+        if url.scheme() != "https" {
+            return Err(anyhow!("DNS-over-HTTPS resolver {url} must use https."));
+        }
+        Ok(Self {
+            url,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    async fn lookup(&self, name: &str, record_type: u16) -> Result<Vec<IpAddr>, Error> {
+        let body = self
+            .client
+            .get(self.url.clone())
+            .query(&[("name", name), ("type", &record_type.to_string())])
+            .header("accept", "application/dns-json")
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let response: DohResponse = serde_json::from_str(&body)?;
+        Ok(response
+            .answer
+            .into_iter()
+            .filter(|answer| answer.record_type == record_type)
+            .filter_map(|answer| answer.data.parse().ok())
+            .collect())
+    }
+
+    pub(crate) async fn resolve_all(&self, name: &str) -> Result<Vec<IpAddr>, Error> {
+        let (v4, v6) = tokio::join!(
+            self.lookup(name, RECORD_TYPE_A),
+            self.lookup(name, RECORD_TYPE_AAAA)
+        );
+        let addrs: Vec<IpAddr> = v4
+            .unwrap_or_default()
+            .into_iter()
+            .chain(v6.unwrap_or_default())
+            .collect();
+        if addrs.is_empty() {
+            return Err(anyhow!("{} returned no addresses for {name}.", self.url));
+        }
+        Ok(addrs)
+    }
+}
+
+impl Resolve for DohResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.clone();
+        Box::pin(async move {
+            let addrs = resolver.resolve_all(name.as_str()).await?;
+            let addrs: Addrs = Box::new(addrs.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::test_util::http_server;
+
+    #[test]
+    fn needs_https() {
+        assert!(DohResolver::new("http://cloudflare-dns.com/dns-query").is_err());
+        assert!(DohResolver::new("https://cloudflare-dns.com/dns-query").is_ok());
+    }
+
+    #[tokio::test]
+    async fn resolves_requests_through_the_endpoint() {
+        let (provider, _) = http_server(|_| (200, "203.0.113.9".to_owned())).await;
+        let port = provider.rsplit(':').next().unwrap().to_owned();
+        let (doh, lookups) = http_server(|request| {
+            let body = if request.target.ends_with("type=1") {
+                r#"{"Status":0,"Answer":[{"name":"ip.provider.test","type":1,"data":"127.0.0.1"}]}"#
+            } else {
+                r#"{"Status":0}"#
+            };
+            (200, body.to_owned())
+        })
+        .await;
+        // Plain HTTP is refused for real endpoints, the stub has no certificate.
+        let resolver = DohResolver {
+            url: format!("{doh}/dns-query").parse().unwrap(),
+            client: reqwest::Client::new(),
+        };
+        let client = reqwest::Client::builder()
+            .dns_resolver(Arc::new(resolver))
+            .build()
+            .unwrap();
+
+        let body = client
+            .get(format!("http://ip.provider.test:{port}/"))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        assert_eq!(body, "203.0.113.9");
+        let lookups = lookups.lock().unwrap();
+        assert!(lookups
+            .iter()
+            .all(|lookup| lookup.target.contains("name=ip.provider.test")
+                && lookup.header("accept") == Some("application/dns-json")));
+        assert_eq!(lookups.len(), 2);
+    }
+}
@@ -0,0 +1,48 @@
+use anyhow::Error;
+use log::info;
+
+/// Collects why one tick did or didn't write a record, so `--explain` can log the
+/// whole chain of gates as a single entry.
+#[derive(Debug)]
+pub struct Explanation {
+    record: String,
+    steps: Vec<String>,
+    decision: Option<String>,
+}
+
+impl Explanation {
+    pub fn new(record: &str) -> Self {
+        Self {
+            record: record.to_owned(),
+            steps: Vec::new(),
+            decision: None,
+        }
+    }
+
+    pub fn note(&mut self, step: impl Into<String>) {
+        self.steps.push(step.into());
+    }
+
+    pub fn decide(&mut self, decision: impl Into<String>) {
+        self.decision = Some(decision.into());
+    }
+
+    /// Logs the steps and the decision. A failed update is its own decision.
+    pub fn log<T>(&self, result: &Result<T, Error>) {
+        info!("{}", self.message(result));
+    }
+
+    pub(crate) fn message<T>(&self, result: &Result<T, Error>) -> String {
+        let decision = match (result, &self.decision) {
+            (Err(e), _) => format!("error: {e:#}"),
+            (Ok(_), Some(decision)) => decision.clone(),
+            (Ok(_), None) => "skip: nothing to publish".to_owned(),
+        };
+        let mut message = format!("Decision for {}: {decision}", self.record);
+        for step in &self.steps {
+            message.push_str("\n  - ");
+            message.push_str(step);
+        }
+        message
+    }
+}
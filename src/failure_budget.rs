@@ -0,0 +1,123 @@
+use std::{collections::VecDeque, fmt::Display, str::FromStr, time::Duration};
+
+use anyhow::{anyhow, Error};
+use clap::ValueEnum;
+use serde::Deserialize;
+use tokio::time::Instant;
+
+/// What to do once the failure budget is used up.
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetAction {
+    /// Log an alert and keep running.
+    #[default]
+    Alert,
+    /// Stop the daemon with an error.
+    Exit,
+}
+
+/// At most `limit` failures are tolerated within any `window`, written as `N/window`, e.g. `5/1h`.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct FailureBudgetSpec {
+    pub limit: usize,
+    pub window: Duration,
+}
+
+impl FromStr for FailureBudgetSpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (limit, window) = s.split_once('/').ok_or(anyhow!(
+            "Failure budget {s:?} must be in the form N/window, e.g. 5/1h."
+        ))?;
+        Ok(Self {
+            limit: limit.trim().parse()?,
+            window: humantime::parse_duration(window.trim())?,
+        })
+    }
+}
+
+impl TryFrom<String> for FailureBudgetSpec {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for FailureBudgetSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{}",
+            self.limit,
+            humantime::format_duration(self.window)
+        )
+    }
+}
+
+/// Sliding window of recent failures.
+#[derive(Debug)]
+pub struct FailureBudget {
+    spec: FailureBudgetSpec,
+    failures: VecDeque<Instant>,
+}
+
+impl FailureBudget {
+    pub fn new(spec: FailureBudgetSpec) -> Self {
+        Self {
+            spec,
+            failures: VecDeque::new(),
+        }
+    }
+
+    pub fn spec(&self) -> FailureBudgetSpec {
+        self.spec
+    }
+
+    /// Records a failure at `now`, returning true once more than `limit` failures
+    /// fall within the window.
+    pub fn record_failure(&mut self, now: Instant) -> bool {
+        self.record_failures(now, 1)
+    }
+
+    /// Records `count` failures at `now`, see [`FailureBudget::record_failure`]. No
+    /// failures only tells whether the budget is still exhausted.
+    pub fn record_failures(&mut self, now: Instant, count: u64) -> bool {
+        self.failures.extend((0..count).map(|_| now));
+        while let Some(oldest) = self.failures.front() {
+            if now.duration_since(*oldest) <= self.spec.window {
+                break;
+            }
+            self.failures.pop_front();
+        }
+        self.failures.len() > self.spec.limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_limit_and_window() {
+        let spec: FailureBudgetSpec = "5/1h".parse().unwrap();
+        assert_eq!(spec.limit, 5);
+        assert_eq!(spec.window, Duration::from_secs(3600));
+        assert!("5".parse::<FailureBudgetSpec>().is_err());
+    }
+
+    #[test]
+    fn exhausted_only_by_failures_within_the_window() {
+        let mut budget = FailureBudget::new("2/1m".parse().unwrap());
+        let start = Instant::now();
+        assert!(!budget.record_failure(start));
+        assert!(!budget.record_failure(start + Duration::from_secs(10)));
+        // The first failure has left the window by now.
+        assert!(!budget.record_failure(start + Duration::from_secs(61)));
+        assert!(budget.record_failure(start + Duration::from_secs(62)));
+        assert!(budget.record_failures(start + Duration::from_secs(200), 3));
+        assert!(!budget.record_failures(start + Duration::from_secs(400), 0));
+    }
+}
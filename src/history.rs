@@ -0,0 +1,124 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Write},
+    net::IpAddr,
+    path::PathBuf,
+};
+
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+
+/// A single published address change. Timestamps are unix seconds.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HistoryEntry {
+    pub timestamp: u64,
+    pub old_ip: Option<IpAddr>,
+    pub new_ip: IpAddr,
+    pub record: String,
+    pub zone: String,
+}
+
+/// Append-only change history stored as one JSON object per line.
+#[derive(Clone, Debug)]
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn append(&self, entry: &HistoryEntry) -> Result<(), Error> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Makes sure the appended entries have reached the disk.
+    pub fn flush(&self) -> Result<(), Error> {
+        match OpenOptions::new().append(true).open(&self.path) {
+            Ok(file) => Ok(file.sync_all()?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Streams the stored entries in the order they were written.
+    pub fn entries(&self) -> Result<impl Iterator<Item = Result<HistoryEntry, Error>>, Error> {
+        let reader = BufReader::new(File::open(&self.path)?);
+        Ok(reader
+            .lines()
+            .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+            .map(|line| Ok(serde_json::from_str(&line?)?)))
+    }
+}
+
+/// Writes the entries as CSV with a header row, one row at a time.
+pub fn write_csv(
+    entries: impl Iterator<Item = Result<HistoryEntry, Error>>,
+    writer: impl Write,
+) -> Result<(), Error> {
+    let mut csv = csv::Writer::from_writer(writer);
+    csv.write_record(["timestamp", "old_ip", "new_ip", "record", "zone"])?;
+    for entry in entries {
+        let entry = entry?;
+        csv.write_record([
+            entry.timestamp.to_string(),
+            entry.old_ip.map(|ip| ip.to_string()).unwrap_or_default(),
+            entry.new_ip.to_string(),
+            entry.record,
+            entry.zone,
+        ])?;
+    }
+    csv.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(timestamp: u64, old_ip: Option<&str>, new_ip: &str, zone: &str) -> HistoryEntry {
+        HistoryEntry {
+            timestamp,
+            old_ip: old_ip.map(|ip| ip.parse().unwrap()),
+            new_ip: new_ip.parse().unwrap(),
+            record: "home.example.com".to_owned(),
+            zone: zone.to_owned(),
+        }
+    }
+
+    #[test]
+    fn exports_the_entries_as_csv_in_order() {
+        let path = crate::test_util::temp_path("history.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let history = History::new(path);
+        history
+            .append(&entry(1_700_000_000, None, "8.8.8.8", "example.com"))
+            .unwrap();
+        history
+            .append(&entry(
+                1_700_000_600,
+                Some("8.8.8.8"),
+                "2001:4860::8888",
+                "a \"quoted\", listed zone",
+            ))
+            .unwrap();
+
+        let mut csv = Vec::new();
+        write_csv(history.entries().unwrap(), &mut csv).unwrap();
+
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "timestamp,old_ip,new_ip,record,zone\n\
+             1700000000,,8.8.8.8,home.example.com,example.com\n\
+             1700000600,8.8.8.8,2001:4860::8888,home.example.com,\"a \"\"quoted\"\", listed zone\"\n"
+        );
+    }
+}
@@ -0,0 +1,23 @@
+use std::net::SocketAddr;
+
+use anyhow::Error;
+use axum::Router;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+/// Serves `app` on `listener` until `shutdown_token` is cancelled. Handlers can extract
+/// the peer's `ConnectInfo<SocketAddr>`. Every HTTP endpoint the daemon offers goes
+/// through here, so they all behave the same on shutdown.
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    shutdown_token: CancellationToken,
+) -> Result<(), Error> {
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(async move { shutdown_token.cancelled().await })
+    .await?;
+    Ok(())
+}
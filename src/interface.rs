@@ -0,0 +1,188 @@
+//! Reads the address straight off a local network interface, for hosts that have their
+//! public address assigned directly.
+
+use std::net::{IpAddr, Ipv6Addr};
+
+use anyhow::{anyhow, Context, Error};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{ip_validation, providers::RecordType};
+
+/// Which of an interface's global IPv6 addresses is published.
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Ipv6Prefer {
+    /// Skip RFC 4941 temporary addresses, and deprecated or still tentative ones.
+    #[default]
+    Stable,
+    /// Like `stable`, but pick an EUI-64 address derived from the MAC when there is one.
+    Eui64,
+    /// The first global address, whatever its kind.
+    Any,
+}
+
+/// The global address of `family` assigned to the interface called `name`, picking
+/// among IPv6 addresses according to `prefer`.
+pub fn interface_address(
+    name: &str,
+    family: RecordType,
+    allow_ula: bool,
+    prefer: Ipv6Prefer,
+) -> Result<IpAddr, Error> {
+    let addresses = addresses(name)?;
+    let global = match family {
+        RecordType::A => addresses.iter().find_map(|ip| match ip {
+            IpAddr::V4(ipv4) if ip_validation::is_global_ipv4(ipv4) => Some(*ip),
+            _ => None,
+        }),
+        RecordType::Aaaa => {
+            let candidates: Vec<Ipv6Addr> = addresses
+                .iter()
+                .filter_map(|ip| match ip {
+                    IpAddr::V6(ipv6) if ip_validation::is_global_ipv6(ipv6, allow_ula) => {
+                        Some(*ipv6)
+                    }
+                    _ => None,
+                })
+                .collect();
+            select_ipv6(name, &candidates, prefer)?.map(IpAddr::V6)
+        }
+        RecordType::Txt => return Err(anyhow!("TXT records have no address family.")),
+    };
+    global.ok_or(anyhow!(
+        "Interface {name} has no global {family} address, only {addresses:?}."
+    ))
+}
+
+/// The address of `family` the interface called `name` has on its local network, private
+/// or not, which is what the private zone of a split-horizon pair serves.
+pub fn lan_address(name: &str, family: RecordType) -> Result<IpAddr, Error> {
+    let addresses = addresses(name)?;
+    select_lan_address(&addresses, family).ok_or(anyhow!(
+        "Interface {name} has no {family} LAN address, only {addresses:?}."
+    ))
+}
+
+/// The first address of `family` that other hosts on the network can reach, so neither
+/// loopback nor link-local.
+fn select_lan_address(addresses: &[IpAddr], family: RecordType) -> Option<IpAddr> {
+    addresses.iter().copied().find(|ip| match (ip, family) {
+        (IpAddr::V4(ipv4), RecordType::A) => {
+            !ipv4.is_loopback() && !ipv4.is_link_local() && !ipv4.is_unspecified()
+        }
+        (IpAddr::V6(ipv6), RecordType::Aaaa) => {
+            !ipv6.is_loopback() && !ip_validation::is_link_local(ipv6) && !ipv6.is_unspecified()
+        }
+        _ => false,
+    })
+}
+
+fn addresses(name: &str) -> Result<Vec<IpAddr>, Error> {
+    let addresses: Vec<IpAddr> = if_addrs::get_if_addrs()
+        .context("Failed to list network interfaces")?
+        .into_iter()
+        .filter(|interface| interface.name == name)
+        .map(|interface| interface.ip())
+        .collect();
+    if addresses.is_empty() {
+        return Err(anyhow!(
+            "Interface {name} does not exist or has no addresses."
+        ));
+    }
+    Ok(addresses)
+}
+
+fn select_ipv6(
+    name: &str,
+    candidates: &[Ipv6Addr],
+    prefer: Ipv6Prefer,
+) -> Result<Option<Ipv6Addr>, Error> {
+    if prefer == Ipv6Prefer::Any || candidates.is_empty() {
+        return Ok(candidates.first().copied());
+    }
+    let unstable = unstable_ipv6_addresses(name)?;
+    let mut stable = candidates.iter().filter(|ip| !unstable.contains(ip));
+    let selected = match prefer {
+        Ipv6Prefer::Eui64 => stable
+            .clone()
+            .find(|ip| is_eui64(ip))
+            .or_else(|| stable.next()),
+        _ => stable.next(),
+    };
+    match selected {
+        Some(ip) => Ok(Some(*ip)),
+        None => Err(anyhow!(
+            "Interface {name} only has temporary or deprecated IPv6 addresses {candidates:?}, \
+             use --ipv6-prefer any to publish one anyway."
+        )),
+    }
+}
+
+/// Interface identifiers derived from a MAC address have `ff:fe` in their middle.
+fn is_eui64(ip: &Ipv6Addr) -> bool {
+    let octets = ip.octets();
+    octets[11] == 0xff && octets[12] == 0xfe
+}
+
+/// Addresses of `name` that are temporary (RFC 4941), deprecated, tentative or failed
+/// duplicate address detection, according to the kernel's flags.
+#[cfg(target_os = "linux")]
+fn unstable_ipv6_addresses(name: &str) -> Result<Vec<Ipv6Addr>, Error> {
+    const IFA_F_TEMPORARY: u8 = 0x01;
+    const IFA_F_DADFAILED: u8 = 0x08;
+    const IFA_F_DEPRECATED: u8 = 0x20;
+    const IFA_F_TENTATIVE: u8 = 0x40;
+    let unstable = IFA_F_TEMPORARY | IFA_F_DADFAILED | IFA_F_DEPRECATED | IFA_F_TENTATIVE;
+    // Each line is: address, interface index, prefix length, scope, flags, name.
+    let table =
+        std::fs::read_to_string("/proc/net/if_inet6").context("Failed to read IPv6 flags")?;
+    Ok(table
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [address, _, _, _, flags, interface] = fields[..] else {
+                return None;
+            };
+            let flags = u8::from_str_radix(flags, 16).ok()?;
+            if interface != name || flags & unstable == 0 {
+                return None;
+            }
+            u128::from_str_radix(address, 16).ok().map(Ipv6Addr::from)
+        })
+        .collect())
+}
+
+/// Other platforms don't expose the flags, so every address counts as stable there.
+#[cfg(not(target_os = "linux"))]
+fn unstable_ipv6_addresses(_name: &str) -> Result<Vec<Ipv6Addr>, Error> {
+    Ok(Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lan_address_skips_loopback_and_link_local() {
+        let addresses: Vec<IpAddr> = [
+            "127.0.0.1",
+            "169.254.3.4",
+            "fe80::1",
+            "192.168.1.20",
+            "fd00::20",
+        ]
+        .iter()
+        .map(|ip| ip.parse().unwrap())
+        .collect();
+        assert_eq!(
+            select_lan_address(&addresses, RecordType::A),
+            Some("192.168.1.20".parse().unwrap())
+        );
+        assert_eq!(
+            select_lan_address(&addresses, RecordType::Aaaa),
+            Some("fd00::20".parse().unwrap())
+        );
+        assert_eq!(select_lan_address(&addresses[..3], RecordType::A), None);
+    }
+}
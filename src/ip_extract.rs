@@ -0,0 +1,89 @@
+use std::{fmt::Display, net::IpAddr, str::FromStr};
+
+use anyhow::{anyhow, Context, Error};
+use regex_lite::Regex;
+use serde::Deserialize;
+
+/// How the address is read from an IP provider's response body: `plain` for the whole
+/// body, `regex:<pattern>` for the first capture group (or the whole match without
+/// groups), or `json:<pointer>` for a string at a JSON pointer such as `/ip`.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(try_from = "String")]
+pub enum IpExtract {
+    #[default]
+    Plain,
+    Regex(Regex),
+    Json(String),
+}
+
+impl IpExtract {
+    pub fn extract(&self, body: &str) -> Result<IpAddr, Error> {
+        let text = match self {
+            Self::Plain => body,
+            Self::Regex(regex) => {
+                let captures = regex
+                    .captures(body)
+                    .ok_or(anyhow!("{regex} did not match the response."))?;
+                captures
+                    .get(1)
+                    .or_else(|| captures.get(0))
+                    .map(|m| m.as_str())
+                    .unwrap_or_default()
+            }
+            Self::Json(pointer) => {
+                let value: serde_json::Value =
+                    serde_json::from_str(body).context("The response is not JSON")?;
+                return value
+                    .pointer(pointer)
+                    .and_then(|value| value.as_str())
+                    .ok_or(anyhow!("The response has no string at {pointer}."))?
+                    .trim()
+                    .parse()
+                    .map_err(Error::from);
+            }
+        };
+        Ok(text.trim().parse()?)
+    }
+}
+
+impl FromStr for IpExtract {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("plain") {
+            return Ok(Self::Plain);
+        }
+        if let Some(pattern) = s.strip_prefix("regex:") {
+            return Ok(Self::Regex(
+                Regex::new(pattern).with_context(|| format!("Invalid regex {pattern:?}"))?,
+            ));
+        }
+        if let Some(pointer) = s.strip_prefix("json:") {
+            if !pointer.is_empty() && !pointer.starts_with('/') {
+                return Err(anyhow!("JSON pointer {pointer:?} must start with /."));
+            }
+            return Ok(Self::Json(pointer.to_owned()));
+        }
+        Err(anyhow!(
+            "IP extraction {s:?} must be \"plain\", \"regex:<pattern>\" or \"json:<pointer>\"."
+        ))
+    }
+}
+
+impl TryFrom<String> for IpExtract {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for IpExtract {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Plain => write!(f, "plain"),
+            Self::Regex(regex) => write!(f, "regex:{regex}"),
+            Self::Json(pointer) => write!(f, "json:{pointer}"),
+        }
+    }
+}
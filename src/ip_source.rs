@@ -0,0 +1,110 @@
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::{anyhow, Error};
+use serde::Deserialize;
+
+use crate::dns_echo::DnsEcho;
+
+/// Where the published address is read from: an HTTP provider URL, `imds` for EC2
+/// instance metadata, `upnp`, `natpmp` or `pcp` for the router's WAN address via UPnP
+/// IGD, NAT-PMP or PCP, `fritzbox` for a FRITZ!Box's TR-064 API, `mikrotik`, `opnsense`
+/// or `pfsense` for the WAN interface of that router or firewall, `interface` for the
+/// address assigned to a local network interface, `stun` for a STUN binding request to
+/// the zone's STUN servers, `dns:opendns` or `dns:google` to ask those name servers
+/// over DNS, or `lan` for the LAN address of the zone's interface, which is what a
+/// private zone in a split-horizon setup should serve.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum IpSource {
+    Http(String),
+    Imds,
+    Upnp,
+    NatPmp,
+    Pcp,
+    FritzBox,
+    Mikrotik,
+    Opnsense,
+    Pfsense,
+    Interface,
+    Stun,
+    Dns(DnsEcho),
+    Lan,
+}
+
+impl FromStr for IpSource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("imds") {
+            return Ok(Self::Imds);
+        }
+        if s.eq_ignore_ascii_case("upnp") {
+            return Ok(Self::Upnp);
+        }
+        if s.eq_ignore_ascii_case("natpmp") {
+            return Ok(Self::NatPmp);
+        }
+        if s.eq_ignore_ascii_case("pcp") {
+            return Ok(Self::Pcp);
+        }
+        if s.eq_ignore_ascii_case("fritzbox") {
+            return Ok(Self::FritzBox);
+        }
+        if s.eq_ignore_ascii_case("mikrotik") {
+            return Ok(Self::Mikrotik);
+        }
+        if s.eq_ignore_ascii_case("opnsense") {
+            return Ok(Self::Opnsense);
+        }
+        if s.eq_ignore_ascii_case("pfsense") {
+            return Ok(Self::Pfsense);
+        }
+        if s.eq_ignore_ascii_case("interface") {
+            return Ok(Self::Interface);
+        }
+        if s.eq_ignore_ascii_case("stun") {
+            return Ok(Self::Stun);
+        }
+        if let Some(echo) = s.strip_prefix("dns:") {
+            return Ok(Self::Dns(echo.parse()?));
+        }
+        if s.eq_ignore_ascii_case("lan") {
+            return Ok(Self::Lan);
+        }
+        if s.starts_with("http://") || s.starts_with("https://") {
+            return Ok(Self::Http(s.to_owned()));
+        }
+        Err(anyhow!(
+            "IP source {s:?} must be an http(s) URL, \"imds\", \"upnp\", \"natpmp\", \"pcp\", \"fritzbox\", \
+             \"mikrotik\", \"opnsense\", \"pfsense\", \"interface\", \"stun\", \"dns:opendns\", \"dns:google\" or \"lan\"."
+        ))
+    }
+}
+
+impl TryFrom<String> for IpSource {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for IpSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Http(url) => write!(f, "{url}"),
+            Self::Imds => write!(f, "imds"),
+            Self::Upnp => write!(f, "upnp"),
+            Self::NatPmp => write!(f, "natpmp"),
+            Self::Pcp => write!(f, "pcp"),
+            Self::FritzBox => write!(f, "fritzbox"),
+            Self::Mikrotik => write!(f, "mikrotik"),
+            Self::Opnsense => write!(f, "opnsense"),
+            Self::Pfsense => write!(f, "pfsense"),
+            Self::Interface => write!(f, "interface"),
+            Self::Stun => write!(f, "stun"),
+            Self::Dns(echo) => write!(f, "dns:{echo}"),
+            Self::Lan => write!(f, "lan"),
+        }
+    }
+}
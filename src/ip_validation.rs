@@ -0,0 +1,169 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::LazyLock,
+};
+
+use anyhow::{anyhow, Error};
+use ipnet::IpNet;
+
+/// Documentation ranges (RFC 5737, RFC 3849). No real client has one of these,
+/// so seeing one means a placeholder value leaked out of a provider.
+static DOCUMENTATION_RANGES: LazyLock<Vec<IpNet>> = LazyLock::new(|| {
+    [
+        "192.0.2.0/24",
+        "198.51.100.0/24",
+        "203.0.113.0/24",
+        "2001:db8::/32",
+    ]
+    .iter()
+    .map(|net| net.parse().unwrap())
+    .collect()
+});
+
+/// Ranges that are never reachable from the internet, with what they are used for.
+/// Carrier-grade NAT is handled separately, since whether to publish it is a choice.
+static NON_PUBLIC_RANGES: LazyLock<Vec<(IpNet, &str)>> = LazyLock::new(|| {
+    [
+        ("0.0.0.0/8", "\"this network\""),
+        ("10.0.0.0/8", "private (RFC 1918)"),
+        ("127.0.0.0/8", "loopback"),
+        ("169.254.0.0/16", "link-local"),
+        ("172.16.0.0/12", "private (RFC 1918)"),
+        ("192.168.0.0/16", "private (RFC 1918)"),
+        ("224.0.0.0/4", "multicast"),
+        ("240.0.0.0/4", "reserved"),
+        ("::/128", "unspecified"),
+        ("::1/128", "loopback"),
+        ("fe80::/10", "link-local"),
+        ("ff00::/8", "multicast"),
+    ]
+    .iter()
+    .map(|(net, kind)| (net.parse().unwrap(), *kind))
+    .collect()
+});
+
+/// Unique local IPv6 addresses (RFC 4193), the IPv6 counterpart of RFC 1918.
+static ULA_RANGE: LazyLock<IpNet> = LazyLock::new(|| "fc00::/7".parse().unwrap());
+
+/// Shared address space for carrier-grade NAT (RFC 6598).
+static CGNAT_RANGE: LazyLock<IpNet> = LazyLock::new(|| "100.64.0.0/10".parse().unwrap());
+
+/// Whether `ip` is handed out by a carrier-grade NAT, which usually means inbound
+/// connections can't reach this host no matter what DNS says.
+pub fn is_cgnat(ip: IpAddr) -> bool {
+    CGNAT_RANGE.contains(&ip)
+}
+
+/// Refuses addresses that should never be published as a client address. Unless
+/// `allow_private` is set, that includes private, loopback, link-local and other ranges
+/// that can't be reached from the internet, and unique local addresses unless
+/// `allow_ula` is set.
+pub fn check_publishable(ip: IpAddr, allow_private: bool, allow_ula: bool) -> Result<(), Error> {
+    if let Some(net) = DOCUMENTATION_RANGES.iter().find(|net| net.contains(&ip)) {
+        return Err(anyhow!(
+            "Detected address {ip} is in the documentation range {net}, refusing to publish it."
+        ));
+    }
+    if allow_private {
+        return Ok(());
+    }
+    if let Some((net, kind)) = NON_PUBLIC_RANGES.iter().find(|(net, _)| net.contains(&ip)) {
+        return Err(anyhow!(
+            "Detected address {ip} is in the {kind} range {net}, refusing to publish it. \
+             Pass --allow-private-addresses if this zone is meant to serve it."
+        ));
+    }
+    if !allow_ula && ULA_RANGE.contains(&ip) {
+        return Err(anyhow!(
+            "Detected address {ip} is a unique local address, refusing to publish it. \
+             Pass --allow-ula if this zone is meant to serve it."
+        ));
+    }
+    Ok(())
+}
+
+/// Whether `ip` is reachable from the internet, i.e. in none of the non-public or
+/// documentation ranges. Carrier-grade NAT addresses count, see [`is_cgnat`].
+pub fn is_global_ipv4(ip: &Ipv4Addr) -> bool {
+    let ip = IpAddr::V4(*ip);
+    !DOCUMENTATION_RANGES
+        .iter()
+        .chain(NON_PUBLIC_RANGES.iter().map(|(net, _)| net))
+        .any(|net| net.contains(&ip))
+}
+
+/// Picks the first globally routable IPv6 address, skipping link-local (fe80::/10)
+/// and, unless `allow_ula` is set, unique local (fc00::/7) addresses.
+pub fn select_global_ipv6(
+    addrs: impl IntoIterator<Item = Ipv6Addr>,
+    allow_ula: bool,
+) -> Option<Ipv6Addr> {
+    addrs.into_iter().find(|ip| is_global_ipv6(ip, allow_ula))
+}
+
+/// Whether `ip` is globally routable, see [`select_global_ipv6`].
+pub fn is_global_ipv6(ip: &Ipv6Addr, allow_ula: bool) -> bool {
+    !ip.is_loopback()
+        && !ip.is_unspecified()
+        && !ip.is_multicast()
+        && !is_link_local(ip)
+        && (allow_ula || !ip.is_unique_local())
+}
+
+/// Whether `ip` is an IPv6 link-local address (fe80::/10), which routers list next to
+/// the global address of an interface.
+pub fn is_link_local(ip: &Ipv6Addr) -> bool {
+    ip.is_unicast_link_local()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_documentation_ranges_even_when_private_is_allowed() {
+        for ip in ["192.0.2.1", "198.51.100.7", "203.0.113.254", "2001:db8::1"] {
+            let ip: IpAddr = ip.parse().unwrap();
+            let err = check_publishable(ip, true, true).unwrap_err();
+            assert!(err.to_string().contains("documentation range"), "{err}");
+        }
+    }
+
+    #[test]
+    fn rejects_non_public_ranges_unless_allowed() {
+        let ip: IpAddr = "192.168.1.10".parse().unwrap();
+        assert!(check_publishable(ip, false, false).is_err());
+        assert!(check_publishable(ip, true, false).is_ok());
+        let ula: IpAddr = "fd00::1".parse().unwrap();
+        assert!(check_publishable(ula, false, false).is_err());
+        assert!(check_publishable(ula, false, true).is_ok());
+    }
+
+    #[test]
+    fn accepts_public_addresses() {
+        for ip in ["8.8.8.8", "2606:4700:4700::1111"] {
+            assert!(check_publishable(ip.parse().unwrap(), false, false).is_ok());
+        }
+    }
+
+    #[test]
+    fn global_ipv4_excludes_non_public_ranges() {
+        for ip in ["8.8.8.8", "100.64.1.1"] {
+            assert!(is_global_ipv4(&ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["10.1.2.3", "169.254.1.1", "192.0.2.1", "255.255.255.255"] {
+            assert!(!is_global_ipv4(&ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn skips_link_local_ipv6() {
+        let addrs = ["fe80::1", "fd00::2", "2a00:1450::3"].map(|ip| ip.parse().unwrap());
+        assert_eq!(
+            select_global_ipv6(addrs, false),
+            Some("2a00:1450::3".parse().unwrap())
+        );
+        assert_eq!(select_global_ipv6(addrs, true), Some(addrs[1]));
+        assert_eq!(select_global_ipv6(addrs[..1].to_vec(), true), None);
+    }
+}
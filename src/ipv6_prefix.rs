@@ -0,0 +1,42 @@
+//! Builds a host's IPv6 address from the prefix the ISP currently delegates and an
+//! interface identifier that stays the same when the prefix rotates.
+
+use std::net::Ipv6Addr;
+
+use anyhow::{anyhow, Error};
+
+/// Keeps the first `prefix_length` bits of `detected` and takes the rest from `suffix`.
+pub fn with_suffix(detected: Ipv6Addr, prefix_length: u8, suffix: Ipv6Addr) -> Ipv6Addr {
+    let mask = prefix_mask(prefix_length);
+    Ipv6Addr::from((u128::from(detected) & mask) | (u128::from(suffix) & !mask))
+}
+
+/// Refuses suffixes that would be cut off by the prefix, which usually means the prefix
+/// length or the suffix is a typo.
+pub fn validate_suffix(prefix_length: u8, suffix: Ipv6Addr) -> Result<(), Error> {
+    if prefix_length == 0 || prefix_length > 127 {
+        return Err(anyhow!(
+            "IPv6 prefix length {prefix_length} must be between 1 and 127."
+        ));
+    }
+    if u128::from(suffix) & prefix_mask(prefix_length) != 0 {
+        return Err(anyhow!(
+            "IPv6 suffix {suffix} has bits set inside the /{prefix_length} prefix, write it like ::1234:5678."
+        ));
+    }
+    Ok(())
+}
+
+/// Parses a `--lan-host` value written as `name=suffix`, e.g. `nas=::11`.
+pub fn parse_lan_host(s: &str) -> Result<(String, Ipv6Addr), Error> {
+    let (name, suffix) = s.split_once('=').ok_or(anyhow!(
+        "LAN host {s:?} must be written as name=suffix, e.g. nas=::11."
+    ))?;
+    Ok((name.to_owned(), suffix.parse()?))
+}
+
+fn prefix_mask(prefix_length: u8) -> u128 {
+    u128::MAX
+        .checked_shl(128 - u32::from(prefix_length))
+        .unwrap_or(0)
+}
@@ -0,0 +1,2182 @@
+//! Keeps DNS records pointed at this host's public address. The `route53_dd` binary is
+//! a thin command line wrapper around this crate: embedders build a [`HostedZoneConfig`]
+//! and an [`UpdateContext`], then call [`update_hosted_zone`] for a single update or
+//! [`daemon_update_zone`] to keep the records updated until shutdown.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    future::Future,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use adaptive_ttl::AdaptiveTtl;
+use address_watch::AddressWatch;
+use anyhow::{anyhow, Context, Error};
+use aws_config::{
+    self,
+    identity::IdentityCache,
+    provider_config::ProviderConfig,
+    retry::RetryConfig,
+    sts::AssumeRoleProvider,
+    web_identity_token::{StaticConfiguration, WebIdentityTokenCredentialsProvider},
+    BehaviorVersion, ConfigLoader, Region, SdkConfig,
+};
+use aws_credential_types::provider::SharedCredentialsProvider;
+use aws_sdk_route53::Client;
+use aws_smithy_runtime::client::http::hyper_014::HyperClientBuilder;
+use cert_pin::CertPin;
+use cgnat::CgnatAction;
+use clients::ZoneClients;
+use config::ZoneSource;
+use credential_provider::{
+    AssumeRoleConfig, AwsCredentials, ReloadingCredentials, WebIdentityConfig,
+};
+use desktop::{ChangeNotification, Notifier};
+use detect::{detect_addresses, detect_per_source, Detected};
+use doh::DohResolver;
+use explain::Explanation;
+use failure_budget::{BudgetAction, FailureBudget, FailureBudgetSpec};
+use futures::future::join_all;
+use history::{History, HistoryEntry};
+use interface::Ipv6Prefer;
+use ip_extract::IpExtract;
+use ip_source::IpSource;
+use log::{debug, error, info, warn};
+use mfa::MfaAssumeRoleProvider;
+#[cfg(feature = "keyring")]
+use os_keyring::KeyringCredentials;
+use providers::{
+    cloudflare::{CloudflareConfig, CloudflareProvider},
+    rfc2136::{Rfc2136Config, Rfc2136Provider},
+    route53::{GeolocationRouting, Route53Provider, ZoneVisibility},
+    zone_file::ZoneFileProvider,
+    DnsProvider, RecordSpec, RecordType,
+};
+use reachability::ReachabilityCheck;
+use retry::RetryPolicy;
+use routers::{firewall::FirewallConfig, fritzbox::FritzBoxConfig, mikrotik::MikrotikConfig};
+use secrets_manager::SecretsManagerCredentials;
+use serde::Deserialize;
+use state::{SharedState, StateFile};
+use statsd::StatsdClient;
+use sync_tracker::SyncTracker;
+use template::{Template, TemplateValues};
+use tokio::{
+    select,
+    time::{self, MissedTickBehavior},
+};
+use tokio_util::sync::CancellationToken;
+use vault::{VaultConfig, VaultCredentials};
+
+pub mod adaptive_ttl;
+pub mod address_watch;
+mod aws_errors;
+pub mod bulk;
+pub mod cert_pin;
+pub mod cgnat;
+mod change_limiter;
+pub mod clients;
+pub mod config;
+pub mod credential_provider;
+pub mod desktop;
+pub mod detect;
+pub mod dns_echo;
+mod doh;
+pub mod explain;
+pub mod failure_budget;
+pub mod history;
+mod http;
+pub mod interface;
+pub mod ip_extract;
+pub mod ip_source;
+mod ip_validation;
+pub mod ipv6_prefix;
+pub mod logging;
+pub mod mfa;
+mod nat_pmp;
+#[cfg(feature = "keyring")]
+pub mod os_keyring;
+pub mod plan;
+pub mod providers;
+pub mod reachability;
+pub mod retry;
+pub mod routers;
+pub mod secrets_manager;
+pub mod serve_ip;
+pub mod ssm;
+pub mod state;
+pub mod statsd;
+pub mod stun;
+pub mod sync_tracker;
+pub mod template;
+#[cfg(test)]
+mod test_util;
+pub mod vault;
+mod zones;
+
+/// Longest wait before restarting a panicked update loop.
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Daemon-wide handles shared by every update.
+#[derive(Clone, Debug)]
+pub struct UpdateContext {
+    /// Whether updates repeat; retries and waits are only worth it in that case.
+    pub daemon: bool,
+    /// Print the records an update would write instead of writing them.
+    pub dry_run: bool,
+    pub state: SharedState,
+    pub history: Option<History>,
+    pub statsd: Option<Arc<StatsdClient>>,
+    pub sync_tracker: Option<SyncTracker>,
+    pub state_file: Option<StateFile>,
+    pub clients: ZoneClients,
+    pub notifier: Notifier,
+    /// Cancelled on shutdown, which stops waiting to retry a failed step.
+    pub shutdown: CancellationToken,
+}
+
+impl UpdateContext {
+    /// Finishes side effects that are still in flight so they aren't lost on exit.
+    pub async fn flush(&self) {
+        if let Some(tracker) = &self.sync_tracker {
+            tracker.flush().await;
+        }
+        self.notifier.flush().await;
+        if let Some(statsd) = &self.statsd {
+            statsd.flush();
+        }
+        if let Some(history) = &self.history {
+            if let Err(e) = history.flush() {
+                warn!("Failed to flush the history file: {e:?}");
+            }
+        }
+        log::logger().flush();
+    }
+
+    /// Counts a failed call to the DNS backend against the zone's failure budget.
+    fn count_failure<T>(&self, result: Result<T, Error>) -> Result<T, Error> {
+        if result.is_err() {
+            self.state.lock().unwrap().failed_calls += 1;
+        }
+        result
+    }
+
+    /// Records the outcome of one update in the shared state, the state file and metrics.
+    fn report_update(
+        &self,
+        zone: &HostedZoneConfig,
+        fqdn: &str,
+        result: &Result<UpdateOutcome, Error>,
+    ) {
+        let last_ip_change = {
+            let mut state = self.state.lock().unwrap();
+            match result {
+                Ok(_) => state.record_success(),
+                Err(e) => state.record_failure(e),
+            }
+            if let Some(state_file) = self.state_file.as_ref().filter(|_| !self.dry_run) {
+                if let Err(e) = state_file.save(fqdn, &state) {
+                    warn!("Failed to save the state of {fqdn}: {e:?}");
+                }
+            }
+            state.last_ip_change
+        };
+        let Some(statsd) = &self.statsd else {
+            return;
+        };
+        let zone_name = canonical_name(&zone.zone_name);
+        let tags = [("zone", zone_name.as_str()), ("record", fqdn)];
+        match result {
+            Ok(_) => statsd.count("updates", 1, &tags),
+            Err(_) => statsd.count("failures", 1, &tags),
+        }
+        if let Some(changed_at) = last_ip_change {
+            let age = state::unix_now().saturating_sub(changed_at);
+            statsd.gauge("last_change_age_seconds", age, &tags);
+        }
+    }
+}
+
+/// One zone's records and how to keep them updated, read from the command line or one
+/// `[[zones]]` table of the config file.
+#[derive(Deserialize, Clone, Debug)]
+pub struct HostedZoneConfig {
+    pub update_frequency_minutes: u64,
+    pub zone_name: String,
+    #[serde(default)]
+    pub zone_tag: Option<String>,
+    #[serde(default)]
+    pub hosted_zone_id: Option<String>,
+    #[serde(default)]
+    pub zone_visibility: ZoneVisibility,
+    #[serde(default)]
+    pub refresh_zone_id_every_seconds: Option<u64>,
+    #[serde(deserialize_with = "config::one_or_many")]
+    pub record_name: Vec<String>,
+    #[serde(default)]
+    pub record_name_is_fqdn: bool,
+    #[serde(default)]
+    pub also_apex: bool,
+    #[serde(default)]
+    pub also_www: bool,
+    pub ipv4: bool,
+    pub ipv6: bool,
+    #[serde(default)]
+    pub allow_ula: bool,
+    #[serde(default)]
+    pub allow_private_addresses: bool,
+    /// Publish the first `ipv6_prefix_length` bits of the detected IPv6 address followed
+    /// by `ipv6_suffix`, for hosts whose delegated prefix rotates.
+    #[serde(default)]
+    pub ipv6_prefix_length: Option<u8>,
+    #[serde(default)]
+    pub ipv6_suffix: Option<Ipv6Addr>,
+    /// Other hosts in the delegated prefix, by record name, whose AAAA records are
+    /// rewritten with the prefix's new bits and their interface identifier whenever the
+    /// IPv6 address changes.
+    #[serde(default)]
+    pub lan_hosts: BTreeMap<String, Ipv6Addr>,
+    #[serde(
+        default = "detect::default_ip_providers",
+        deserialize_with = "config::one_or_many"
+    )]
+    pub ip_provider: Vec<String>,
+    #[serde(
+        default = "detect::default_ip_provider_timeout_seconds",
+        alias = "detection_timeout_secs"
+    )]
+    pub ip_provider_timeout_seconds: u64,
+    #[serde(default)]
+    pub ip_consensus: Option<usize>,
+    #[serde(default)]
+    pub ipv4_url: Option<String>,
+    #[serde(default)]
+    pub ipv6_url: Option<String>,
+    #[serde(default)]
+    pub ip_extract: IpExtract,
+    #[serde(default = "stun::default_stun_servers")]
+    pub stun_servers: Vec<String>,
+    #[serde(default)]
+    pub nat_gateway: Option<Ipv4Addr>,
+    #[serde(default)]
+    pub fritzbox: Option<FritzBoxConfig>,
+    #[serde(default)]
+    pub mikrotik: Option<MikrotikConfig>,
+    #[serde(default)]
+    pub firewall: Option<FirewallConfig>,
+    /// Network interface read by the `interface` and `lan` IP sources, and the only one
+    /// `watch_address_changes` listens to when set.
+    #[serde(default)]
+    pub interface: Option<String>,
+    #[serde(default)]
+    pub ipv6_prefer: Ipv6Prefer,
+    #[serde(default)]
+    pub watch_address_changes: bool,
+    #[serde(default = "address_watch::default_debounce_seconds")]
+    pub address_change_debounce_seconds: u64,
+    #[serde(default)]
+    pub txt_template: Option<Template>,
+    #[serde(default)]
+    pub change_comment: Option<Template>,
+    #[serde(default = "detect::default_ip_response_max_bytes")]
+    pub ip_response_max_bytes: usize,
+    #[serde(default)]
+    pub ip_from_header: Option<String>,
+    #[serde(default)]
+    pub ip_from_imds: bool,
+    #[serde(default = "detect::default_imds_endpoint")]
+    pub imds_endpoint: String,
+    #[serde(default)]
+    pub primary_ip_source: Option<IpSource>,
+    #[serde(default)]
+    pub backup_ip_source: Option<IpSource>,
+    #[serde(default)]
+    pub detect_timeout_total_seconds: Option<u64>,
+    #[serde(default)]
+    pub ip_doh_resolver: Option<String>,
+    /// Empty to take the region from the profile or the environment.
+    #[serde(default)]
+    pub region: String,
+    /// Static keys. Without them, and without `aws_profile`, credentials come from the
+    /// SDK's default chain: environment, shared files, SSO, web identity, ECS and IMDS.
+    #[serde(default)]
+    pub aws_credentials: Option<AwsCredentials>,
+    #[serde(default)]
+    pub aws_profile: Option<String>,
+    #[serde(default)]
+    pub web_identity: Option<WebIdentityConfig>,
+    /// ARN or name of a Secrets Manager secret holding the keys, read with the default
+    /// credential chain.
+    #[serde(default)]
+    pub aws_credentials_secret: Option<String>,
+    #[serde(default)]
+    pub vault: Option<VaultConfig>,
+    /// Name of the OS keyring entry written by `route53_dd credentials set`.
+    #[serde(default)]
+    pub aws_keyring_entry: Option<String>,
+    /// A role assumed after `assume_role_arn`, if any, for chaining from a hub account's
+    /// role into the account that owns this zone.
+    #[serde(default)]
+    pub assume_role: Option<AssumeRoleConfig>,
+    /// Set when the zone comes from `--config`.
+    #[serde(skip)]
+    pub source: Option<ZoneSource>,
+    pub ttl_seconds: i64,
+    #[serde(default)]
+    pub adaptive_ttl: Option<AdaptiveTtl>,
+    #[serde(default)]
+    pub zone_file: Option<PathBuf>,
+    #[serde(default)]
+    pub rfc2136: Option<Rfc2136Config>,
+    #[serde(default)]
+    pub preserve_other_types: bool,
+    #[serde(default)]
+    pub preserve_ttl: bool,
+    #[serde(default)]
+    pub seed_cache_from_dns: bool,
+    #[serde(default)]
+    pub compare_dns: bool,
+    #[serde(default)]
+    pub compare_dns_resolver: Option<String>,
+    #[serde(default)]
+    pub explain: bool,
+    #[serde(default)]
+    pub multi_value: bool,
+    #[serde(default)]
+    pub source_addresses: Vec<IpAddr>,
+    #[serde(default)]
+    pub startup_delay_seconds: u64,
+    #[serde(default)]
+    pub startup_jitter_seconds: u64,
+    #[serde(default)]
+    pub min_change_interval_seconds: u64,
+    #[serde(default)]
+    pub wait_for_sync_seconds: Option<u64>,
+    #[serde(default)]
+    pub assume_role_arn: Option<String>,
+    /// Defaults to [`DEFAULT_ROLE_SESSION_NAME`].
+    #[serde(default)]
+    pub role_session_name: Option<String>,
+    #[serde(default)]
+    pub external_id: Option<String>,
+    /// Serial number or ARN of the MFA device the role's trust policy requires.
+    #[serde(default)]
+    pub mfa_serial: Option<String>,
+    /// Shell command printing the current MFA code. Without it the code is read from
+    /// the terminal.
+    #[serde(default)]
+    pub mfa_token_command: Option<String>,
+    #[serde(default)]
+    pub aws_cert_pins: Vec<CertPin>,
+    #[serde(default)]
+    pub cloudflare: Option<CloudflareConfig>,
+    #[serde(default)]
+    pub geolocation: Option<GeolocationRouting>,
+    #[serde(default)]
+    pub reachability_check: Option<ReachabilityCheck>,
+    #[serde(default)]
+    pub pause_file: Option<PathBuf>,
+    #[serde(default)]
+    pub desktop_notify: bool,
+    /// Same as `on_cgnat = "fail"`, kept for existing configs.
+    #[serde(default)]
+    pub block_cgnat: bool,
+    #[serde(default)]
+    pub on_cgnat: Option<CgnatAction>,
+    /// Router IP source, e.g. `upnp`, whose WAN address is compared with the detected
+    /// IPv4 address to recognize carrier-grade NAT outside 100.64.0.0/10.
+    #[serde(default)]
+    pub cgnat_check_source: Option<IpSource>,
+    /// Attempts at detection and at each write within one cycle in daemon mode.
+    #[serde(default = "retry::default_max_attempts")]
+    pub retry_max_attempts: u32,
+    #[serde(default = "retry::default_max_elapsed_seconds")]
+    pub retry_max_elapsed_seconds: u64,
+    #[serde(default)]
+    pub failure_budget: Option<FailureBudgetSpec>,
+    #[serde(default)]
+    pub on_failure_budget_exhausted: BudgetAction,
+}
+
+impl HostedZoneConfig {
+    /// The fully qualified name of the first managed record, which identifies the zone's
+    /// updates in logs and state.
+    pub fn fqdn(&self) -> Result<String, Error> {
+        let record_name = self
+            .record_name
+            .first()
+            .ok_or(anyhow!("No record name configured for {}.", self.zone_name))?;
+        self.qualify(record_name)
+    }
+
+    /// What to do with a carrier-grade NAT address: the configured action, or refusing
+    /// it unless private addresses may be published.
+    pub fn cgnat_action(&self) -> CgnatAction {
+        match self.on_cgnat {
+            _ if self.block_cgnat => CgnatAction::Fail,
+            Some(action) => action,
+            None if self.allows_private_addresses() => CgnatAction::Warn,
+            None => CgnatAction::Fail,
+        }
+    }
+
+    /// Whether addresses that aren't reachable from the internet may be published, which
+    /// the `lan` IP source always implies.
+    pub fn allows_private_addresses(&self) -> bool {
+        self.allow_private_addresses
+            || [&self.primary_ip_source, &self.backup_ip_source]
+                .into_iter()
+                .any(|source| *source == Some(IpSource::Lan))
+    }
+
+    /// The fully qualified form of `record_name`, lowercased and without a trailing dot.
+    fn qualify(&self, record_name: &str) -> Result<String, Error> {
+        let zone = canonical_name(&self.zone_name);
+        if !self.record_name_is_fqdn {
+            return Ok(format!("{}.{}", canonical_name(record_name), zone));
+        }
+        let fqdn = canonical_name(record_name);
+        if fqdn != zone && !fqdn.ends_with(&format!(".{zone}")) {
+            return Err(anyhow!("Record name {fqdn} is not within zone {zone}."));
+        }
+        Ok(fqdn)
+    }
+
+    /// Checks the settings that can't be rejected while parsing them.
+    pub fn validate(&self) -> Result<(), Error> {
+        for url in [&self.ip_doh_resolver, &self.compare_dns_resolver]
+            .into_iter()
+            .flatten()
+        {
+            DohResolver::new(url)?;
+        }
+        if let Some(tag) = &self.zone_tag {
+            zones::parse_tag(tag)?;
+            if self.hosted_zone_id.is_some() {
+                return Err(anyhow!(
+                    "Only one of zone_tag and hosted_zone_id can be set."
+                ));
+            }
+        }
+        if let Some(geolocation) = &self.geolocation {
+            geolocation.validate()?;
+        }
+        if let Some(adaptive_ttl) = &self.adaptive_ttl {
+            adaptive_ttl.validate()?;
+        }
+        if self.txt_template.is_some() && self.rfc2136.is_some() {
+            return Err(anyhow!("--txt-template is not supported with RFC 2136."));
+        }
+        if let Some(quorum) = self.ip_consensus {
+            let families = [(self.ipv4, RecordType::A), (self.ipv6, RecordType::Aaaa)];
+            for (_, family) in families.into_iter().filter(|(enabled, _)| *enabled) {
+                let providers = detect::providers_for(self, family).len();
+                if quorum == 0 || quorum > providers {
+                    return Err(anyhow!(
+                        "--ip-consensus must be between 1 and the number of IP providers asked for {family} ({providers})."
+                    ));
+                }
+            }
+            if self.primary_ip_source.is_some() {
+                return Err(anyhow!(
+                    "--ip-consensus can't be combined with --primary-ip-source."
+                ));
+            }
+        }
+        for source in [&self.primary_ip_source, &self.backup_ip_source]
+            .into_iter()
+            .flatten()
+        {
+            let missing = match source {
+                IpSource::Interface | IpSource::Lan => {
+                    self.interface.is_none().then_some("--interface")
+                }
+                IpSource::Mikrotik => self.mikrotik.is_none().then_some("--mikrotik-url"),
+                IpSource::Opnsense | IpSource::Pfsense => {
+                    self.firewall.is_none().then_some("--firewall-url")
+                }
+                _ => None,
+            };
+            if let Some(missing) = missing {
+                return Err(anyhow!("The {source} IP source needs {missing}."));
+            }
+        }
+        let without_url =
+            (self.ipv4 && self.ipv4_url.is_none()) || (self.ipv6 && self.ipv6_url.is_none());
+        if self.primary_ip_source.is_none() && self.ip_provider.is_empty() && without_url {
+            return Err(anyhow!(
+                "Zone {} has no IP source, set ip_provider, ipv4_url and ipv6_url or primary_ip_source.",
+                self.zone_name
+            ));
+        }
+        match (self.ipv6_prefix_length, self.ipv6_suffix) {
+            (Some(prefix_length), Some(suffix)) => {
+                ipv6_prefix::validate_suffix(prefix_length, suffix)?
+            }
+            (None, None) => {}
+            (Some(_), None) if !self.lan_hosts.is_empty() => {}
+            _ => {
+                return Err(anyhow!(
+                    "ipv6_prefix_length needs ipv6_suffix or lan_hosts, and ipv6_suffix needs ipv6_prefix_length."
+                ))
+            }
+        }
+        if !self.lan_hosts.is_empty() {
+            let prefix_length = self
+                .ipv6_prefix_length
+                .ok_or(anyhow!("lan_hosts needs ipv6_prefix_length."))?;
+            if !self.ipv6 {
+                return Err(anyhow!("lan_hosts needs ipv6 to be enabled."));
+            }
+            let names = self.record_names()?;
+            for (host, suffix) in &self.lan_hosts {
+                ipv6_prefix::validate_suffix(prefix_length, *suffix)?;
+                let fqdn = self.qualify(host)?;
+                if names.contains(&fqdn) {
+                    return Err(anyhow!("LAN host {fqdn} is also a managed record."));
+                }
+            }
+        }
+        if self.assume_role_arn.is_none()
+            && (self.external_id.is_some() || self.mfa_serial.is_some())
+        {
+            return Err(anyhow!("external_id and mfa_serial need assume_role_arn."));
+        }
+        if self.role_session_name.is_some()
+            && self.assume_role_arn.is_none()
+            && self.web_identity.is_none()
+            && self.assume_role.is_none()
+        {
+            return Err(anyhow!(
+                "role_session_name needs assume_role_arn, assume_role or web_identity."
+            ));
+        }
+        let base_credentials = [
+            self.aws_profile.is_some() || self.aws_credentials.is_some(),
+            self.web_identity.is_some(),
+            self.aws_credentials_secret.is_some(),
+            self.vault.is_some(),
+            self.aws_keyring_entry.is_some(),
+        ];
+        if base_credentials.into_iter().filter(|&set| set).count() > 1 {
+            return Err(anyhow!(
+                "Only one of aws_profile or aws_credentials, web_identity, aws_credentials_secret, vault and aws_keyring_entry can be set."
+            ));
+        }
+        if cfg!(not(feature = "keyring")) && self.aws_keyring_entry.is_some() {
+            return Err(anyhow!(
+                "aws_keyring_entry needs route53_dd built with the `keyring` feature."
+            ));
+        }
+        if let Some(vault) = &self.vault {
+            vault.validate()?;
+        }
+        if self.mfa_token_command.is_some() && self.mfa_serial.is_none() {
+            return Err(anyhow!("mfa_token_command needs mfa_serial."));
+        }
+        let session_names = [
+            self.role_session_name.as_ref(),
+            self.assume_role
+                .as_ref()
+                .and_then(|role| role.session_name.as_ref()),
+        ];
+        for name in session_names.into_iter().flatten() {
+            let valid_char = |c: char| c.is_ascii_alphanumeric() || "_+=,.@-".contains(c);
+            if !(2..=64).contains(&name.len()) || !name.chars().all(valid_char) {
+                return Err(anyhow!(
+                    "Role session name {name:?} must be 2 to 64 letters, digits or any of _+=,.@-"
+                ));
+            }
+        }
+        if self.retry_max_attempts == 0 {
+            return Err(anyhow!("retry_max_attempts must be at least 1."));
+        }
+        let route53 = self.zone_file.is_none()
+            && self.rfc2136.is_none()
+            && !self
+                .cloudflare
+                .as_ref()
+                .is_some_and(|cloudflare| cloudflare.primary);
+        if !route53 && (self.preserve_other_types || self.preserve_ttl) {
+            return Err(anyhow!(
+                "preserve_other_types and preserve_ttl are only supported for Route53."
+            ));
+        }
+        if self.multi_value && self.cloudflare.is_some() {
+            return Err(anyhow!("--multi-value is only supported for Route53."));
+        }
+        Ok(())
+    }
+
+    /// The managed records followed by the apex or `www` sibling when one was asked for.
+    pub fn record_names(&self) -> Result<Vec<String>, Error> {
+        let mut names = vec![self.fqdn()?];
+        for record_name in &self.record_name[1..] {
+            let fqdn = self.qualify(record_name)?;
+            if names.contains(&fqdn) {
+                return Err(anyhow!("{fqdn} is listed more than once."));
+            }
+            names.push(fqdn);
+        }
+        let zone = canonical_name(&self.zone_name);
+        let www = format!("www.{zone}");
+        let sibling = match (self.also_apex, self.also_www) {
+            (true, _) if names.contains(&www) => Some(zone),
+            (true, _) => return Err(anyhow!("--also-apex needs {www} to be managed.")),
+            (_, true) if names.contains(&zone) => Some(www),
+            (_, true) => return Err(anyhow!("--also-www needs the apex {zone} to be managed.")),
+            _ => None,
+        };
+        if let Some(sibling) = sibling.filter(|sibling| !names.contains(sibling)) {
+            names.push(sibling);
+        }
+        Ok(names)
+    }
+}
+
+fn canonical_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Writes `records` to `provider`, retrying transient failures of the write itself, then
+/// waits for the change to be served without retrying it.
+async fn publish(
+    provider: &dyn DnsProvider,
+    records: &[RecordSpec],
+    retry: &RetryPolicy,
+    what: &str,
+    ctx: &UpdateContext,
+) -> Result<(), Error> {
+    let change = retry
+        .run_transient(what, || async {
+            ctx.count_failure(provider.submit_all(records).await)
+        })
+        .await?;
+    if let Some(change) = change {
+        ctx.count_failure(provider.wait_for_sync(&change).await)?;
+    }
+    Ok(())
+}
+
+/// Cancels `shutdown_token` once `max_runtime` has passed, so every update loop winds
+/// down as if the daemon had been asked to stop.
+pub fn shut_down_after(max_runtime: Duration, shutdown_token: CancellationToken) {
+    tokio::spawn(async move {
+        select! {
+            _ = time::sleep(max_runtime) => {
+                info!("Reached max runtime of {max_runtime:?}, shutting down.");
+                shutdown_token.cancel();
+            }
+            _ = shutdown_token.cancelled() => {}
+        }
+    });
+}
+
+/// Runs the zone's daemon loop in its own task and restarts it with backoff when it
+/// panics, so a bug in one update path doesn't stop monitoring for good.
+pub async fn supervise_zone(
+    zone: HostedZoneConfig,
+    ctx: UpdateContext,
+    shutdown_token: CancellationToken,
+) -> Result<(), Error> {
+    let record = zone.fqdn()?;
+    let state = ctx.state.clone();
+    supervise(&record, &zone.zone_name, &state, &shutdown_token, || {
+        daemon_update_zone(zone.clone(), true, ctx.clone(), shutdown_token.clone())
+    })
+    .await
+}
+
+/// Spawns the loop `run` returns, and a fresh one each time it panics.
+async fn supervise<F, Fut>(
+    record: &str,
+    zone_name: &str,
+    state: &SharedState,
+    shutdown_token: &CancellationToken,
+    run: F,
+) -> Result<(), Error>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<(), Error>> + Send + 'static,
+{
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        let task = tokio::spawn(run());
+        match task.await {
+            Ok(result) => break result,
+            Err(e) if e.is_panic() => {
+                error!(
+                    "Update loop for {record} in zone {zone_name} panicked: {e}. Restarting in {backoff:?}."
+                );
+                // The loop may have panicked while holding the state's lock. What the state
+                // holds is still the best record of what was published, so keep it.
+                state.clear_poison();
+                select! {
+                    _ = time::sleep(backoff) => {}
+                    _ = shutdown_token.cancelled() => {
+                        info!("{record} shutdown.");
+                        break Ok(())
+                    }
+                }
+                backoff = (backoff * 2).min(MAX_RESTART_BACKOFF);
+            }
+            Err(e) => break Err(e.into()),
+        }
+    }
+}
+
+/// How long the daemon waits before the zone's first update: the configured delay plus a
+/// random share of the jitter window.
+fn startup_delay(zone: &HostedZoneConfig) -> Duration {
+    let jitter_millis = zone.startup_jitter_seconds.saturating_mul(1000);
+    Duration::from_secs(zone.startup_delay_seconds)
+        + Duration::from_millis(fastrand::u64(0..=jitter_millis))
+}
+
+/// Updates the zone once, or with `daemon` every `update_frequency_minutes` until
+/// `shutdown_token` is cancelled.
+pub async fn daemon_update_zone(
+    zone: HostedZoneConfig,
+    daemon: bool,
+    ctx: UpdateContext,
+    shutdown_token: CancellationToken,
+) -> Result<(), Error> {
+    let record = zone.fqdn()?;
+    // A single run was started on purpose, so it doesn't wait for the fleet to spread out.
+    let delay = startup_delay(&zone);
+    if daemon && !delay.is_zero() {
+        info!("Waiting {delay:?} before the first update of {record}");
+        select! {
+            _ = time::sleep(delay) => {}
+            _ = shutdown_token.cancelled() => {
+                info!("{record} shutdown.");
+                return Ok(());
+            }
+        }
+    }
+    if zone.seed_cache_from_dns {
+        if let Err(e) = seed_published(&zone, &ctx).await {
+            warn!("Failed to read the published values of {record}: {e:?}");
+        }
+    }
+    if !daemon {
+        let mut explanation = Explanation::new(&record);
+        let result = update_hosted_zone(zone.clone(), &ctx, &mut explanation).await;
+        if zone.explain {
+            explanation.log(&result);
+        }
+        if let Ok(UpdateOutcome::Paused) = result {
+            return Ok(());
+        }
+        ctx.report_update(&zone, &record, &result);
+        if let Err(e) = result {
+            error!("Error while updating zone {zone:?}: {e:?}");
+            return Err(e);
+        }
+        return Ok(());
+    }
+    let mut failure_budget = zone.failure_budget.map(FailureBudget::new);
+    let mut interval = time::interval(Duration::from_secs(60 * zone.update_frequency_minutes));
+    // A deferred or slow update shouldn't be followed by a burst of catch-up ticks.
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut address_watch = zone
+        .watch_address_changes
+        .then(|| AddressWatch::new(zone.interface.clone()))
+        .transpose()?;
+    let debounce = Duration::from_secs(zone.address_change_debounce_seconds);
+    loop {
+        select! {
+            _ = interval.tick() => {}
+            changed = address_watch::next_change(&mut address_watch, debounce) => match changed {
+                Ok(()) => {
+                    info!("Local addresses changed, updating {record} now.");
+                    interval.reset();
+                }
+                Err(e) => {
+                    warn!("Stopped watching for address changes, updating on the interval only: {e:?}");
+                    address_watch = None;
+                    continue;
+                }
+            },
+            _ = shutdown_token.cancelled() => {
+                info!("{record} shutdown.");
+                break Ok(())
+            }
+        }
+        let mut explanation = Explanation::new(&record);
+        let failed_calls = ctx.state.lock().unwrap().failed_calls;
+        let result = update_hosted_zone(zone.clone(), &ctx, &mut explanation).await;
+        if zone.explain {
+            explanation.log(&result);
+        }
+        if let Ok(UpdateOutcome::Paused) = result {
+            info!("Updating again at {:?}", interval.period());
+            continue;
+        }
+        if let Ok(UpdateOutcome::Deferred(wait)) = result {
+            // Detects again once the wait is over, the address may have changed back.
+            select! {
+                _ = time::sleep(wait) => interval.reset_immediately(),
+                _ = shutdown_token.cancelled() => {
+                    info!("{record} shutdown.");
+                    break Ok(())
+                }
+            }
+            continue;
+        }
+        ctx.report_update(&zone, &record, &result);
+        if let Some(budget) = failure_budget.as_mut() {
+            let failed = ctx.state.lock().unwrap().failed_calls - failed_calls;
+            if budget.record_failures(time::Instant::now(), failed) {
+                let spec = budget.spec();
+                match zone.on_failure_budget_exhausted {
+                    BudgetAction::Alert => {
+                        error!("Failure budget of {spec} for {record} is exhausted.")
+                    }
+                    BudgetAction::Exit => {
+                        break Err(anyhow!(
+                            "Failure budget of {spec} for {record} is exhausted, exiting."
+                        ));
+                    }
+                }
+            }
+        }
+        match result {
+            Err(e) => {
+                error!("Error while updating zone {zone:?}: {e:?}");
+                error!("Trying again at {:?}", interval.period())
+            }
+            Ok(_) => info!("Updating again at {:?}", interval.period()),
+        }
+    }
+}
+
+/// Session name used for `sts:AssumeRole` unless the zone sets `role_session_name`. It
+/// shows up in CloudTrail as part of the assumed role's ARN.
+pub const DEFAULT_ROLE_SESSION_NAME: &str = "route53_dd";
+
+/// Builds a Route53 client for the zone. With `web_identity`, the base credentials come
+/// from exchanging the token file for the web identity role. When `assume_role_arn` is
+/// set, the zone's credentials are only used to call `sts:AssumeRole`, and Route53 is
+/// called with the assumed role. The role's trust policy must allow `sts:AssumeRole` for
+/// the principal owning the base credentials, and the role itself needs the Route53
+/// permissions. The client's credential cache fetches new temporary credentials shortly
+/// before the current ones expire, so a long-running daemon keeps working.
+async fn route53_client(zone: &HostedZoneConfig) -> Client {
+    Client::new(&aws_sdk_config(zone, aws_config_loader(&zone.aws_cert_pins)).await)
+}
+
+/// The settings of [`route53_client`], starting from `loader` instead of the zone's TLS
+/// settings.
+async fn aws_sdk_config(zone: &HostedZoneConfig, loader: ConfigLoader) -> SdkConfig {
+    let loader = match (&zone.aws_profile, &zone.aws_credentials) {
+        (Some(profile), _) => {
+            info!("Using AWS profile {profile} for zone {}", zone.zone_name);
+            loader.profile_name(profile)
+        }
+        (None, Some(credentials)) => match &zone.source {
+            Some(source) => loader.credentials_provider(ReloadingCredentials::new(source.clone())),
+            None => loader.credentials_provider(credentials.clone()),
+        },
+        (None, None)
+            if zone.web_identity.is_some()
+                || zone.aws_credentials_secret.is_some()
+                || zone.vault.is_some()
+                || zone.aws_keyring_entry.is_some() =>
+        {
+            loader
+        }
+        (None, None) => {
+            info!(
+                "Using the default AWS credential chain for zone {}",
+                zone.zone_name
+            );
+            loader
+        }
+    };
+    let loader = match zone.region.as_str() {
+        "" => loader,
+        region => loader.region(Region::new(region.to_owned())),
+    };
+    let config = loader.load().await;
+    let session_name = zone
+        .role_session_name
+        .as_deref()
+        .unwrap_or(DEFAULT_ROLE_SESSION_NAME);
+    let provider = match (
+        &zone.web_identity,
+        &zone.aws_credentials_secret,
+        &zone.vault,
+        &zone.aws_keyring_entry,
+    ) {
+        (Some(web_identity), _, _, _) => {
+            info!(
+                "Using web identity role {} for zone {}",
+                web_identity.role_arn, zone.zone_name
+            );
+            let provider_config =
+                ProviderConfig::without_region().with_region(config.region().cloned());
+            let provider_config = match config.http_client() {
+                Some(http_client) => provider_config.with_http_client(http_client),
+                None => provider_config,
+            };
+            let provider = WebIdentityTokenCredentialsProvider::builder()
+                .configure(&provider_config)
+                .static_configuration(StaticConfiguration {
+                    web_identity_token_file: web_identity.token_file.clone(),
+                    role_arn: web_identity.role_arn.clone(),
+                    session_name: session_name.to_owned(),
+                })
+                .build();
+            Some(SharedCredentialsProvider::new(provider))
+        }
+        (None, Some(secret_id), _, _) => {
+            info!(
+                "Using AWS credentials from secret {secret_id} for zone {}",
+                zone.zone_name
+            );
+            let provider = SecretsManagerCredentials::new(&config, secret_id);
+            Some(SharedCredentialsProvider::new(provider))
+        }
+        (None, None, Some(vault), _) => {
+            info!(
+                "Using AWS credentials from Vault at {} for zone {}",
+                vault.path, zone.zone_name
+            );
+            let provider = VaultCredentials::new(vault.clone());
+            Some(SharedCredentialsProvider::new(provider))
+        }
+        #[cfg(feature = "keyring")]
+        (None, None, None, Some(entry)) => {
+            info!(
+                "Using AWS credentials from keyring entry {entry} for zone {}",
+                zone.zone_name
+            );
+            Some(SharedCredentialsProvider::new(KeyringCredentials::new(
+                entry,
+            )))
+        }
+        (None, None, None, _) => None,
+    };
+    let config = match provider {
+        Some(provider) => config.into_builder().credentials_provider(provider).build(),
+        None => config,
+    };
+    let config = match &zone.assume_role_arn {
+        Some(role_arn) => {
+            info!("Assuming role {role_arn} for zone {}", zone.zone_name);
+            match &zone.mfa_serial {
+                Some(serial) => {
+                    let provider = MfaAssumeRoleProvider::new(
+                        &config,
+                        role_arn,
+                        session_name,
+                        zone.external_id.as_deref(),
+                        serial,
+                        zone.mfa_token_command.as_deref(),
+                    );
+                    let cache = IdentityCache::lazy()
+                        .load_timeout(mfa::PROMPT_TIMEOUT)
+                        .build();
+                    config
+                        .into_builder()
+                        .credentials_provider(SharedCredentialsProvider::new(provider))
+                        .identity_cache(cache)
+                        .build()
+                }
+                None => {
+                    let provider =
+                        assume_role(&config, role_arn, session_name, zone.external_id.as_deref())
+                            .await;
+                    config.into_builder().credentials_provider(provider).build()
+                }
+            }
+        }
+        None => config,
+    };
+    let config = match &zone.assume_role {
+        Some(role) => {
+            info!(
+                "Assuming role {} for zone {}",
+                role.role_arn, zone.zone_name
+            );
+            let session_name = role.session_name.as_deref().unwrap_or(session_name);
+            let provider = assume_role(
+                &config,
+                &role.role_arn,
+                session_name,
+                role.external_id.as_deref(),
+            )
+            .await;
+            config.into_builder().credentials_provider(provider).build()
+        }
+        None => config,
+    };
+    config
+}
+
+/// Attempts at each AWS call while it fails transiently, e.g. while Route53 throttles
+/// changes past its limit of five requests per second or answers
+/// `PriorRequestNotComplete`.
+const AWS_MAX_ATTEMPTS: u32 = 6;
+/// Longest wait between two attempts at an AWS call.
+const AWS_MAX_BACKOFF: Duration = Duration::from_secs(20);
+
+/// Loads AWS settings over rustls, trusting the webpki roots or, with `cert_pins`, only
+/// the pinned certificates. Throttled and otherwise transient failures are retried with
+/// the SDK's standard jittered backoff.
+pub fn aws_config_loader(cert_pins: &[CertPin]) -> ConfigLoader {
+    let builder = hyper_rustls::HttpsConnectorBuilder::new();
+    let builder = if cert_pins.is_empty() {
+        builder.with_webpki_roots()
+    } else {
+        builder.with_tls_config(cert_pin::pinned_tls_config(cert_pins))
+    };
+    let rustls_connector = builder.https_only().enable_http1().enable_http2().build();
+    let http_client = HyperClientBuilder::new().build(rustls_connector);
+    let retry_config = RetryConfig::standard()
+        .with_max_attempts(AWS_MAX_ATTEMPTS)
+        .with_max_backoff(AWS_MAX_BACKOFF);
+    aws_config::defaults(BehaviorVersion::latest())
+        .http_client(http_client)
+        .retry_config(retry_config)
+}
+
+/// Credentials for `role_arn`, assumed with the credentials of `config`.
+async fn assume_role(
+    config: &SdkConfig,
+    role_arn: &str,
+    session_name: &str,
+    external_id: Option<&str>,
+) -> SharedCredentialsProvider {
+    let provider = AssumeRoleProvider::builder(role_arn)
+        .session_name(session_name)
+        .configure(config);
+    let provider = match external_id {
+        Some(external_id) => provider.external_id(external_id),
+        None => provider,
+    };
+    SharedCredentialsProvider::new(provider.build().await)
+}
+
+/// Resolves the zone's hosted zone and returns a provider writing to it.
+async fn route53_provider(
+    zone: &HostedZoneConfig,
+    ctx: &UpdateContext,
+) -> Result<Route53Provider, Error> {
+    let client = ctx.clients.route53(zone).await;
+    let max_age = zone.refresh_zone_id_every_seconds.map(Duration::from_secs);
+    let hosted_zone = match ctx.clients.hosted_zone_id(max_age) {
+        Some(id) => id,
+        None => {
+            let id = zones::resolve_hosted_zone(&client, zone, ctx.daemon, &ctx.shutdown).await?;
+            info!("Found hosted zone id {id}");
+            ctx.clients.cache_hosted_zone_id(id.clone());
+            id
+        }
+    };
+    let comment = zone.change_comment.as_ref().map(|template| {
+        let state = ctx.state.lock().unwrap();
+        template.render(&TemplateValues::new(state.last_ipv4, state.last_ipv6))
+    });
+    Ok(Route53Provider::new(client, hosted_zone)
+        .with_geolocation(zone.geolocation.clone())
+        .with_comment(comment)
+        .with_sync_tracker(ctx.sync_tracker.clone())
+        .with_wait_for_sync(zone.wait_for_sync_seconds.map(Duration::from_secs)))
+}
+
+/// The backend that holds the authoritative copy of the record: a zone file, an
+/// RFC 2136 server or Cloudflare if one is configured, otherwise Route53.
+async fn primary_provider(
+    zone: &HostedZoneConfig,
+    ctx: &UpdateContext,
+) -> Result<Box<dyn DnsProvider>, Error> {
+    if let Some(path) = &zone.zone_file {
+        return Ok(Box::new(ZoneFileProvider::new(
+            path.clone(),
+            &zone.zone_name,
+        )));
+    }
+    if let Some(rfc2136) = &zone.rfc2136 {
+        return Ok(Box::new(Rfc2136Provider::new(
+            rfc2136.clone(),
+            &zone.zone_name,
+        )));
+    }
+    if let Some(cloudflare) = zone.cloudflare.as_ref().filter(|c| c.primary) {
+        return Ok(Box::new(CloudflareProvider::new(
+            cloudflare.clone(),
+            &zone.zone_name,
+        )));
+    }
+    Ok(Box::new(route53_provider(zone, ctx).await?))
+}
+
+/// What one successful update did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpdateOutcome {
+    /// At least one record was written.
+    Written,
+    /// Every record already had its value, or it was a dry run.
+    Unchanged,
+    /// The pause file exists, so nothing was written and nothing counts as a success.
+    Paused,
+    /// A record to write was written too recently, so the update is retried after
+    /// the wait.
+    Deferred(Duration),
+}
+
+/// Detects the current addresses and writes every record of the zone that changed.
+pub async fn update_hosted_zone(
+    zone: HostedZoneConfig,
+    ctx: &UpdateContext,
+    explanation: &mut Explanation,
+) -> Result<UpdateOutcome, Error> {
+    info!("Updating hosted zone {:?}", &zone);
+    let pause_file = zone.pause_file.as_ref().filter(|path| path.exists());
+
+    let fqdn = zone.fqdn()?;
+    let (previous_ipv4, previous_ipv6) = {
+        let state = ctx.state.lock().unwrap();
+        (state.last_ipv4, state.last_ipv6)
+    };
+    let (addresses, failures) = detect_publishable(&zone, ctx).await?;
+    for failure in &failures {
+        explanation.note(format!("detection failed for {failure}"));
+    }
+    let mut published: Vec<(Option<IpAddr>, IpAddr)> = addresses
+        .into_iter()
+        .map(|ip| match ip {
+            IpAddr::V4(_) => (previous_ipv4, ip),
+            IpAddr::V6(_) => (previous_ipv6, ip),
+        })
+        .collect();
+    for (previous, ip) in &published {
+        let cached = ctx
+            .state
+            .lock()
+            .unwrap()
+            .published(&fqdn, RecordType::for_ip(ip))
+            .cloned();
+        explanation.note(format!(
+            "detected {ip}, previously detected {previous:?}, known published values {cached:?}"
+        ));
+    }
+    if let Some(check) = &zone.reachability_check {
+        let mut reachable = Vec::with_capacity(published.len());
+        for (previous, ip) in published {
+            match check.check(ip).await {
+                Ok(()) => {
+                    explanation.note(format!("{ip} is reachable via {check}"));
+                    reachable.push((previous, ip))
+                }
+                Err(e) => {
+                    warn!("{ip} is not reachable via {check}, keeping the old value: {e:?}");
+                    explanation.note(format!("{ip} is not reachable via {check}: {e}"));
+                }
+            }
+        }
+        published = reachable;
+    }
+    if zone.multi_value {
+        // A multi-value set is written whole, so it is only skipped if nothing changed.
+        let state = ctx.state.lock().unwrap();
+        if address_sets(&published)
+            .iter()
+            .all(|ips| state.is_published(&fqdn, ips))
+        {
+            explanation.note("every address of the multi-value set is already published");
+            published.clear();
+        }
+    } else {
+        published.retain(|(_, ip)| {
+            let unchanged = ctx
+                .state
+                .lock()
+                .unwrap()
+                .is_published(&fqdn, &BTreeSet::from([*ip]));
+            if unchanged {
+                info!("{fqdn} already serves {ip}, skipping write.");
+                explanation.note(format!("{ip} is already published"));
+            }
+            !unchanged
+        });
+    }
+
+    let names = zone.record_names()?;
+    if zone.compare_dns && !published.is_empty() {
+        skip_resolved(&zone, &names, &mut published, ctx, explanation).await?;
+    }
+
+    let ttl = match &zone.adaptive_ttl {
+        Some(adaptive_ttl) => {
+            let changing = published
+                .iter()
+                .any(|(old, new)| matches!(old, Some(old) if old != new));
+            let stable_for = if changing {
+                Some(Duration::ZERO)
+            } else {
+                let last_ip_change = ctx.state.lock().unwrap().last_ip_change;
+                last_ip_change.map(|changed_at| {
+                    Duration::from_secs(state::unix_now().saturating_sub(changed_at))
+                })
+            };
+            let ttl = adaptive_ttl.ttl(stable_for);
+            explanation.note(format!("adaptive TTL {ttl}s, stable for {stable_for:?}"));
+            ttl
+        }
+        None => zone.ttl_seconds,
+    };
+    let mut records: Vec<RecordSpec> = published
+        .iter()
+        .flat_map(|(_, ip)| names.iter().map(|name| RecordSpec::for_ip(name, *ip, ttl)))
+        .collect();
+    if let (Some(IpAddr::V6(ipv6)), Some(prefix_length)) = (
+        published.iter().map(|(_, ip)| *ip).find(IpAddr::is_ipv6),
+        zone.ipv6_prefix_length,
+    ) {
+        // Written in the same change as the managed records, so every host moves to the
+        // new prefix at once.
+        for (host, suffix) in &zone.lan_hosts {
+            let ip = ipv6_prefix::with_suffix(ipv6, prefix_length, *suffix);
+            records.push(RecordSpec::for_ip(
+                &zone.qualify(host)?,
+                IpAddr::V6(ip),
+                ttl,
+            ));
+        }
+    }
+    let template_values = {
+        let state = ctx.state.lock().unwrap();
+        TemplateValues::new(state.last_ipv4, state.last_ipv6)
+    };
+    if let (Some(template), false) = (&zone.txt_template, records.is_empty()) {
+        records.push(RecordSpec::txt(
+            &fqdn,
+            &template.render(&template_values),
+            ttl,
+        ));
+    }
+    if let Some(path) = pause_file {
+        info!("{} exists, skipping write of {records:?}", path.display());
+        explanation.decide(format!("skip: pause file {} exists", path.display()));
+        return Ok(UpdateOutcome::Paused);
+    }
+    let min_interval = Duration::from_secs(zone.min_change_interval_seconds);
+    let wait = {
+        let state = ctx.state.lock().unwrap();
+        records
+            .iter()
+            .map(|record| state.change_limiter.remaining(&record.name, min_interval))
+            .max()
+            .unwrap_or_default()
+    };
+    if !wait.is_zero() && !ctx.dry_run {
+        info!("Last write to {fqdn} was too recent, deferring update by {wait:?}");
+        explanation.decide(format!(
+            "defer by {wait:?} to respect the minimum change interval of {}s",
+            zone.min_change_interval_seconds
+        ));
+        return Ok(UpdateOutcome::Deferred(wait));
+    }
+    let outcome = if records.is_empty() {
+        info!("No change for {fqdn}.");
+        UpdateOutcome::Unchanged
+    } else {
+        let provider = ctx.count_failure(primary_provider(&zone, ctx).await)?;
+        let zone_id = ctx.count_failure(provider.find_zone().await)?;
+        debug!("Writing to zone {zone_id} with {}.", provider.name());
+        if zone.preserve_other_types {
+            ctx.count_failure(provider.check_other_types(&records).await)?;
+        }
+        if zone.preserve_ttl {
+            ctx.count_failure(provider.preserve_ttls(&mut records).await)?;
+        }
+        let secondaries = secondary_providers(&zone, &ctx.clients);
+        if ctx.dry_run {
+            let mirrors: Vec<&dyn DnsProvider> = secondaries.iter().map(|p| p.as_ref()).collect();
+            let planned: Vec<String> = plan::plan_records(provider.as_ref(), &mirrors, &records)
+                .await?
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+            let mut state = ctx.state.lock().unwrap();
+            if state.dry_run_plan == planned {
+                info!("Dry run plan for {fqdn} is unchanged.");
+            } else {
+                for line in &planned {
+                    println!("{line}");
+                }
+                state.dry_run_plan = planned;
+            }
+            explanation.decide("skip: dry run");
+            return Ok(UpdateOutcome::Unchanged);
+        }
+        let retry = RetryPolicy::for_zone(&zone, ctx);
+        let what = format!("Updating {fqdn}");
+        // Every backend is written at once, so a slow one doesn't hold up the others.
+        let (primary, secondaries) = tokio::join!(
+            publish(provider.as_ref(), &records, &retry, &what, ctx),
+            join_all(secondaries.iter().map(|provider| {
+                let what = format!("Updating {fqdn} at {}", provider.name());
+                let failed = format!("Failed to update {} for {fqdn}", provider.name());
+                let (records, retry) = (&records, &retry);
+                async move {
+                    publish(provider.as_ref(), records, retry, &what, ctx)
+                        .await
+                        .context(failed)
+                }
+            })),
+        );
+        primary?;
+        secondaries
+            .into_iter()
+            .collect::<Result<Vec<()>, Error>>()?;
+        {
+            let mut state = ctx.state.lock().unwrap();
+            state.record_change();
+            for record in &records {
+                state.change_limiter.record_write(&record.name);
+            }
+            for ips in address_sets(&published) {
+                state.record_published(&fqdn, ips);
+            }
+        }
+        record_ip_changes(ctx, &zone, &fqdn, &published);
+        let values: Vec<&str> = records.iter().map(|record| record.value.as_str()).collect();
+        explanation.decide(format!("write {}", values.join(", ")));
+        UpdateOutcome::Written
+    };
+    info!("Finished updating hosted zone {zone:?}");
+
+    if !failures.is_empty() {
+        return Err(anyhow!(
+            "Published what was detected, but detection failed for {}",
+            failures.join("; ")
+        ));
+    }
+    Ok(outcome)
+}
+
+/// Fills the published cache with the values served for the record, so a
+/// restart doesn't rewrite values that are already live.
+async fn seed_published(zone: &HostedZoneConfig, ctx: &UpdateContext) -> Result<(), Error> {
+    let provider = primary_provider(zone, ctx).await?;
+    let fqdn = zone.fqdn()?;
+    let record_types = [(zone.ipv4, RecordType::A), (zone.ipv6, RecordType::Aaaa)];
+    for (_, record_type) in record_types.into_iter().filter(|(enabled, _)| *enabled) {
+        let Some(values) = provider.current_values(&fqdn, record_type).await? else {
+            continue;
+        };
+        if let Ok(ips) = values.iter().map(|value| value.parse()).collect() {
+            info!("{fqdn} {record_type} currently serves {ips:?}");
+            ctx.state.lock().unwrap().record_published(&fqdn, ips);
+        }
+    }
+    Ok(())
+}
+
+/// Drops the addresses of every family that all `names` already resolve to, so the
+/// provider is only written when DNS disagrees with the detected values. Lookup
+/// failures count as a disagreement.
+async fn skip_resolved(
+    zone: &HostedZoneConfig,
+    names: &[String],
+    published: &mut Vec<(Option<IpAddr>, IpAddr)>,
+    ctx: &UpdateContext,
+    explanation: &mut Explanation,
+) -> Result<(), Error> {
+    let resolver = zone
+        .compare_dns_resolver
+        .as_deref()
+        .map(DohResolver::new)
+        .transpose()?;
+    let mut resolved = Vec::with_capacity(names.len());
+    for name in names {
+        let answers = match &resolver {
+            Some(resolver) => resolver.resolve_all(name).await,
+            None => tokio::net::lookup_host((name.as_str(), 0))
+                .await
+                .map(|addrs| addrs.map(|addr| addr.ip()).collect())
+                .map_err(Error::from),
+        };
+        match answers {
+            Ok(answers) => resolved.push(answers),
+            Err(e) => {
+                warn!("Failed to resolve {name}, writing without comparing: {e:?}");
+                return Ok(());
+            }
+        }
+    }
+    let family = |ips: &mut dyn Iterator<Item = IpAddr>, v4: bool| {
+        let mut ips: Vec<IpAddr> = ips.filter(|ip| ip.is_ipv4() == v4).collect();
+        ips.sort();
+        ips.dedup();
+        ips
+    };
+    for v4 in [true, false] {
+        let detected = family(&mut published.iter().map(|(_, ip)| *ip), v4);
+        if detected.is_empty()
+            || !resolved
+                .iter()
+                .all(|answers| family(&mut answers.iter().copied(), v4) == detected)
+        {
+            continue;
+        }
+        for ip in &detected {
+            info!(
+                "DNS already answers {ip} for {}, skipping write.",
+                names.join(", ")
+            );
+            explanation.note(format!("DNS already answers {ip}"));
+        }
+        let ips = detected.into_iter().collect();
+        ctx.state.lock().unwrap().record_published(&names[0], ips);
+        published.retain(|(_, ip)| ip.is_ipv4() != v4);
+    }
+    Ok(())
+}
+
+/// Detects the enabled address families and returns the ones that may be published,
+/// along with why detection failed for a family when the other one succeeded.
+async fn detect_publishable(
+    zone: &HostedZoneConfig,
+    ctx: &UpdateContext,
+) -> Result<(Vec<IpAddr>, Vec<String>), Error> {
+    let retry = RetryPolicy::for_zone(zone, ctx);
+    if zone.multi_value {
+        let mut addresses = retry
+            .run("Detection", || {
+                detect_per_source(zone, &ctx.state, &ctx.clients)
+            })
+            .await?;
+        for ip in &addresses {
+            ip_validation::check_publishable(*ip, zone.allows_private_addresses(), zone.allow_ula)?;
+        }
+        let mut allowed = Vec::with_capacity(addresses.len());
+        for ip in addresses.drain(..) {
+            if cgnat::allows(zone, ctx, ip).await? {
+                allowed.push(ip);
+            }
+        }
+        return Ok((allowed, Vec::new()));
+    }
+
+    let mut addresses = Vec::with_capacity(2);
+    let detected = retry
+        .run("Detection", || async {
+            let mut detected = Detected::default();
+            detect_addresses(zone, &ctx.state, &ctx.clients, &mut detected).await?;
+            Ok(detected)
+        })
+        .await?;
+    // Logged together so a NAT'd IPv4 and the host's own IPv6 can be compared at a glance.
+    info!("Detected addresses: {detected}");
+
+    if let Some(ipv4) = detected.ipv4 {
+        ip_validation::check_publishable(ipv4, zone.allows_private_addresses(), zone.allow_ula)?;
+        if cgnat::allows(zone, ctx, ipv4).await? {
+            addresses.push(ipv4);
+        }
+    }
+    if let Some(ipv6) = detected.ipv6.map(IpAddr::V6) {
+        ip_validation::check_publishable(ipv6, zone.allows_private_addresses(), zone.allow_ula)?;
+        addresses.push(ipv6);
+    }
+    Ok((addresses, detected.failures))
+}
+
+/// The addresses of `published` grouped into one set per address family.
+fn address_sets(published: &[(Option<IpAddr>, IpAddr)]) -> Vec<BTreeSet<IpAddr>> {
+    [true, false]
+        .into_iter()
+        .map(|v4| {
+            published
+                .iter()
+                .map(|(_, ip)| *ip)
+                .filter(|ip| ip.is_ipv4() == v4)
+                .collect::<BTreeSet<_>>()
+        })
+        .filter(|ips| !ips.is_empty())
+        .collect()
+}
+
+/// Backends that receive the same updates as the primary backend.
+pub(crate) fn secondary_providers(
+    zone: &HostedZoneConfig,
+    clients: &ZoneClients,
+) -> Vec<Arc<dyn DnsProvider>> {
+    let mut providers: Vec<Arc<dyn DnsProvider>> = Vec::new();
+    if let Some(cloudflare) = zone.cloudflare.as_ref().filter(|c| !c.primary) {
+        providers.push(clients.cloudflare(cloudflare, &zone.zone_name));
+    }
+    providers
+}
+
+/// Records each published address that differs from the previously known one. The
+/// first address published for a family is logged to the history, but isn't a change.
+fn record_ip_changes(
+    ctx: &UpdateContext,
+    zone: &HostedZoneConfig,
+    fqdn: &str,
+    published: &[(Option<IpAddr>, IpAddr)],
+) {
+    let zone_name = canonical_name(&zone.zone_name);
+    for (old_ip, new_ip) in published {
+        if *old_ip == Some(*new_ip) {
+            continue;
+        }
+        if old_ip.is_some() {
+            ctx.state.lock().unwrap().record_ip_change();
+            if zone.desktop_notify {
+                ctx.notifier
+                    .notify(ChangeNotification::new(fqdn, *old_ip, *new_ip));
+            }
+            if let Some(statsd) = &ctx.statsd {
+                statsd.count(
+                    "ip_changed",
+                    1,
+                    &[("zone", zone_name.as_str()), ("record", fqdn)],
+                );
+            }
+        }
+        let Some(history) = &ctx.history else {
+            continue;
+        };
+        let entry = HistoryEntry {
+            timestamp: state::unix_now(),
+            old_ip: *old_ip,
+            new_ip: *new_ip,
+            record: fqdn.to_owned(),
+            zone: zone_name.clone(),
+        };
+        if let Err(e) = history.append(&entry) {
+            warn!("Failed to record history entry {entry:?}: {e:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, http_server};
+
+    /// A zone kept in a zone file, detecting `ip` through a local IP provider.
+    async fn zone_file_zone(name: &str, ip: &'static str, extra: &str) -> HostedZoneConfig {
+        let (url, _) = http_server(move |_| (200, ip.to_owned())).await;
+        let path = test_util::zone_file(name, "home 300 IN A 8.8.4.4\n");
+        test_util::zone(&format!(
+            "zone_file = {:?}\nip_provider = [\"{url}\"]\n{extra}",
+            path.display().to_string()
+        ))
+    }
+
+    #[tokio::test]
+    async fn paused_update_writes_nothing() {
+        let pause_file = test_util::temp_path("pause");
+        std::fs::write(&pause_file, "").unwrap();
+        let zone = zone_file_zone(
+            "paused.zone",
+            "8.8.8.8",
+            &format!("pause_file = {:?}", pause_file.display().to_string()),
+        )
+        .await;
+        let before = std::fs::read_to_string(zone.zone_file.as_ref().unwrap()).unwrap();
+        let ctx = test_util::context();
+
+        let mut explanation = Explanation::new("home.example.com");
+        let outcome = update_hosted_zone(zone.clone(), &ctx, &mut explanation)
+            .await
+            .unwrap();
+        assert_eq!(outcome, UpdateOutcome::Paused);
+        let after = std::fs::read_to_string(zone.zone_file.as_ref().unwrap()).unwrap();
+        assert_eq!(before, after);
+
+        // Nor does it count as a successful update.
+        daemon_update_zone(zone, false, ctx.clone(), CancellationToken::new())
+            .await
+            .unwrap();
+        let state = ctx.state.lock().unwrap();
+        assert_eq!(state.last_success, None);
+        assert_eq!(state.change_count, 0);
+    }
+
+    #[tokio::test]
+    async fn defers_only_a_pending_change_to_a_recent_record() {
+        let interval = "min_change_interval_seconds = 600";
+        let zone = zone_file_zone("limited.zone", "8.8.8.8", interval).await;
+        let ctx = test_util::context();
+        let update = |zone: &HostedZoneConfig| {
+            let (zone, ctx) = (zone.clone(), &ctx);
+            async move {
+                let mut explanation = Explanation::new("home.example.com");
+                update_hosted_zone(zone, ctx, &mut explanation)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        assert_eq!(update(&zone).await, UpdateOutcome::Written);
+        // Nothing to write, so there is nothing to defer.
+        assert_eq!(update(&zone).await, UpdateOutcome::Unchanged);
+
+        let moved = zone_file_zone("limited-moved.zone", "8.8.4.4", interval).await;
+        match update(&moved).await {
+            UpdateOutcome::Deferred(wait) => {
+                assert!(wait > Duration::from_secs(590) && wait <= Duration::from_secs(600))
+            }
+            outcome => panic!("{outcome:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn explains_each_decision() {
+        let zone = zone_file_zone("explain.zone", "8.8.8.8", "").await;
+        let ctx = test_util::context();
+        let explain = |zone: &HostedZoneConfig| {
+            let (zone, ctx) = (zone.clone(), &ctx);
+            async move {
+                let mut explanation = Explanation::new("home.example.com");
+                let result = update_hosted_zone(zone, ctx, &mut explanation).await;
+                explanation.message(&result)
+            }
+        };
+
+        let written = explain(&zone).await;
+        assert!(
+            written.starts_with("Decision for home.example.com: write 8.8.8.8\n"),
+            "{written}"
+        );
+        assert!(written.contains("  - detected 8.8.8.8, previously detected None"));
+
+        let unchanged = explain(&zone).await;
+        assert!(
+            unchanged.starts_with("Decision for home.example.com: skip: nothing to publish\n"),
+            "{unchanged}"
+        );
+        assert!(unchanged.contains("  - 8.8.8.8 is already published"));
+
+        let pause_file = test_util::temp_path("explain-pause");
+        std::fs::write(&pause_file, "").unwrap();
+        let paused = HostedZoneConfig {
+            pause_file: Some(pause_file.clone()),
+            ..zone.clone()
+        };
+        ctx.state.lock().unwrap().published.clear();
+        assert_eq!(
+            explain(&paused).await.lines().next().unwrap(),
+            format!(
+                "Decision for home.example.com: skip: pause file {} exists",
+                pause_file.display()
+            )
+        );
+
+        let failing = HostedZoneConfig {
+            ip_provider: vec!["http://127.0.0.1:1".to_owned()],
+            ..zone
+        };
+        assert!(explain(&failing)
+            .await
+            .starts_with("Decision for home.example.com: error: "));
+    }
+
+    #[tokio::test]
+    async fn dry_run_remembers_its_plan() {
+        let zone = zone_file_zone("dry-run.zone", "8.8.8.8", "").await;
+        let before = std::fs::read_to_string(zone.zone_file.as_ref().unwrap()).unwrap();
+        let ctx = UpdateContext {
+            dry_run: true,
+            ..test_util::context()
+        };
+
+        for _ in 0..2 {
+            let mut explanation = Explanation::new("home.example.com");
+            let outcome = update_hosted_zone(zone.clone(), &ctx, &mut explanation)
+                .await
+                .unwrap();
+            assert_eq!(outcome, UpdateOutcome::Unchanged);
+        }
+        let after = std::fs::read_to_string(zone.zone_file.as_ref().unwrap()).unwrap();
+        assert_eq!(before, after);
+        assert_eq!(
+            ctx.state.lock().unwrap().dry_run_plan,
+            ["~ change   home.example.com A 8.8.4.4 -> 8.8.8.8"]
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_every_uplinks_address_to_one_record() {
+        let answered = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let (url, _) = http_server(move |_| {
+            let n = answered.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            (200, ["8.8.8.8", "8.8.4.4"][n % 2].to_owned())
+        })
+        .await;
+        let path = test_util::zone_file("multi-value.zone", "");
+        let zone = test_util::zone(&format!(
+            "zone_file = {:?}\nip_provider = [\"{url}\"]\nmulti_value = true\n\
+             source_addresses = [\"127.0.0.1\", \"127.0.0.2\"]",
+            path.display().to_string()
+        ));
+        let ctx = test_util::context();
+
+        let mut explanation = Explanation::new("home.example.com");
+        let outcome = update_hosted_zone(zone, &ctx, &mut explanation)
+            .await
+            .unwrap();
+
+        assert_eq!(outcome, UpdateOutcome::Written);
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(
+            text.contains("8.8.8.8") && text.contains("8.8.4.4"),
+            "{text}"
+        );
+        let both = BTreeSet::from(["8.8.8.8".parse().unwrap(), "8.8.4.4".parse().unwrap()]);
+        assert!(ctx
+            .state
+            .lock()
+            .unwrap()
+            .is_published("home.example.com", &both));
+    }
+
+    #[tokio::test]
+    async fn seeds_every_published_value() {
+        let path = test_util::zone_file(
+            "seed.zone",
+            "home 300 IN A 8.8.8.8\nhome 300 IN A 8.8.4.4\n",
+        );
+        let (url, _) = http_server(|_| (200, "8.8.8.8".to_owned())).await;
+        let zone = test_util::zone(&format!(
+            "zone_file = {:?}\nip_provider = [\"{url}\"]\nseed_cache_from_dns = true",
+            path.display().to_string()
+        ));
+        let ctx = test_util::context();
+        seed_published(&zone, &ctx).await.unwrap();
+        let both = BTreeSet::from(["8.8.8.8".parse().unwrap(), "8.8.4.4".parse().unwrap()]);
+        assert!(ctx
+            .state
+            .lock()
+            .unwrap()
+            .is_published("home.example.com", &both));
+
+        // Only 8.8.8.8 is detected, so the second value has to go.
+        let mut explanation = Explanation::new("home.example.com");
+        let outcome = update_hosted_zone(zone, &ctx, &mut explanation)
+            .await
+            .unwrap();
+        assert_eq!(outcome, UpdateOutcome::Written);
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(!text.contains("8.8.4.4"), "{text}");
+        let only = BTreeSet::from(["8.8.8.8".parse().unwrap()]);
+        assert!(ctx
+            .state
+            .lock()
+            .unwrap()
+            .is_published("home.example.com", &only));
+    }
+
+    #[tokio::test]
+    async fn flush_delivers_every_side_effect_of_a_change() {
+        let metrics = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let history_path = test_util::temp_path("flush-history.jsonl");
+        let ctx = UpdateContext {
+            history: Some(History::new(history_path.clone())),
+            statsd: Some(Arc::new(
+                StatsdClient::new(&metrics.local_addr().unwrap().to_string()).unwrap(),
+            )),
+            ..test_util::context()
+        };
+        let zone = test_util::zone("desktop_notify = true");
+        let change = ("8.8.4.4".parse().ok(), "8.8.8.8".parse().unwrap());
+        record_ip_changes(&ctx, &zone, "home.example.com", &[change]);
+        ctx.flush().await;
+
+        let mut buf = [0; 512];
+        let n = metrics.recv(&mut buf).unwrap();
+        let metric = std::str::from_utf8(&buf[..n]).unwrap();
+        assert!(metric.starts_with("route53_dd.ip_changed:1|c"), "{metric}");
+        let entries: Vec<HistoryEntry> = History::new(history_path)
+            .entries()
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].old_ip, change.0);
+    }
+
+    #[test]
+    fn preserving_other_records_needs_route53() {
+        assert!(test_util::zone("preserve_ttl = true").validate().is_ok());
+        for backend in [
+            "zone_file = \"/tmp/example.zone\"",
+            "cloudflare = { api_token = \"token\", primary = true }",
+        ] {
+            for option in ["preserve_ttl", "preserve_other_types"] {
+                let zone = test_util::zone(&format!("{backend}\n{option} = true"));
+                assert!(zone.validate().is_err(), "{backend} {option}");
+            }
+        }
+    }
+
+    #[test]
+    fn every_zone_needs_a_usable_ip_source() {
+        assert!(test_util::zone("").validate().is_ok());
+        assert!(test_util::zone("ip_provider = []").validate().is_err());
+        assert!(
+            test_util::zone("ip_provider = []\nipv4_url = \"https://ipv4.example\"")
+                .validate()
+                .is_ok()
+        );
+        let err = test_util::zone("primary_ip_source = \"lan\"")
+            .validate()
+            .unwrap_err();
+        assert!(err.to_string().contains("--interface"), "{err}");
+        assert!(
+            test_util::zone("primary_ip_source = \"lan\"\ninterface = \"eth0\"")
+                .validate()
+                .is_ok()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restarts_a_panicked_loop() {
+        let state = state::DaemonState::shared();
+        let runs = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let run = || {
+            let (state, runs) = (state.clone(), runs.clone());
+            async move {
+                if runs.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                    let _state = state.lock().unwrap();
+                    panic!("update loop bug");
+                }
+                Ok(())
+            }
+        };
+
+        supervise(
+            "home.example.com",
+            "example.com",
+            &state,
+            &CancellationToken::new(),
+            run,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(runs.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert!(!state.is_poisoned());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_the_startup_delay_before_the_first_update() {
+        let (url, requests) = http_server(|_| (200, "8.8.8.8".to_owned())).await;
+        let zone = test_util::zone(&format!(
+            "ip_provider = [\"{url}\"]\nstartup_delay_seconds = 30"
+        ));
+        assert_eq!(startup_delay(&zone), Duration::from_secs(30));
+        let ctx = test_util::context();
+        let shutdown = ctx.shutdown.clone();
+        let daemon = tokio::spawn(daemon_update_zone(zone, true, ctx, shutdown.clone()));
+
+        time::sleep(Duration::from_secs(29)).await;
+        shutdown.cancel();
+
+        daemon.await.unwrap().unwrap();
+        assert!(requests.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn startup_jitter_stays_within_its_window() {
+        let zone = test_util::zone("startup_delay_seconds = 10\nstartup_jitter_seconds = 5");
+        let delays: Vec<Duration> = (0..200).map(|_| startup_delay(&zone)).collect();
+
+        assert!(delays
+            .iter()
+            .all(|delay| (Duration::from_secs(10)..=Duration::from_secs(15)).contains(delay)));
+        // Spread out, not every instance at the same moment.
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+    }
+
+    #[tokio::test]
+    async fn the_daemon_exits_after_its_max_runtime() {
+        let zone = zone_file_zone("max-runtime.zone", "8.8.8.8", "").await;
+        let ctx = UpdateContext {
+            daemon: true,
+            ..test_util::context()
+        };
+        let shutdown = ctx.shutdown.clone();
+        shut_down_after(Duration::from_millis(200), shutdown.clone());
+
+        let result = time::timeout(
+            Duration::from_secs(5),
+            daemon_update_zone(zone, true, ctx.clone(), shutdown.clone()),
+        )
+        .await
+        .expect("the daemon outlived its max runtime");
+
+        assert!(result.is_ok());
+        assert!(shutdown.is_cancelled());
+        assert_eq!(ctx.state.lock().unwrap().change_count, 1);
+    }
+
+    #[test]
+    fn takes_fully_qualified_record_names_verbatim() {
+        let mut zone = test_util::zone("record_name_is_fqdn = true");
+        zone.record_name = vec!["Home.Example.com.".to_owned(), "example.com".to_owned()];
+        assert_eq!(
+            zone.record_names().unwrap(),
+            ["home.example.com", "example.com"]
+        );
+
+        zone.record_name = vec!["home.example.org".to_owned()];
+        let err = zone.fqdn().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Record name home.example.org is not within zone example.com."
+        );
+        // A name only ending in the zone's name isn't within the zone.
+        zone.record_name = vec!["homeexample.com".to_owned()];
+        assert!(zone.fqdn().is_err());
+
+        zone.record_name_is_fqdn = false;
+        zone.record_name = vec!["home.example.com".to_owned()];
+        assert_eq!(zone.fqdn().unwrap(), "home.example.com.example.com");
+    }
+
+    #[test]
+    fn role_session_name_goes_with_an_assumed_role() {
+        assert!(test_util::zone("role_session_name = \"ddns\"")
+            .validate()
+            .is_err());
+        let zone = test_util::zone(
+            "role_session_name = \"ddns\"\n\
+             [assume_role]\nrole_arn = \"arn:aws:iam::123456789012:role/dns\"",
+        );
+        assert!(zone.validate().is_ok());
+    }
+
+    /// Answers `sts:AssumeRole` with an access key named after the role, and Route53 with
+    /// a hosted zone.
+    fn assuming_aws(request: &test_util::Request) -> (u16, String) {
+        if request.method != "POST" {
+            return (
+                200,
+                "<GetHostedZoneResponse xmlns=\"https://route53.amazonaws.com/doc/2013-04-01/\">\
+                 <HostedZone><Id>/hostedzone/Z1</Id><Name>example.com.</Name>\
+                 <CallerReference>x</CallerReference></HostedZone></GetHostedZoneResponse>"
+                    .to_owned(),
+            );
+        }
+        let key = if request.body.contains("role%2Fhome") {
+            "ASIAHOME"
+        } else {
+            "ASIAWWW"
+        };
+        let body = format!(
+            "<AssumeRoleResponse xmlns=\"https://sts.amazonaws.com/doc/2011-06-15/\"><AssumeRoleResult>\
+             <Credentials><AccessKeyId>{key}</AccessKeyId><SecretAccessKey>s</SecretAccessKey>\
+             <SessionToken>t</SessionToken><Expiration>2100-01-01T00:00:00Z</Expiration></Credentials>\
+             <AssumedRoleUser><Arn>arn:aws:sts::123456789012:assumed-role/ddns/route53_dd</Arn><AssumedRoleId>id</AssumedRoleId></AssumedRoleUser>\
+             </AssumeRoleResult></AssumeRoleResponse>"
+        );
+        (200, body)
+    }
+
+    #[tokio::test]
+    async fn each_zone_assumes_its_own_role() {
+        let (url, requests) = http_server(assuming_aws).await;
+        for role in ["home", "www"] {
+            let zone = test_util::zone(&format!(
+                "assume_role_arn = \"arn:aws:iam::123456789012:role/{role}\""
+            ));
+            let config = aws_sdk_config(&zone, test_util::aws_config_loader(&url)).await;
+            let client = Client::new(&config);
+            client.get_hosted_zone().id("Z1").send().await.unwrap();
+        }
+
+        let requests = requests.lock().unwrap();
+        let calls: Vec<(&str, &str)> = requests
+            .iter()
+            .map(|request| {
+                let authorization = request.header("authorization").unwrap();
+                let key = authorization
+                    .split_once("Credential=")
+                    .and_then(|(_, rest)| rest.split_once('/'))
+                    .unwrap()
+                    .0;
+                (request.method.as_str(), key)
+            })
+            .collect();
+        assert_eq!(
+            calls,
+            [
+                ("POST", "AKID"),
+                ("GET", "ASIAHOME"),
+                ("POST", "AKID"),
+                ("GET", "ASIAWWW")
+            ]
+        );
+        for (request, role) in [(&requests[0], "home"), (&requests[2], "www")] {
+            assert!(
+                request.body.contains("Action=AssumeRole"),
+                "{}",
+                request.body
+            );
+            assert!(
+                request.body.contains(&format!(
+                    "RoleArn=arn%3Aaws%3Aiam%3A%3A123456789012%3Arole%2F{role}"
+                )),
+                "{}",
+                request.body
+            );
+            assert!(request.body.contains("RoleSessionName=route53_dd"));
+        }
+    }
+
+    // aws-config only exports the profile file types under their deprecated names.
+    #[allow(deprecated)]
+    #[tokio::test]
+    async fn loads_region_and_credentials_from_the_profile() {
+        use aws_config::profile::profile_file::{ProfileFileKind, ProfileFiles};
+        use aws_credential_types::provider::ProvideCredentials;
+
+        let config = test_util::temp_path("aws-config");
+        std::fs::write(
+            &config,
+            "[default]\nregion = us-east-1\n\n[profile ddns]\nregion = eu-west-2\n",
+        )
+        .unwrap();
+        let credentials = test_util::temp_path("aws-credentials");
+        std::fs::write(
+            &credentials,
+            "[default]\naws_access_key_id = AKIADEFAULT\naws_secret_access_key = default\n\n\
+             [ddns]\naws_access_key_id = AKIADDNS\naws_secret_access_key = ddns-secret\n",
+        )
+        .unwrap();
+        let files = ProfileFiles::builder()
+            .with_file(ProfileFileKind::Config, &config)
+            .with_file(ProfileFileKind::Credentials, &credentials)
+            .build();
+        let zone = test_util::zone("aws_profile = \"ddns\"");
+
+        let loader = aws_config_loader(&[]).profile_files(files);
+        let config = aws_sdk_config(&zone, loader).await;
+
+        assert_eq!(config.region().unwrap().as_ref(), "eu-west-2");
+        let loaded = config
+            .credentials_provider()
+            .unwrap()
+            .provide_credentials()
+            .await
+            .unwrap();
+        assert_eq!(loaded.access_key_id(), "AKIADDNS");
+        assert_eq!(loaded.secret_access_key(), "ddns-secret");
+    }
+
+    #[test]
+    fn consensus_needs_enough_providers_for_each_family() {
+        assert!(test_util::zone("ip_consensus = 2").validate().is_ok());
+        // A dedicated URL is the only provider asked for its family.
+        let err = test_util::zone("ip_consensus = 2\nipv4_url = \"https://ipv4.example\"")
+            .validate()
+            .unwrap_err();
+        assert!(err.to_string().contains("asked for A (1)"), "{err}");
+        let mut zone = test_util::zone("ip_consensus = 2\nipv6_url = \"https://ipv6.example\"");
+        assert!(zone.validate().is_ok());
+        zone.ipv6 = true;
+        assert!(zone.validate().is_err());
+    }
+
+    /// The public zone of a split-horizon pair publishes the address the IP provider
+    /// sees, the private one the LAN address of its interface.
+    #[tokio::test]
+    async fn split_horizon_zones_use_their_own_source() {
+        let (url, _) = http_server(|_| (200, "8.8.8.8".to_owned())).await;
+        let public = test_util::zone(&format!("ip_provider = [\"{url}\"]"));
+        let private = test_util::zone(&format!(
+            "ip_provider = [\"{url}\"]\nprimary_ip_source = \"lan\"\ninterface = \"route53-dd-missing\""
+        ));
+        let ctx = test_util::context();
+        let mut detected = detect::Detected::default();
+        detect::detect_addresses(&public, &ctx.state, &ctx.clients, &mut detected)
+            .await
+            .unwrap();
+        assert_eq!(detected.ipv4, Some("8.8.8.8".parse().unwrap()));
+
+        let mut detected = detect::Detected::default();
+        let err = detect::detect_addresses(&private, &ctx.state, &ctx.clients, &mut detected)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("route53-dd-missing"), "{err:#}");
+    }
+
+    #[tokio::test]
+    async fn writes_apex_and_www_in_one_batch() {
+        let (url, _) = http_server(|_| (200, "8.8.8.8".to_owned())).await;
+        let path = test_util::temp_path("apex.zone");
+        std::fs::write(
+            &path,
+            "$ORIGIN example.com.\n\
+             @ 3600 IN SOA ns1.example.com. hostmaster.example.com. ( 7 7200 3600 1209600 300 )\n",
+        )
+        .unwrap();
+        let mut zone = test_util::zone(&format!(
+            "zone_file = {:?}\nip_provider = [\"{url}\"]\nalso_apex = true",
+            path.display().to_string()
+        ));
+        zone.record_name = vec!["www".to_owned()];
+        let ctx = test_util::context();
+        let mut explanation = Explanation::new("www.example.com");
+        update_hosted_zone(zone, &ctx, &mut explanation)
+            .await
+            .unwrap();
+
+        // Both records went out in one write, so the serial moved only once.
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.contains("( 8 7200"), "{text}");
+        assert!(text.contains("www.example.com. 300 IN A 8.8.8.8"), "{text}");
+        assert!(
+            text.lines()
+                .any(|line| line.starts_with("example.com. 300 IN A 8.8.8.8")),
+            "{text}"
+        );
+    }
+
+    #[tokio::test]
+    async fn adaptive_ttl_drops_only_on_a_real_change() {
+        let answer = Arc::new(std::sync::Mutex::new("8.8.8.8"));
+        let current = answer.clone();
+        let (url, _) = http_server(move |_| (200, current.lock().unwrap().to_string())).await;
+        let path = test_util::zone_file("adaptive.zone", "");
+        let zone = test_util::zone(&format!(
+            "zone_file = {:?}\nip_provider = [\"{url}\"]\n\
+             adaptive_ttl = {{ min = 60, max = 3600, ramp_seconds = 86400 }}",
+            path.display().to_string()
+        ));
+        let ctx = test_util::context();
+        let mut explanation = Explanation::new("home.example.com");
+
+        // The first publication is not a change, so it keeps the full TTL.
+        update_hosted_zone(zone.clone(), &ctx, &mut explanation)
+            .await
+            .unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(
+            text.contains("home.example.com. 3600 IN A 8.8.8.8"),
+            "{text}"
+        );
+        assert_eq!(ctx.state.lock().unwrap().last_ip_change, None);
+
+        *answer.lock().unwrap() = "8.8.4.4";
+        update_hosted_zone(zone, &ctx, &mut explanation)
+            .await
+            .unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.contains("home.example.com. 60 IN A 8.8.4.4"), "{text}");
+        assert!(ctx.state.lock().unwrap().last_ip_change.is_some());
+    }
+}
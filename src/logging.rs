@@ -0,0 +1,121 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use anyhow::Error;
+use log::{LevelFilter, Log, Metadata, Record};
+use simple_logger::SimpleLogger;
+
+/// Sets up human readable console logging and, when `json_log_file` is given, also
+/// appends every record to that file as one JSON object per line.
+pub fn init(json_log_file: Option<&Path>) -> Result<(), Error> {
+    let console = SimpleLogger::new().with_level(LevelFilter::Info);
+    let Some(path) = json_log_file else {
+        console.init()?;
+        return Ok(());
+    };
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    log::set_max_level(console.max_level());
+    log::set_boxed_logger(Box::new(TeeLogger {
+        console,
+        json: Mutex::new(file),
+    }))?;
+    Ok(())
+}
+
+/// Sends each record to the console logger and to a JSON lines file.
+struct TeeLogger<C> {
+    console: C,
+    json: Mutex<File>,
+}
+
+impl<C: Log> Log for TeeLogger<C> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.console.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        self.console.log(record);
+        let mut line = serde_json::json!({
+            "timestamp": humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            "level": record.level().as_str(),
+            "target": record.target(),
+            "message": record.args().to_string(),
+        })
+        .to_string();
+        line.push('\n');
+        // There is nowhere left to report a failing log sink, so write errors are dropped.
+        let _ = self.json.lock().unwrap().write_all(line.as_bytes());
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+        let _ = self.json.lock().unwrap().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+
+    use super::*;
+
+    /// Keeps the messages of the records it receives.
+    #[derive(Default)]
+    struct Console(Mutex<Vec<String>>);
+
+    impl Log for Console {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= Level::Info
+        }
+
+        fn log(&self, record: &Record) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn writes_each_record_to_both_sinks() {
+        let path = crate::test_util::temp_path("log.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let tee = TeeLogger {
+            console: Console::default(),
+            json: Mutex::new(File::create(&path).unwrap()),
+        };
+
+        for (level, message) in [
+            (Level::Info, "Updated home.example.com"),
+            (Level::Debug, "hidden"),
+        ] {
+            tee.log(
+                &Record::builder()
+                    .level(level)
+                    .target("route53_dd")
+                    .args(format_args!("{message}"))
+                    .build(),
+            );
+        }
+        tee.flush();
+
+        assert_eq!(*tee.console.0.lock().unwrap(), ["Updated home.example.com"]);
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["target"], "route53_dd");
+        assert_eq!(lines[0]["message"], "Updated home.example.com");
+        assert!(humantime::parse_rfc3339(lines[0]["timestamp"].as_str().unwrap()).is_ok());
+    }
+}
@@ -1,41 +1,195 @@
-use std::{net::IpAddr, time::Duration};
+use std::{
+    fs::File,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
-use anyhow::{anyhow, Error};
-use aws_config::{self, BehaviorVersion, Region};
-use aws_sdk_route53::{
-    types::{Change, ChangeBatch, ResourceRecord, ResourceRecordSet},
-    Client,
+use anyhow::{anyhow, Context, Error};
+use clap::{
+    parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
 };
-use aws_smithy_runtime::client::http::hyper_014::HyperClientBuilder;
-use credential_provider::AwsCredentials;
-use log::{error, info};
-use serde::Deserialize;
-mod credential_provider;
-use clap::Parser;
-use shadow_rs::shadow;
-use simple_logger::SimpleLogger;
-use tokio::{
-    select,
-    time::{self},
+use log::{error, info, warn};
+use route53_dd::{
+    adaptive_ttl::AdaptiveTtl,
+    address_watch, bulk,
+    cert_pin::CertPin,
+    cgnat::CgnatAction,
+    clients::ZoneClients,
+    config,
+    credential_provider::{AwsCredentials, WebIdentityConfig},
+    daemon_update_zone,
+    desktop::Notifier,
+    detect,
+    failure_budget::{BudgetAction, FailureBudgetSpec},
+    history::{self, History},
+    interface::Ipv6Prefer,
+    ip_extract::IpExtract,
+    ip_source::IpSource,
+    ipv6_prefix, logging, plan,
+    providers::{
+        cloudflare::CloudflareConfig,
+        rfc2136::{Rfc2136Config, TsigAlgorithmName},
+        route53::{GeolocationRouting, ZoneVisibility},
+    },
+    reachability::ReachabilityCheck,
+    retry,
+    routers::{
+        firewall::FirewallConfig,
+        fritzbox::{self, FritzBoxConfig},
+        mikrotik::MikrotikConfig,
+    },
+    serve_ip, shut_down_after,
+    state::{self, DaemonState, SharedState, StateFile},
+    statsd::StatsdClient,
+    stun, supervise_zone,
+    sync_tracker::SyncTracker,
+    template::Template,
+    vault::{VaultConfig, VaultEngine},
+    HostedZoneConfig, UpdateContext,
 };
-use tokio_util::sync::CancellationToken;
+use shadow_rs::shadow;
+use tokio::time;
 
 shadow!(build);
 
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum DumpSignal {
+    #[value(name = "SIGUSR1")]
+    Sigusr1,
+    #[value(name = "SIGUSR2")]
+    Sigusr2,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ExportFormat {
+    Csv,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Compare the detected addresses with the published records without writing.
+    /// Exits with status 2 when any record would change.
+    Diff,
+
+    /// Serve the caller's public IP over HTTP so other instances can use this one as
+    /// their `--ip-provider`.
+    ServeIp {
+        #[arg(long, env = "SERVE_IP_BIND", default_value = "0.0.0.0:8080")]
+        bind: SocketAddr,
+
+        /// Proxies whose `X-Forwarded-For` header is trusted.
+        #[arg(long = "trusted-proxy", env = "TRUSTED_PROXIES", value_delimiter = ',')]
+        trusted_proxies: Vec<IpAddr>,
+    },
+
+    /// Export the recorded change history.
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+
+        /// Write the export to this file instead of stdout.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+
+    /// Manage AWS keys in the OS keyring, for use with --aws-keyring-entry. Needs the
+    /// `keyring` feature.
+    Credentials {
+        #[command(subcommand)]
+        action: CredentialsAction,
+
+        /// Name of the keyring entry.
+        #[arg(long, global = true, default_value = "default")]
+        entry: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CredentialsAction {
+    /// Store an access key, asking for it on the terminal.
+    Set,
+
+    /// Print the stored keys in the `credential_process` format, so `~/.aws/config` can
+    /// use them with `credential_process = route53_dd credentials get`.
+    Get,
+
+    /// Remove the stored keys.
+    Delete,
+}
+
 #[derive(Parser, Debug)]
-#[command(about, long_about = None, version = version())]
+#[command(about, long_about = None, version = version(), subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(long, short, default_value_t = false)]
     daemon: bool,
 
+    /// Detect the addresses and look up the zone, then print the records that would be
+    /// upserted with their TTL and current values instead of writing them.
+    #[arg(long, env = "DRY_RUN", default_value_t = false)]
+    dry_run: bool,
+
+    /// Read the zones to update from this TOML file, one `[[zones]]` table per zone,
+    /// instead of the per-zone arguments.
+    #[arg(long, env = "CONFIG")]
+    config: Option<PathBuf>,
+
     #[arg(long, short, env = "UPDATE_FREQUENCY_MINUTES", default_value_t = 5)]
     update_frequency_minutes: u64,
 
-    #[arg(long, env = "ZONE_NAME")]
-    zone_name: String,
+    #[arg(long, env = "ZONE_NAME", required_unless_present = "config")]
+    zone_name: Option<String>,
+
+    /// Select the hosted zone by a `key=value` tag instead of by name.
+    /// The zone name is still used to build the record name.
+    #[arg(long, env = "ZONE_TAG")]
+    zone_tag: Option<String>,
+
+    /// Use this hosted zone id instead of looking the zone up, which needs no
+    /// `route53:ListHostedZonesByName` permission. The zone name is still used to build
+    /// the record name.
+    #[arg(long, env = "HOSTED_ZONE_ID", conflicts_with = "zone_tag")]
+    hosted_zone_id: Option<String>,
+
+    /// Only consider public or private hosted zones when both exist with the zone name.
+    #[arg(long, env = "ZONE_VISIBILITY", value_enum, default_value_t = ZoneVisibility::Any)]
+    zone_visibility: ZoneVisibility,
 
-    #[arg(long, env = "RECORD_NAME")]
-    record_name: String,
+    /// The hosted zone id is looked up once and kept for the life of the daemon. Set
+    /// this, e.g. to `1d`, to look it up again after that long.
+    #[arg(long, env = "REFRESH_ZONE_ID_EVERY", value_parser = humantime::parse_duration)]
+    refresh_zone_id_every: Option<Duration>,
+
+    /// Records to point at the detected address, repeated or comma separated. All of
+    /// them are written in one batch.
+    #[arg(
+        long,
+        env = "RECORD_NAME",
+        value_delimiter = ',',
+        required_unless_present = "config"
+    )]
+    record_name: Vec<String>,
+
+    /// Use the record names verbatim as the fully qualified name instead of appending the zone name.
+    #[arg(long, env = "RECORD_NAME_IS_FQDN", default_value_t = false)]
+    record_name_is_fqdn: bool,
+
+    /// When managing `www.<zone>`, also point the zone apex at the same address.
+    #[arg(
+        long,
+        env = "ALSO_APEX",
+        default_value_t = false,
+        conflicts_with = "also_www"
+    )]
+    also_apex: bool,
+
+    /// When managing the zone apex, also point `www.<zone>` at the same address.
+    #[arg(long, env = "ALSO_WWW", default_value_t = false)]
+    also_www: bool,
 
     #[arg(long, env = "IPV4", default_value_t = true)]
     ipv4: bool,
@@ -43,32 +197,615 @@ struct Args {
     #[arg(long, env = "IPV6", default_value_t = false)]
     ipv6: bool,
 
+    /// URLs returning the caller's public IP address, repeated or comma separated. They
+    /// are tried in order until one answers.
+    #[arg(long, env = "IP_PROVIDER", value_delimiter = ',', default_values_t = detect::default_ip_providers())]
+    ip_provider: Vec<String>,
+
+    /// Give up on an IP provider that hasn't connected or answered within this many
+    /// seconds.
+    #[arg(long, visible_alias = "detection-timeout-secs", env = "IP_PROVIDER_TIMEOUT_SECONDS", default_value_t = detect::DEFAULT_IP_PROVIDER_TIMEOUT_SECONDS)]
+    ip_provider_timeout_seconds: u64,
+
+    /// Ask every IP provider at once and only accept an address at least this many of
+    /// them return, so a single misbehaving provider can't change the record.
+    #[arg(long, env = "IP_CONSENSUS")]
+    ip_consensus: Option<usize>,
+
+    /// Detect the IPv4 address with only this URL, e.g. a self-hosted echo service,
+    /// instead of the IP providers.
+    #[arg(long, env = "IPV4_URL")]
+    ipv4_url: Option<String>,
+
+    /// Detect the IPv6 address with only this URL instead of the IP providers.
+    #[arg(long, env = "IPV6_URL")]
+    ipv6_url: Option<String>,
+
+    /// How to read the address from the `--ipv4-url`/`--ipv6-url` response: `plain`,
+    /// `regex:<pattern>` using the first capture group, or `json:<pointer>`, e.g.
+    /// `json:/ip`. The default IP providers are always read as plain text.
+    #[arg(long, env = "IP_EXTRACT", default_value = "plain")]
+    ip_extract: IpExtract,
+
+    /// STUN servers asked in order by the `stun` IP source, repeated or comma separated.
+    #[arg(long = "stun-server", env = "STUN_SERVERS", value_delimiter = ',', default_values_t = stun::default_stun_servers())]
+    stun_servers: Vec<String>,
+
+    /// Gateway asked by the `natpmp` and `pcp` IP sources. Defaults to the IPv4 default
+    /// gateway on Linux.
+    #[arg(long, env = "NAT_GATEWAY")]
+    nat_gateway: Option<Ipv4Addr>,
+
+    /// Address of the FRITZ!Box asked by the `fritzbox` IP source, `http://fritz.box:49000`
+    /// by default.
+    #[arg(long, env = "FRITZBOX_URL")]
+    fritzbox_url: Option<String>,
+
+    /// Log in to the FRITZ!Box's TR-064 API as this user instead of using the
+    /// unauthenticated UPnP endpoint.
+    #[arg(long, env = "FRITZBOX_USERNAME")]
+    fritzbox_username: Option<String>,
+
+    #[arg(
+        long,
+        env = "FRITZBOX_PASSWORD",
+        group = "fritzbox_password_input",
+        requires = "fritzbox_username"
+    )]
+    fritzbox_password: Option<String>,
+
+    #[arg(
+        long,
+        env = "FRITZBOX_PASSWORD_FILE",
+        group = "fritzbox_password_input",
+        requires = "fritzbox_username"
+    )]
+    fritzbox_password_file: Option<PathBuf>,
+
+    /// Base URL of the MikroTik router whose REST API the `mikrotik` IP source asks,
+    /// e.g. `https://192.168.88.1`.
+    #[arg(long, env = "MIKROTIK_URL", requires_all = ["mikrotik_username", "mikrotik_interface"])]
+    mikrotik_url: Option<String>,
+
+    #[arg(long, env = "MIKROTIK_USERNAME")]
+    mikrotik_username: Option<String>,
+
+    #[arg(
+        long,
+        env = "MIKROTIK_PASSWORD",
+        group = "mikrotik_password_input",
+        default_value = ""
+    )]
+    mikrotik_password: String,
+
+    #[arg(
+        long,
+        env = "MIKROTIK_PASSWORD_FILE",
+        group = "mikrotik_password_input"
+    )]
+    mikrotik_password_file: Option<PathBuf>,
+
+    /// The router's WAN interface, e.g. `ether1` or `pppoe-out1`.
+    #[arg(long, env = "MIKROTIK_INTERFACE")]
+    mikrotik_interface: Option<String>,
+
+    /// Base URL of the firewall whose API the `opnsense` and `pfsense` IP sources ask,
+    /// e.g. `https://192.168.1.1`.
+    #[arg(long, env = "FIREWALL_URL", requires_all = ["firewall_api_key_input", "firewall_interface"])]
+    firewall_url: Option<String>,
+
+    #[arg(long, env = "FIREWALL_API_KEY", group = "firewall_api_key_input")]
+    firewall_api_key: Option<String>,
+
+    #[arg(long, env = "FIREWALL_API_KEY_FILE", group = "firewall_api_key_input")]
+    firewall_api_key_file: Option<PathBuf>,
+
+    /// OPNsense's API secret; pfSense authenticates with the key alone.
+    #[arg(
+        long,
+        env = "FIREWALL_API_SECRET",
+        group = "firewall_api_secret_input",
+        default_value = ""
+    )]
+    firewall_api_secret: String,
+
+    #[arg(
+        long,
+        env = "FIREWALL_API_SECRET_FILE",
+        group = "firewall_api_secret_input"
+    )]
+    firewall_api_secret_file: Option<PathBuf>,
+
+    /// The firewall's WAN interface: its device on OPNsense, e.g. `igb0`, and its name on
+    /// pfSense, e.g. `wan`.
+    #[arg(long, env = "FIREWALL_INTERFACE")]
+    firewall_interface: Option<String>,
+
+    /// Network interface whose global address the `interface` IP source publishes, for
+    /// hosts with a directly assigned public address, or whose LAN address the `lan` IP
+    /// source publishes.
+    #[arg(long, env = "INTERFACE")]
+    interface: Option<String>,
+
+    /// Which of the interface's IPv6 addresses the `interface` IP source publishes.
+    #[arg(long, env = "IPV6_PREFER", value_enum, default_value_t = Ipv6Prefer::Stable)]
+    ipv6_prefer: Ipv6Prefer,
+
+    /// In daemon mode, update as soon as the kernel reports an address change instead of
+    /// waiting for the next interval (Linux only). Limited to --interface when it's set.
+    #[arg(long, env = "WATCH_ADDRESS_CHANGES")]
+    watch_address_changes: bool,
+
+    /// Wait until addresses have stopped changing for this long before updating.
+    #[arg(long, env = "ADDRESS_CHANGE_DEBOUNCE_SECONDS", default_value_t = address_watch::DEFAULT_DEBOUNCE_SECONDS)]
+    address_change_debounce_seconds: u64,
+
+    /// Read the IP address from this response header instead of the body.
+    #[arg(long, env = "IP_FROM_HEADER")]
+    ip_from_header: Option<String>,
+
+    /// Also publish a TXT record at the record name whenever the addresses change, e.g.
+    /// `{hostname} {ipv4} {iso8601}`. Placeholders: {ip}, {ipv4}, {ipv6}, {hostname},
+    /// {iso8601} and {epoch}.
+    #[arg(long, env = "TXT_TEMPLATE")]
+    txt_template: Option<Template>,
+
+    /// Comment attached to Route53 change batches, using the same placeholders as
+    /// --txt-template.
+    #[arg(long, env = "CHANGE_COMMENT")]
+    change_comment: Option<Template>,
+
+    /// Fail detection when an IP provider's response body is larger than this.
+    #[arg(long, env = "IP_RESPONSE_MAX_BYTES", default_value_t = detect::DEFAULT_IP_RESPONSE_MAX_BYTES)]
+    ip_response_max_bytes: usize,
+
+    /// Preferred source of the address: an http(s) URL, `imds`, `upnp`, `natpmp`, `pcp`,
+    /// `fritzbox`, `mikrotik`, `opnsense`, `pfsense`, `interface`, `stun`, `dns:opendns`,
+    /// `dns:google`, or `lan` for the LAN address of --interface, e.g. for the private zone
+    /// of a split-horizon pair. Replaces --ip-provider.
+    #[arg(long, env = "PRIMARY_IP_SOURCE")]
+    primary_ip_source: Option<IpSource>,
+
+    /// Source used only while the primary IP source fails.
+    #[arg(long, env = "BACKUP_IP_SOURCE", requires = "primary_ip_source")]
+    backup_ip_source: Option<IpSource>,
+
+    /// On EC2, read the public IPv4 address from instance metadata (IMDSv2) instead of
+    /// the IP provider, falling back to the provider if IMDS is unavailable.
+    #[arg(long, env = "IP_FROM_IMDS", default_value_t = false)]
+    ip_from_imds: bool,
+
+    /// Instance metadata endpoint used by --ip-from-imds and the `imds` IP source.
+    #[arg(long, env = "AWS_EC2_METADATA_SERVICE_ENDPOINT", default_value = detect::DEFAULT_IMDS_ENDPOINT)]
+    imds_endpoint: String,
+
+    /// Upper bound in seconds on detecting each address family, which run side by side.
+    /// When only one family is found in time, it is still published.
+    #[arg(long, env = "DETECT_TIMEOUT_TOTAL_SECONDS")]
+    detect_timeout_total_seconds: Option<u64>,
+
+    /// Resolve the IP provider's hostname with this DNS-over-HTTPS JSON endpoint
+    /// instead of the system resolver, e.g. `https://cloudflare-dns.com/dns-query`.
+    #[arg(long, env = "IP_DOH_RESOLVER")]
+    ip_doh_resolver: Option<String>,
+
+    /// Allow publishing unique local (fc00::/7) IPv6 addresses, e.g. for private zones.
+    #[arg(long, env = "ALLOW_ULA", default_value_t = false)]
+    allow_ula: bool,
+
+    /// Allow publishing private (RFC 1918), loopback, link-local and other addresses that
+    /// can't be reached from the internet, e.g. for private zones. Implied by the `lan` IP
+    /// source.
+    #[arg(long, env = "ALLOW_PRIVATE_ADDRESSES", default_value_t = false)]
+    allow_private_addresses: bool,
+
+    /// Keep only this many leading bits of the detected IPv6 address and append
+    /// --ipv6-suffix, for a host whose delegated prefix rotates but whose interface
+    /// identifier doesn't.
+    #[arg(long, env = "IPV6_PREFIX_LENGTH")]
+    ipv6_prefix_length: Option<u8>,
+
+    /// Interface identifier appended to the detected prefix, e.g. `::1234:5678:9abc:def0`.
+    #[arg(long, env = "IPV6_SUFFIX", requires = "ipv6_prefix_length")]
+    ipv6_suffix: Option<Ipv6Addr>,
+
+    /// Another host in the delegated prefix as `name=suffix`, e.g. `nas=::11`, whose AAAA
+    /// record moves to the new prefix together with the managed records. Repeatable.
+    #[arg(long = "lan-host", env = "LAN_HOSTS", value_delimiter = ',', value_parser = ipv6_prefix::parse_lan_host, requires = "ipv6_prefix_length")]
+    lan_hosts: Vec<(String, Ipv6Addr)>,
+
+    /// Load the region and credentials from this profile in the AWS shared config and
+    /// credentials files instead of --region and the access key arguments. --region still
+    /// overrides the profile's region.
+    #[arg(long, env = "AWS_PROFILE")]
+    aws_profile: Option<String>,
+
+    /// Exchange this OIDC token file for the credentials of --web-identity-role-arn, as
+    /// with EKS IAM Roles for Service Accounts. Both are set in the pod's environment.
+    #[arg(
+        long,
+        env = "AWS_WEB_IDENTITY_TOKEN_FILE",
+        requires = "web_identity_role_arn"
+    )]
+    web_identity_token_file: Option<PathBuf>,
+
+    #[arg(long, env = "AWS_ROLE_ARN", requires = "web_identity_token_file")]
+    web_identity_role_arn: Option<String>,
+
+    /// Read the access key id, secret access key and optional session token from this
+    /// Secrets Manager secret, stored as JSON with `access_key_id`, `secret_access_key`
+    /// and `session_token`. The secret itself is read with the default credential chain,
+    /// e.g. the instance role, and read again whenever the credentials are refreshed.
+    #[arg(long, env = "AWS_CREDENTIALS_SECRET", conflicts_with_all = ["aws_profile", "aws_access_key_id_input", "web_identity_token_file"])]
+    aws_credentials_secret: Option<String>,
+
+    /// Read the AWS keys from this path in Vault, e.g. `aws/creds/route53` for the AWS
+    /// secrets engine, which mints short-lived keys and is asked for new ones before
+    /// they expire.
+    #[arg(long, env = "VAULT_SECRET_PATH", requires = "vault_addr", conflicts_with_all = ["aws_profile", "aws_access_key_id_input", "web_identity_token_file", "aws_credentials_secret"])]
+    vault_path: Option<String>,
+
+    #[arg(long, env = "VAULT_ADDR")]
+    vault_addr: Option<String>,
+
+    #[arg(long, env = "VAULT_ENGINE", value_enum, default_value_t = VaultEngine::Aws)]
+    vault_engine: VaultEngine,
+
+    /// Log in to Vault with this token, or with --vault-role-id and --vault-secret-id.
+    #[arg(long, env = "VAULT_TOKEN", group = "vault_token_input")]
+    vault_token: Option<String>,
+
+    #[arg(long, env = "VAULT_TOKEN_FILE", group = "vault_token_input")]
+    vault_token_file: Option<PathBuf>,
+
+    #[arg(long, env = "VAULT_ROLE_ID")]
+    vault_role_id: Option<String>,
+
+    #[arg(long, env = "VAULT_SECRET_ID", group = "vault_secret_id_input")]
+    vault_secret_id: Option<String>,
+
+    #[arg(long, env = "VAULT_SECRET_ID_FILE", group = "vault_secret_id_input")]
+    vault_secret_id_file: Option<PathBuf>,
+
+    /// Read the AWS keys from this OS keyring entry, stored with `credentials set`. Needs
+    /// the `keyring` feature.
+    #[arg(long, env = "AWS_KEYRING_ENTRY", conflicts_with_all = ["aws_profile", "aws_access_key_id_input", "web_identity_token_file", "aws_credentials_secret", "vault_path"])]
+    aws_keyring_entry: Option<String>,
+
+    /// Defaults to the profile's or the environment's region.
     #[arg(long, env = "AWS_REGION")]
-    region: String,
+    region: Option<String>,
+
+    /// Static credentials. Without them the SDK's default chain is used: environment,
+    /// shared config and credentials files, SSO, web identity, ECS task roles and IMDS.
+    #[arg(
+        long,
+        env = "AWS_ACCESS_KEY_ID",
+        group = "aws_access_key_id_input",
+        requires = "aws_secret_access_key_input"
+    )]
+    aws_access_key_id: Option<String>,
 
-    #[arg(long, env = "AWS_ACCESS_KEY_ID")]
-    aws_access_key_id: String,
+    /// Read the access key id from this file instead. Every secret has a `--*-file` flag
+    /// and a `*_FILE` variable like this one, which take Docker secrets and systemd
+    /// credentials as they are delivered and keep the secret out of `ps` and inspect.
+    #[arg(
+        long,
+        env = "AWS_ACCESS_KEY_ID_FILE",
+        group = "aws_access_key_id_input",
+        requires = "aws_secret_access_key_input"
+    )]
+    aws_access_key_id_file: Option<PathBuf>,
 
-    #[arg(long, env = "AWS_SECRET_ACCESS_KEY")]
-    aws_secret_access_key: String,
+    #[arg(
+        long,
+        env = "AWS_SECRET_ACCESS_KEY",
+        group = "aws_secret_access_key_input",
+        requires = "aws_access_key_id_input"
+    )]
+    aws_secret_access_key: Option<String>,
 
-    #[arg(long, env = "AWS_SESSION_TOKEN")]
+    #[arg(
+        long,
+        env = "AWS_SECRET_ACCESS_KEY_FILE",
+        group = "aws_secret_access_key_input",
+        requires = "aws_access_key_id_input"
+    )]
+    aws_secret_access_key_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        env = "AWS_SESSION_TOKEN",
+        group = "aws_session_token_input",
+        requires = "aws_access_key_id_input"
+    )]
     aws_session_token: Option<String>,
 
+    #[arg(
+        long,
+        env = "AWS_SESSION_TOKEN_FILE",
+        group = "aws_session_token_input",
+        requires = "aws_access_key_id_input"
+    )]
+    aws_session_token_file: Option<PathBuf>,
+
     #[arg(long, env = "TTL_SECONDS", default_value_t = 300)]
     ttl_seconds: i64,
-}
 
-#[derive(Deserialize, Clone, Debug)]
-struct HostedZoneConfig {
-    pub update_frequency_minutes: u64,
-    pub zone_name: String,
-    pub record_name: String,
-    pub ipv4: bool,
-    pub ipv6: bool,
-    pub region: String,
-    pub aws_credentials: AwsCredentials,
-    pub ttl_seconds: i64,
+    /// Derive the TTL from how long the address has been stable instead of using
+    /// --ttl-seconds, ramping from --ttl-min to --ttl-max over --ttl-ramp-seconds.
+    #[arg(long, env = "ADAPTIVE_TTL", default_value_t = false)]
+    adaptive_ttl: bool,
+
+    #[arg(long, env = "TTL_MIN", default_value_t = 60)]
+    ttl_min: i64,
+
+    #[arg(long, env = "TTL_MAX", default_value_t = 3600)]
+    ttl_max: i64,
+
+    #[arg(long, env = "TTL_RAMP_SECONDS", default_value_t = 86400)]
+    ttl_ramp_seconds: u64,
+
+    /// Publish the addresses found through every --source-address as one round-robin
+    /// record per family instead of a single address.
+    #[arg(
+        long,
+        env = "MULTI_VALUE",
+        default_value_t = false,
+        requires = "source_addresses"
+    )]
+    multi_value: bool,
+
+    /// Local address to detect the public address through, one per uplink. Repeatable.
+    #[arg(
+        long = "source-address",
+        env = "SOURCE_ADDRESSES",
+        value_delimiter = ','
+    )]
+    source_addresses: Vec<IpAddr>,
+
+    /// Log why each tick did or didn't write: detected and published values, the gates
+    /// that applied and the final decision.
+    #[arg(long, env = "EXPLAIN", default_value_t = false)]
+    explain: bool,
+
+    /// Resolve every record before writing and skip the write when DNS already answers
+    /// with the detected addresses.
+    #[arg(long, env = "COMPARE_DNS", default_value_t = false)]
+    compare_dns: bool,
+
+    /// DNS-over-HTTPS JSON endpoint for --compare-dns instead of the system resolver.
+    #[arg(long, env = "COMPARE_DNS_RESOLVER", requires = "compare_dns")]
+    compare_dns_resolver: Option<String>,
+
+    /// Read the published values at startup, so the first update after a restart is
+    /// skipped too when the detected address is already served.
+    #[arg(long, env = "SEED_CACHE_FROM_DNS", default_value_t = false)]
+    seed_cache_from_dns: bool,
+
+    /// Write the record to this RFC 1035 zone file instead of Route53, bumping the SOA serial.
+    #[arg(long, env = "ZONE_FILE")]
+    zone_file: Option<PathBuf>,
+
+    /// Send RFC 2136 dynamic updates to this name server instead of calling Route53,
+    /// e.g. `192.0.2.53:53`.
+    #[arg(long, env = "RFC2136_SERVER", requires_all = ["tsig_key_name", "tsig_secret_input"])]
+    rfc2136_server: Option<SocketAddr>,
+
+    /// Name of the TSIG key that signs RFC 2136 updates.
+    #[arg(long, env = "TSIG_KEY_NAME")]
+    tsig_key_name: Option<String>,
+
+    /// Base64 encoded TSIG secret.
+    #[arg(long, env = "TSIG_SECRET", group = "tsig_secret_input")]
+    tsig_secret: Option<String>,
+
+    #[arg(long, env = "TSIG_SECRET_FILE", group = "tsig_secret_input")]
+    tsig_secret_file: Option<PathBuf>,
+
+    #[arg(long, env = "TSIG_ALGORITHM", value_enum, default_value_t = TsigAlgorithmName::HmacSha256)]
+    tsig_algorithm: TsigAlgorithmName,
+
+    /// Before writing, list the other record types at the name and refuse to write next
+    /// to a conflicting CNAME.
+    #[arg(long, env = "PRESERVE_OTHER_TYPES", default_value_t = false)]
+    preserve_other_types: bool,
+
+    /// Keep the TTL of an existing record instead of resetting it to --ttl-seconds.
+    #[arg(long, env = "PRESERVE_TTL")]
+    preserve_ttl: bool,
+
+    /// In daemon mode, wait this long before the first detection, e.g. for PPPoE or VPN
+    /// links that come up after the service starts.
+    #[arg(long, env = "STARTUP_DELAY_SECONDS", default_value_t = 0)]
+    startup_delay_seconds: u64,
+
+    /// In daemon mode, wait a random time of up to this many seconds before the first
+    /// detection, so a fleet restarting together doesn't update all at once.
+    #[arg(long, env = "STARTUP_JITTER_SECONDS", default_value_t = 0)]
+    startup_jitter_seconds: u64,
+
+    /// Minimum number of seconds between two writes to the same record.
+    #[arg(long, env = "MIN_CHANGE_INTERVAL_SECONDS", default_value_t = 0)]
+    min_change_interval_seconds: u64,
+
+    /// After each Route53 write, wait up to this many seconds for the change to be
+    /// INSYNC and fail the update if it isn't, so success means the change propagated.
+    #[arg(long, env = "WAIT_FOR_SYNC_SECONDS", conflicts_with = "track_sync")]
+    wait_for_sync_seconds: Option<u64>,
+
+    /// Escalate once more than N calls to the DNS backend fail within the window, retries
+    /// included, written as `N/window`, e.g. `5/1h`.
+    #[arg(long, env = "FAILURE_BUDGET")]
+    failure_budget: Option<FailureBudgetSpec>,
+
+    /// What to do when the failure budget is exhausted.
+    #[arg(long, env = "ON_FAILURE_BUDGET_EXHAUSTED", value_enum, default_value_t = BudgetAction::Alert)]
+    on_failure_budget_exhausted: BudgetAction,
+
+    /// Cloudflare API token for also writing the record to Cloudflare.
+    #[arg(long, env = "CLOUDFLARE_TOKEN", group = "cloudflare_token_input")]
+    cloudflare_token: Option<String>,
+
+    #[arg(long, env = "CLOUDFLARE_TOKEN_FILE", group = "cloudflare_token_input")]
+    cloudflare_token_file: Option<PathBuf>,
+
+    /// Cloudflare zone id that receives the same updates as Route53. Looked up by the
+    /// zone name when unset.
+    #[arg(long, env = "CLOUDFLARE_ZONE_ID", requires = "cloudflare_token_input")]
+    cloudflare_zone_id: Option<String>,
+
+    /// Keep the record on Cloudflare only, without Route53.
+    #[arg(
+        long,
+        env = "CLOUDFLARE_ONLY",
+        default_value_t = false,
+        requires = "cloudflare_token_input"
+    )]
+    cloudflare_only: bool,
+
+    /// Only publish a new address once it is reachable: either a TCP port to connect to on
+    /// the address, or a checker URL where `{ip}` is replaced by the address.
+    #[arg(long, env = "REACHABILITY_CHECK")]
+    reachability_check: Option<ReachabilityCheck>,
+
+    /// Write the record as the geolocation member for this continent code, e.g. `EU`.
+    #[arg(
+        long,
+        env = "GEOLOCATION_CONTINENT",
+        requires = "set_identifier",
+        conflicts_with = "geolocation_country"
+    )]
+    geolocation_continent: Option<String>,
+
+    /// Write the record as the geolocation member for this country code, e.g. `DE`, or `*`
+    /// for the default location.
+    #[arg(long, env = "GEOLOCATION_COUNTRY", requires = "set_identifier")]
+    geolocation_country: Option<String>,
+
+    /// Identifies the managed member of a geolocation routing policy.
+    #[arg(long, env = "SET_IDENTIFIER")]
+    set_identifier: Option<String>,
+
+    /// While this file exists, keep detecting but skip writing records.
+    #[arg(long, env = "PAUSE_FILE")]
+    pause_file: Option<PathBuf>,
+
+    /// Exit cleanly after running for this long in daemon mode, e.g. `24h`.
+    #[arg(long, env = "MAX_RUNTIME", value_parser = humantime::parse_duration)]
+    max_runtime: Option<Duration>,
+
+    /// In daemon mode, restart the update loop with backoff if it panics instead of exiting.
+    #[arg(long, env = "RESTART_ON_PANIC", default_value_t = false)]
+    restart_on_panic: bool,
+
+    /// Only talk to AWS endpoints whose certificate has one of these SHA-256 fingerprints.
+    /// Pins break as soon as AWS rotates its certificates, so they must be updated ahead
+    /// of every rotation. When assuming a role, STS's certificate must be pinned as well.
+    #[arg(long = "aws-cert-pin", env = "AWS_CERT_PINS", value_delimiter = ',')]
+    aws_cert_pins: Vec<CertPin>,
+
+    /// Role to assume with the given credentials before calling Route53, typically one in
+    /// the account that owns the hosted zone.
+    #[arg(long, env = "ASSUME_ROLE_ARN")]
+    assume_role_arn: Option<String>,
+
+    /// Session name for the assumed role, visible in CloudTrail. Defaults to route53_dd.
+    #[arg(long, env = "ROLE_SESSION_NAME", requires = "assume_role_arn")]
+    role_session_name: Option<String>,
+
+    /// External ID required by the role's trust policy, if any.
+    #[arg(long, env = "EXTERNAL_ID", requires = "assume_role_arn")]
+    external_id: Option<String>,
+
+    /// MFA device the role requires. The code is asked for on the terminal whenever the
+    /// role's session expires, unless --mfa-token-command provides it.
+    #[arg(long, env = "MFA_SERIAL", requires = "assume_role_arn")]
+    mfa_serial: Option<String>,
+
+    /// Shell command printing the current MFA code, e.g. from a password manager.
+    #[arg(long, env = "MFA_TOKEN_COMMAND", requires = "mfa_serial")]
+    mfa_token_command: Option<String>,
+
+    /// Also write every log record to this file as JSON lines, next to the console output.
+    #[arg(long, env = "JSON_LOG_FILE")]
+    json_log_file: Option<PathBuf>,
+
+    /// Read a JSON array of `{"name", "type", "value", "ttl"}` records from stdin and apply
+    /// them as one batch, then exit. A value of `detect` uses the detected address.
+    #[arg(long, env = "BULK_STDIN", default_value_t = false)]
+    bulk_stdin: bool,
+
+    /// Dump the daemon state as JSON when this signal is received (Unix only). With
+    /// several zones the states are keyed by record name.
+    #[arg(long, env = "DUMP_STATE_ON")]
+    dump_state_on: Option<DumpSignal>,
+
+    /// Keep the last pushed addresses and timestamps of every record in this JSON file, so
+    /// restarts and single-shot runs skip writes that wouldn't change anything.
+    #[arg(long, env = "STATE_FILE")]
+    state_file: Option<PathBuf>,
+
+    /// File to write state dumps to. Dumps are logged when unset.
+    #[arg(long, env = "STATE_DUMP_FILE")]
+    state_dump_file: Option<PathBuf>,
+
+    /// Same as --on-cgnat fail.
+    #[arg(
+        long,
+        env = "BLOCK_CGNAT",
+        default_value_t = false,
+        conflicts_with = "on_cgnat"
+    )]
+    block_cgnat: bool,
+
+    /// What to do when the detected IPv4 address is behind carrier-grade NAT, i.e. in
+    /// 100.64.0.0/10 or different from the router's WAN address. Defaults to `fail`, or
+    /// `warn` with --allow-private-addresses.
+    #[arg(long, env = "ON_CGNAT", value_enum)]
+    on_cgnat: Option<CgnatAction>,
+
+    /// Router IP source, e.g. `upnp`, `natpmp` or `fritzbox`, whose WAN address is
+    /// compared with the detected IPv4 address. A mismatch means another NAT sits between
+    /// the router and the internet.
+    #[arg(long, env = "CGNAT_CHECK_SOURCE")]
+    cgnat_check_source: Option<IpSource>,
+
+    /// In daemon mode, attempts at detection and at each write before a cycle fails. Waits
+    /// between attempts grow exponentially with jitter.
+    #[arg(long, env = "RETRY_MAX_ATTEMPTS", default_value_t = retry::DEFAULT_MAX_ATTEMPTS)]
+    retry_max_attempts: u32,
+
+    /// Stop retrying within a cycle once this many seconds have passed.
+    #[arg(long, env = "RETRY_MAX_ELAPSED_SECONDS", default_value_t = retry::DEFAULT_MAX_ELAPSED_SECONDS)]
+    retry_max_elapsed_seconds: u64,
+
+    /// Show a desktop notification when the public IP changes. Requires the
+    /// `desktop-notify` feature, otherwise the change is only logged.
+    #[arg(long, env = "DESKTOP_NOTIFY", default_value_t = false)]
+    desktop_notify: bool,
+
+    /// Push metrics to this StatsD/DogStatsD collector over UDP, e.g. `127.0.0.1:8125`.
+    #[arg(long, env = "STATSD_ADDR")]
+    statsd_addr: Option<String>,
+
+    /// On shutdown, wait this long for in-flight side effects such as change tracking and
+    /// log writes to finish before exiting.
+    #[arg(long, env = "SHUTDOWN_GRACE_SECONDS", default_value_t = 10)]
+    shutdown_grace_seconds: u64,
+
+    /// Poll each submitted Route53 change in the background and log when it is INSYNC.
+    /// Pending changes are listed in the state dump.
+    #[arg(long, env = "TRACK_SYNC", default_value_t = false)]
+    track_sync: bool,
+
+    /// Stop tracking a change that is still not INSYNC after this long, e.g. `10m`.
+    #[arg(long, env = "TRACK_SYNC_TIMEOUT", default_value = "10m", value_parser = humantime::parse_duration)]
+    track_sync_timeout: Duration,
+
+    /// Append published address changes to this file.
+    #[arg(long, env = "HISTORY_FILE", global = true)]
+    history_file: Option<PathBuf>,
 }
 
 #[allow(clippy::const_is_empty)]
@@ -83,28 +820,9 @@ fn version() -> &'static str {
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    SimpleLogger::new()
-        .with_level(log::LevelFilter::Info)
-        .init()
-        .unwrap();
-
-    let args = Args::parse();
-
-    let zone = HostedZoneConfig {
-        update_frequency_minutes: args.update_frequency_minutes,
-        zone_name: args.zone_name,
-        record_name: args.record_name,
-        ipv4: args.ipv4,
-        ipv6: args.ipv6,
-        region: args.region,
-        aws_credentials: AwsCredentials {
-            access_key_id: args.aws_access_key_id,
-            secret_access_key: args.aws_secret_access_key,
-            session_token: args.aws_session_token,
-            expires_after: None,
-        },
-        ttl_seconds: args.ttl_seconds,
-    };
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    logging::init(args.json_log_file.as_deref())?;
 
     let shutdown_token = tokio_util::sync::CancellationToken::new();
     let cloned_token = shutdown_token.clone();
@@ -114,134 +832,475 @@ async fn main() -> Result<(), Error> {
         cloned_token.cancel();
     });
 
-    daemon_update_zone(zone, args.daemon, shutdown_token).await
-}
+    let diff = match args.command {
+        Some(Command::Export { format, output }) => {
+            return export_history(format, output, args.history_file)
+        }
+        Some(Command::ServeIp {
+            bind,
+            trusted_proxies,
+        }) => return serve_ip::serve_ip(bind, trusted_proxies, shutdown_token).await,
+        Some(Command::Credentials { action, entry }) => {
+            return tokio::task::spawn_blocking(move || manage_credentials(action, &entry)).await?
+        }
+        Some(Command::Diff) => true,
+        None => false,
+    };
 
-async fn daemon_update_zone(
-    zone: HostedZoneConfig,
-    daemon: bool,
-    shutdown_token: CancellationToken,
-) -> Result<(), Error> {
-    if !daemon {
-        if let Err(e) = update_hosted_zone(zone.clone()).await {
-            error!("Error while updating zone {zone:?}: {e:?}");
-            return Err(e);
+    let zones = match &args.config {
+        Some(path) => {
+            if let Some(flag) = zone_flag(&matches) {
+                return Err(anyhow!(
+                    "--{flag} is a per-zone setting and can't be combined with --config, set it in {} instead.",
+                    path.display()
+                ));
+            }
+            config::Config::load(path).await?.zones
         }
-        return Ok(());
-    }
-    let mut interval = time::interval(Duration::from_secs(60 * zone.update_frequency_minutes));
-    loop {
-        select! {
-            _ = interval.tick() => {}
-            _ = shutdown_token.cancelled() => {
-                info!("{}.{} shutdown.", zone.record_name, zone.zone_name);
-                break Ok(())
+        None => {
+            let aws_access_key_id = secret(args.aws_access_key_id, args.aws_access_key_id_file)?;
+            let aws_secret_access_key =
+                secret(args.aws_secret_access_key, args.aws_secret_access_key_file)?;
+            let aws_session_token = secret(args.aws_session_token, args.aws_session_token_file)?;
+            let fritzbox_password = secret(args.fritzbox_password, args.fritzbox_password_file)?;
+            let zone = HostedZoneConfig {
+                update_frequency_minutes: args.update_frequency_minutes,
+                zone_name: args.zone_name.context("--zone-name is required")?,
+                zone_tag: args.zone_tag,
+                hosted_zone_id: args.hosted_zone_id,
+                zone_visibility: args.zone_visibility,
+                refresh_zone_id_every_seconds: args
+                    .refresh_zone_id_every
+                    .map(|every| every.as_secs()),
+                record_name: args.record_name,
+                record_name_is_fqdn: args.record_name_is_fqdn,
+                also_apex: args.also_apex,
+                also_www: args.also_www,
+                ipv4: args.ipv4,
+                ipv6: args.ipv6,
+                allow_ula: args.allow_ula,
+                allow_private_addresses: args.allow_private_addresses,
+                ipv6_prefix_length: args.ipv6_prefix_length,
+                ipv6_suffix: args.ipv6_suffix,
+                lan_hosts: args.lan_hosts.into_iter().collect(),
+                ip_provider: args.ip_provider,
+                ip_provider_timeout_seconds: args.ip_provider_timeout_seconds,
+                ip_consensus: args.ip_consensus,
+                ipv4_url: args.ipv4_url,
+                ipv6_url: args.ipv6_url,
+                ip_extract: args.ip_extract,
+                stun_servers: args.stun_servers,
+                nat_gateway: args.nat_gateway,
+                fritzbox: (args.fritzbox_url.is_some() || args.fritzbox_username.is_some()).then(
+                    || FritzBoxConfig {
+                        url: args.fritzbox_url.unwrap_or_else(fritzbox::default_url),
+                        username: args.fritzbox_username,
+                        password: fritzbox_password,
+                    },
+                ),
+                mikrotik: match (
+                    args.mikrotik_url,
+                    args.mikrotik_username,
+                    args.mikrotik_interface,
+                ) {
+                    (Some(url), Some(username), Some(interface)) => Some(MikrotikConfig {
+                        url,
+                        username,
+                        password: secret(
+                            Some(args.mikrotik_password),
+                            args.mikrotik_password_file,
+                        )?
+                        .unwrap_or_default(),
+                        interface,
+                    }),
+                    _ => None,
+                },
+                firewall: match (
+                    args.firewall_url,
+                    secret(args.firewall_api_key, args.firewall_api_key_file)?,
+                    args.firewall_interface,
+                ) {
+                    (Some(url), Some(api_key), Some(interface)) => Some(FirewallConfig {
+                        url,
+                        api_key,
+                        api_secret: secret(
+                            Some(args.firewall_api_secret),
+                            args.firewall_api_secret_file,
+                        )?
+                        .unwrap_or_default(),
+                        interface,
+                    }),
+                    _ => None,
+                },
+                interface: args.interface,
+                ipv6_prefer: args.ipv6_prefer,
+                watch_address_changes: args.watch_address_changes,
+                address_change_debounce_seconds: args.address_change_debounce_seconds,
+                ip_response_max_bytes: args.ip_response_max_bytes,
+                txt_template: args.txt_template,
+                change_comment: args.change_comment,
+                ip_from_header: args.ip_from_header,
+                ip_from_imds: args.ip_from_imds,
+                imds_endpoint: args.imds_endpoint,
+                primary_ip_source: args.primary_ip_source,
+                backup_ip_source: args.backup_ip_source,
+                detect_timeout_total_seconds: args.detect_timeout_total_seconds,
+                ip_doh_resolver: args.ip_doh_resolver,
+                region: args.region.unwrap_or_default(),
+                aws_credentials: aws_access_key_id.zip(aws_secret_access_key).map(
+                    |(access_key_id, secret_access_key)| AwsCredentials {
+                        access_key_id,
+                        secret_access_key,
+                        session_token: aws_session_token,
+                        expires_after: None,
+                    },
+                ),
+                aws_profile: args.aws_profile,
+                aws_credentials_secret: args.aws_credentials_secret,
+                aws_keyring_entry: args.aws_keyring_entry,
+                vault: match (args.vault_path, args.vault_addr) {
+                    (Some(path), Some(address)) => Some(VaultConfig {
+                        address,
+                        token: secret(args.vault_token, args.vault_token_file)?,
+                        role_id: args.vault_role_id,
+                        secret_id: secret(args.vault_secret_id, args.vault_secret_id_file)?,
+                        path,
+                        engine: args.vault_engine,
+                    }),
+                    _ => None,
+                },
+                assume_role: None,
+                source: None,
+                web_identity: args
+                    .web_identity_token_file
+                    .zip(args.web_identity_role_arn)
+                    .map(|(token_file, role_arn)| WebIdentityConfig {
+                        token_file,
+                        role_arn,
+                    }),
+                ttl_seconds: args.ttl_seconds,
+                adaptive_ttl: args.adaptive_ttl.then_some(AdaptiveTtl {
+                    min: args.ttl_min,
+                    max: args.ttl_max,
+                    ramp_seconds: args.ttl_ramp_seconds,
+                }),
+                zone_file: args.zone_file,
+                rfc2136: match (
+                    args.rfc2136_server,
+                    args.tsig_key_name,
+                    secret(args.tsig_secret, args.tsig_secret_file)?,
+                ) {
+                    (Some(server), Some(tsig_key_name), Some(tsig_secret)) => Some(Rfc2136Config {
+                        server,
+                        tsig_key_name,
+                        tsig_secret,
+                        tsig_algorithm: args.tsig_algorithm,
+                    }),
+                    _ => None,
+                },
+                preserve_other_types: args.preserve_other_types,
+                preserve_ttl: args.preserve_ttl,
+                seed_cache_from_dns: args.seed_cache_from_dns,
+                compare_dns: args.compare_dns,
+                compare_dns_resolver: args.compare_dns_resolver,
+                explain: args.explain,
+                multi_value: args.multi_value,
+                source_addresses: args.source_addresses,
+                startup_delay_seconds: args.startup_delay_seconds,
+                startup_jitter_seconds: args.startup_jitter_seconds,
+                min_change_interval_seconds: args.min_change_interval_seconds,
+                wait_for_sync_seconds: args.wait_for_sync_seconds,
+                assume_role_arn: args.assume_role_arn,
+                role_session_name: args.role_session_name,
+                external_id: args.external_id,
+                mfa_serial: args.mfa_serial,
+                mfa_token_command: args.mfa_token_command,
+                aws_cert_pins: args.aws_cert_pins,
+                cloudflare: secret(args.cloudflare_token, args.cloudflare_token_file)?.map(
+                    |api_token| CloudflareConfig {
+                        api_token,
+                        zone_id: args.cloudflare_zone_id,
+                        primary: args.cloudflare_only,
+                    },
+                ),
+                geolocation: match (
+                    args.set_identifier,
+                    args.geolocation_continent,
+                    args.geolocation_country,
+                ) {
+                    (None, None, None) => None,
+                    (set_identifier, continent, country) => Some(GeolocationRouting {
+                        continent,
+                        country,
+                        set_identifier: set_identifier.unwrap_or_default(),
+                    }),
+                },
+                reachability_check: args.reachability_check,
+                pause_file: args.pause_file,
+                desktop_notify: args.desktop_notify,
+                block_cgnat: args.block_cgnat,
+                on_cgnat: args.on_cgnat,
+                cgnat_check_source: args.cgnat_check_source,
+                retry_max_attempts: args.retry_max_attempts,
+                retry_max_elapsed_seconds: args.retry_max_elapsed_seconds,
+                failure_budget: args.failure_budget,
+                on_failure_budget_exhausted: args.on_failure_budget_exhausted,
+            };
+            vec![zone]
+        }
+    };
+    let mut records = Vec::with_capacity(zones.len());
+    for zone in &zones {
+        zone.validate()?;
+        for name in zone.record_names()? {
+            if records.contains(&name) {
+                return Err(anyhow!("{name} is configured more than once."));
             }
+            records.push(name);
         }
-        if let Err(e) = update_hosted_zone(zone.clone()).await {
-            error!("Error while updating zone {zone:?}: {e:?}");
-            error!("Trying again at {:?}", interval.period())
-        } else {
-            info!("Updating again at {:?}", interval.period())
+    }
+
+    if let Some(max_runtime) = args.max_runtime {
+        shut_down_after(max_runtime, shutdown_token.clone());
+    }
+
+    let history = args.history_file.map(History::new);
+    let notifier = Notifier::default();
+    let statsd = args
+        .statsd_addr
+        .as_deref()
+        .map(StatsdClient::new)
+        .transpose()?
+        .map(Arc::new);
+    // Every zone gets its own state, so one zone's update never looks like the previous
+    // address of another.
+    let state_file = args.state_file.map(StateFile::load).transpose()?;
+    let contexts = zones
+        .iter()
+        .map(|zone| {
+            let state = match &state_file {
+                Some(state_file) => state_file.restore(&zone.fqdn()?),
+                None => DaemonState::shared(),
+            };
+            Ok(UpdateContext {
+                daemon: args.daemon,
+                dry_run: args.dry_run,
+                state: state.clone(),
+                history: history.clone(),
+                statsd: statsd.clone(),
+                sync_tracker: args.track_sync.then(|| {
+                    SyncTracker::new(state, args.track_sync_timeout, shutdown_token.clone())
+                }),
+                state_file: state_file.clone(),
+                clients: ZoneClients::default(),
+                notifier: notifier.clone(),
+                shutdown: shutdown_token.clone(),
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let grace = Duration::from_secs(args.shutdown_grace_seconds);
+    if diff {
+        let changes = plan::run_diff(&zones, &contexts).await;
+        flush(&contexts, grace).await;
+        if changes? {
+            std::process::exit(2);
+        }
+        return Ok(());
+    }
+    if args.bulk_stdin {
+        let [zone] = zones.as_slice() else {
+            return Err(anyhow!("--bulk-stdin needs exactly one zone."));
         };
+        return bulk::apply_stdin(zone, &contexts[0]).await;
+    }
+    if let Some(signal) = args.dump_state_on {
+        let states = zones
+            .iter()
+            .zip(&contexts)
+            .map(|(zone, ctx)| Ok((zone.fqdn()?, ctx.state.clone())))
+            .collect::<Result<_, Error>>()?;
+        spawn_state_dumper(signal, args.state_dump_file, states)?;
     }
-}
 
-async fn update_hosted_zone(zone: HostedZoneConfig) -> Result<(), Error> {
-    info!("Updating hosted zone {:?}", &zone);
-    let rustls_connector = hyper_rustls::HttpsConnectorBuilder::new()
-        .with_webpki_roots()
-        .https_only()
-        .enable_http1()
-        .enable_http2()
-        .build();
-    let http_client = HyperClientBuilder::new().build(rustls_connector);
-    let config = aws_config::defaults(BehaviorVersion::latest())
-        .http_client(http_client)
-        .credentials_provider(zone.aws_credentials.clone())
-        .region(Region::new(zone.region.clone()))
-        .load()
-        .await;
-    let client = Client::new(&config);
-
-    let hosted_zones = client
-        .list_hosted_zones_by_name()
-        .dns_name(zone.zone_name.clone())
-        .send()
-        .await?;
-    let hosted_zone = hosted_zones
-        .hosted_zones
+    let tasks: Vec<_> = zones
         .into_iter()
-        .find(|_| true)
-        .ok_or(anyhow!("No hosted zone found."))?
-        .id;
-    info!("Found hosted zone id {hosted_zone}");
-
-    let mut record_changes: Vec<Change> = Vec::with_capacity(2);
-
-    if zone.ipv4 {
-        let web_client_ipv4 = reqwest::Client::builder()
-            .local_address("0.0.0.0".parse::<IpAddr>()?)
-            .build()?;
-        let ipv4_result = web_client_ipv4.get("https://ifconfig.me/ip").send().await;
-        let ipv4_result = ipv4_result?.error_for_status()?.text().await?;
-        info!("Found ipv4 address: {ipv4_result:?}");
-        let ipv4: IpAddr = ipv4_result.parse()?;
-        record_changes.push(
-            Change::builder()
-                .action(aws_sdk_route53::types::ChangeAction::Upsert)
-                .resource_record_set(
-                    ResourceRecordSet::builder()
-                        .name(format!("{}.{}", zone.record_name, zone.zone_name))
-                        .r#type(aws_sdk_route53::types::RrType::A)
-                        .ttl(zone.ttl_seconds)
-                        .resource_records(
-                            ResourceRecord::builder().value(ipv4.to_string()).build()?,
-                        )
-                        .build()?,
-                )
-                .build()?,
-        )
+        .zip(contexts.clone())
+        .map(|(zone, ctx)| {
+            let shutdown_token = shutdown_token.clone();
+            tokio::spawn(async move {
+                if args.daemon && args.restart_on_panic {
+                    supervise_zone(zone, ctx, shutdown_token).await
+                } else {
+                    daemon_update_zone(zone, args.daemon, ctx, shutdown_token).await
+                }
+            })
+        })
+        .collect();
+    let mut result = Ok(());
+    for task in tasks {
+        let outcome = match task.await {
+            Ok(outcome) => outcome,
+            Err(e) => Err(Error::new(e).context("A zone's task failed")),
+        };
+        if let Err(e) = outcome {
+            result = Err(e);
+        }
+    }
+    flush(&contexts, grace).await;
+    result
+}
+
+/// Arguments that apply to the whole run rather than to one zone, so they can be combined
+/// with --config.
+const RUN_ARGS: [&str; 15] = [
+    "daemon",
+    "dry_run",
+    "config",
+    "max_runtime",
+    "restart_on_panic",
+    "json_log_file",
+    "bulk_stdin",
+    "dump_state_on",
+    "state_file",
+    "state_dump_file",
+    "statsd_addr",
+    "shutdown_grace_seconds",
+    "track_sync",
+    "track_sync_timeout",
+    "history_file",
+];
+
+/// The long name of the first per-zone argument passed on the command line, which
+/// --config would silently ignore.
+fn zone_flag(matches: &ArgMatches) -> Option<String> {
+    Args::command()
+        .get_arguments()
+        .filter(|arg| !RUN_ARGS.contains(&arg.get_id().as_str()))
+        .find(|arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine))
+        .map(|arg| arg.get_long().unwrap_or(arg.get_id().as_str()).to_owned())
+}
+
+/// Finishes every zone's side effects that are still in flight, giving up after `grace`.
+async fn flush(contexts: &[UpdateContext], grace: Duration) {
+    let flush = async {
+        for ctx in contexts {
+            ctx.flush().await;
+        }
+    };
+    if time::timeout(grace, flush).await.is_err() {
+        warn!("Side effects were still in flight after the shutdown grace period of {grace:?}.");
     }
+}
 
-    if zone.ipv6 {
-        let web_client_ipv6 = reqwest::Client::builder()
-            .local_address("::".parse::<IpAddr>()?)
-            .build()?;
-
-        let ipv6_result = web_client_ipv6.get("https://ifconfig.me/ip").send().await;
-        let ipv6: IpAddr = ipv6_result?.error_for_status()?.text().await?.parse()?;
-        info!("Found ipv6 address: {ipv6:?}");
-        record_changes.push(
-            Change::builder()
-                .action(aws_sdk_route53::types::ChangeAction::Upsert)
-                .resource_record_set(
-                    ResourceRecordSet::builder()
-                        .name(format!("{}.{}", zone.record_name, zone.zone_name))
-                        .r#type(aws_sdk_route53::types::RrType::Aaaa)
-                        .ttl(zone.ttl_seconds)
-                        .resource_records(
-                            ResourceRecord::builder().value(ipv6.to_string()).build()?,
-                        )
-                        .build()?,
-                )
-                .build()?,
-        )
+/// The secret passed directly, or the contents of the file passed instead without the
+/// trailing newline.
+fn secret(value: Option<String>, file: Option<PathBuf>) -> Result<Option<String>, Error> {
+    match file {
+        Some(path) => {
+            let contents = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read secret from {}", path.display()))?;
+            Ok(Some(contents.trim_end_matches(['\r', '\n']).to_owned()))
+        }
+        None => Ok(value),
     }
+}
+
+#[cfg(feature = "keyring")]
+fn manage_credentials(action: CredentialsAction, entry: &str) -> Result<(), Error> {
+    use std::io::Write;
 
-    if !record_changes.is_empty() {
-        client
-            .change_resource_record_sets()
-            .hosted_zone_id(hosted_zone)
-            .change_batch(
-                ChangeBatch::builder()
-                    .set_changes(Some(record_changes))
-                    .build()?,
-            )
-            .send()
-            .await?;
+    use route53_dd::os_keyring;
+
+    match action {
+        CredentialsAction::Set => {
+            eprint!("AWS access key id: ");
+            std::io::stderr().flush()?;
+            let mut access_key_id = String::new();
+            std::io::stdin().read_line(&mut access_key_id)?;
+            let secret_access_key = rpassword::prompt_password("AWS secret access key: ")?;
+            let credentials = AwsCredentials {
+                access_key_id: access_key_id.trim().to_owned(),
+                secret_access_key: secret_access_key.trim().to_owned(),
+                session_token: None,
+                expires_after: None,
+            };
+            if credentials.access_key_id.is_empty() || credentials.secret_access_key.is_empty() {
+                return Err(anyhow!(
+                    "Both the access key id and the secret are required."
+                ));
+            }
+            os_keyring::store(entry, &credentials)?;
+            eprintln!("Stored in keyring entry {entry}. Use it with --aws-keyring-entry {entry}.");
+        }
+        CredentialsAction::Get => {
+            println!(
+                "{}",
+                os_keyring::credential_process_json(&os_keyring::load(entry)?)
+            );
+        }
+        CredentialsAction::Delete => os_keyring::delete(entry)?,
     }
-    info!("Finished updating hosted zone {zone:?}");
+    Ok(())
+}
 
+#[cfg(not(feature = "keyring"))]
+fn manage_credentials(_action: CredentialsAction, _entry: &str) -> Result<(), Error> {
+    Err(anyhow!(
+        "route53_dd was built without the `keyring` feature, rebuild it with \
+         `--features keyring` to keep AWS keys in the OS keyring."
+    ))
+}
+
+fn export_history(
+    format: ExportFormat,
+    output: Option<PathBuf>,
+    history_file: Option<PathBuf>,
+) -> Result<(), Error> {
+    let history = History::new(history_file.context("--history-file is required to export")?);
+    let entries = history.entries()?;
+    match (format, output) {
+        (ExportFormat::Csv, Some(path)) => history::write_csv(entries, File::create(path)?),
+        (ExportFormat::Csv, None) => history::write_csv(entries, std::io::stdout().lock()),
+    }
+}
+
+#[cfg(unix)]
+fn spawn_state_dumper(
+    signal: DumpSignal,
+    path: Option<PathBuf>,
+    states: Vec<(String, SharedState)>,
+) -> Result<(), Error> {
+    use tokio::signal::unix::{signal as unix_signal, SignalKind};
+    let kind = match signal {
+        DumpSignal::Sigusr1 => SignalKind::user_defined1(),
+        DumpSignal::Sigusr2 => SignalKind::user_defined2(),
+    };
+    let mut stream = unix_signal(kind)?;
+    tokio::spawn(async move {
+        while stream.recv().await.is_some() {
+            let result = state::snapshot(&states).and_then(|json| {
+                match &path {
+                    Some(path) => {
+                        std::fs::write(path, json)?;
+                        info!("Dumped state to {}", path.display());
+                    }
+                    None => info!("Current state:\n{json}"),
+                }
+                Ok(())
+            });
+            if let Err(e) = result {
+                error!("Failed to dump state: {e:?}");
+            }
+        }
+    });
     Ok(())
 }
+
+#[cfg(not(unix))]
+fn spawn_state_dumper(
+    _signal: DumpSignal,
+    _path: Option<PathBuf>,
+    _states: Vec<(String, SharedState)>,
+) -> Result<(), Error> {
+    Err(anyhow!("--dump-state-on is only supported on Unix."))
+}
@@ -0,0 +1,162 @@
+//! Assumes a role whose trust policy requires MFA, for running interactively or with a
+//! command that prints the one-time code.
+
+use std::{
+    io::{BufRead, IsTerminal, Write},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Context, Error};
+use aws_config::SdkConfig;
+use aws_credential_types::{
+    provider::{self, error::CredentialsError, future, ProvideCredentials},
+    Credentials,
+};
+use log::info;
+use tokio::sync::Mutex;
+
+/// Session credentials are renewed this long before they expire, so a request signed
+/// with them doesn't race the expiry.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// How long loading credentials may take when it includes asking for a code, for the
+/// identity cache's load timeout. Its default of five seconds is too short to type one.
+pub const PROMPT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Assumes a role whose trust policy requires MFA, asking for a one-time code from
+/// `token_command` or, without one, on the terminal. The session credentials are kept
+/// until they are about to expire, so a code is only needed once per session.
+#[derive(Debug)]
+pub struct MfaAssumeRoleProvider {
+    sts: aws_sdk_sts::Client,
+    role_arn: String,
+    session_name: String,
+    external_id: Option<String>,
+    serial: String,
+    token_command: Option<String>,
+    session: Mutex<Option<Credentials>>,
+}
+
+impl MfaAssumeRoleProvider {
+    /// `base` supplies the credentials used to call `sts:AssumeRole`.
+    pub fn new(
+        base: &SdkConfig,
+        role_arn: &str,
+        session_name: &str,
+        external_id: Option<&str>,
+        serial: &str,
+        token_command: Option<&str>,
+    ) -> Self {
+        Self {
+            sts: aws_sdk_sts::Client::new(base),
+            role_arn: role_arn.to_owned(),
+            session_name: session_name.to_owned(),
+            external_id: external_id.map(str::to_owned),
+            serial: serial.to_owned(),
+            token_command: token_command.map(str::to_owned),
+            session: Mutex::new(None),
+        }
+    }
+
+    async fn credentials(&self) -> provider::Result {
+        let mut session = self.session.lock().await;
+        if let Some(credentials) = session.as_ref().filter(|c| !expires_soon(c)) {
+            return Ok(credentials.clone());
+        }
+        let credentials = self
+            .assume_role()
+            .await
+            .map_err(CredentialsError::provider_error)?;
+        *session = Some(credentials.clone());
+        Ok(credentials)
+    }
+
+    async fn assume_role(&self) -> Result<Credentials, Error> {
+        let token = self.token().await?;
+        let output = self
+            .sts
+            .assume_role()
+            .role_arn(&self.role_arn)
+            .role_session_name(&self.session_name)
+            .set_external_id(self.external_id.clone())
+            .serial_number(&self.serial)
+            .token_code(token)
+            .send()
+            .await
+            .with_context(|| format!("Failed to assume role {} with MFA", self.role_arn))?;
+        let credentials = output
+            .credentials()
+            .ok_or(anyhow!("AssumeRole returned no credentials."))?;
+        let expiry = SystemTime::try_from(*credentials.expiration())?;
+        info!(
+            "Assumed role {} with MFA until {}",
+            self.role_arn,
+            humantime::format_rfc3339_seconds(expiry)
+        );
+        Ok(Credentials::new(
+            credentials.access_key_id(),
+            credentials.secret_access_key(),
+            Some(credentials.session_token().to_owned()),
+            Some(expiry),
+            "MfaAssumeRoleProvider",
+        ))
+    }
+
+    async fn token(&self) -> Result<String, Error> {
+        let token = match &self.token_command {
+            Some(command) => {
+                let output = tokio::process::Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .output()
+                    .await
+                    .with_context(|| format!("Failed to run MFA token command {command:?}"))?;
+                if !output.status.success() {
+                    return Err(anyhow!(
+                        "MFA token command {command:?} failed with {}",
+                        output.status
+                    ));
+                }
+                String::from_utf8(output.stdout).context("MFA token is not UTF-8")?
+            }
+            None => {
+                let serial = self.serial.clone();
+                tokio::task::spawn_blocking(move || prompt(&serial)).await??
+            }
+        };
+        let token = token.trim();
+        if token.len() != 6 || !token.chars().all(|c| c.is_ascii_digit()) {
+            return Err(anyhow!("MFA token must be six digits."));
+        }
+        Ok(token.to_owned())
+    }
+}
+
+impl ProvideCredentials for MfaAssumeRoleProvider {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(self.credentials())
+    }
+}
+
+fn expires_soon(credentials: &Credentials) -> bool {
+    credentials
+        .expiry()
+        .is_some_and(|expiry| expiry <= SystemTime::now() + EXPIRY_MARGIN)
+}
+
+fn prompt(serial: &str) -> Result<String, Error> {
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        return Err(anyhow!(
+            "An MFA token for {serial} is needed but stdin is not a terminal. Pass --mfa-token-command instead."
+        ));
+    }
+    eprint!("MFA token for {serial}: ");
+    std::io::stderr().flush()?;
+    let mut token = String::new();
+    stdin.lock().read_line(&mut token)?;
+    Ok(token)
+}
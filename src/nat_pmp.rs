@@ -0,0 +1,127 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Error};
+use tokio::{net::UdpSocket, time};
+
+const PORT: u16 = 5351;
+const NAT_PMP_VERSION: u8 = 0;
+const PCP_VERSION: u8 = 2;
+const PCP_MAP: u8 = 1;
+const UDP: u8 = 17;
+/// Long enough for the gateway to answer, the mapping is deleted right after.
+const PCP_MAPPING_LIFETIME: u32 = 60;
+/// RFC 6886 retransmits after 250ms and doubles the wait each time.
+const INITIAL_RETRANSMIT: Duration = Duration::from_millis(250);
+
+/// Asks the gateway for its external address with a NAT-PMP (RFC 6886) request.
+pub async fn nat_pmp_address(
+    gateway: Option<Ipv4Addr>,
+    timeout: Duration,
+) -> Result<IpAddr, Error> {
+    let socket = connect(gateway).await?;
+    let response = exchange(&socket, &[NAT_PMP_VERSION, 0], timeout).await?;
+    if response.len() < 12 || response[0] != NAT_PMP_VERSION || response[1] != 128 {
+        return Err(anyhow!("Invalid NAT-PMP response."));
+    }
+    check_result(u16::from_be_bytes([response[2], response[3]]))?;
+    let octets: [u8; 4] = response[8..12].try_into()?;
+    Ok(IpAddr::V4(Ipv4Addr::from(octets)))
+}
+
+/// Asks the gateway for its external address with PCP (RFC 6887). PCP has no request
+/// for just the address, so a short-lived UDP mapping is created and deleted again.
+pub async fn pcp_address(gateway: Option<Ipv4Addr>, timeout: Duration) -> Result<IpAddr, Error> {
+    let socket = connect(gateway).await?;
+    let local = socket.local_addr()?;
+    let nonce: [u8; 12] = std::array::from_fn(|_| fastrand::u8(..));
+    let request = |lifetime: u32| {
+        let client = match local.ip() {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            IpAddr::V6(ip) => ip,
+        };
+        let mut request = vec![PCP_VERSION, PCP_MAP, 0, 0];
+        request.extend_from_slice(&lifetime.to_be_bytes());
+        request.extend_from_slice(&client.octets());
+        request.extend_from_slice(&nonce);
+        request.extend_from_slice(&[UDP, 0, 0, 0]);
+        request.extend_from_slice(&local.port().to_be_bytes());
+        request.extend_from_slice(&local.port().to_be_bytes());
+        // An IPv4 mapping must suggest the IPv4-mapped unspecified address (RFC 6887
+        // section 11.1), `::` would ask for an IPv6 one.
+        request.extend_from_slice(&Ipv4Addr::UNSPECIFIED.to_ipv6_mapped().octets());
+        request
+    };
+    let response = exchange(&socket, &request(PCP_MAPPING_LIFETIME), timeout).await?;
+    if response.len() < 60 || response[0] != PCP_VERSION || response[1] != (0x80 | PCP_MAP) {
+        return Err(anyhow!("Invalid PCP response."));
+    }
+    check_result(response[3].into())?;
+    if response[24..36] != nonce {
+        return Err(anyhow!("PCP response doesn't match the request."));
+    }
+    let octets: [u8; 16] = response[44..60].try_into()?;
+    let external = Ipv6Addr::from(octets);
+    // Best effort, the mapping expires on its own anyway.
+    let _ = socket.send(&request(0)).await;
+    Ok(external
+        .to_ipv4_mapped()
+        .map(IpAddr::V4)
+        .unwrap_or(IpAddr::V6(external)))
+}
+
+async fn connect(gateway: Option<Ipv4Addr>) -> Result<UdpSocket, Error> {
+    let gateway = match gateway {
+        Some(gateway) => gateway,
+        None => default_gateway()?,
+    };
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket
+        .connect(SocketAddr::new(IpAddr::V4(gateway), PORT))
+        .await?;
+    Ok(socket)
+}
+
+/// Sends `request` until the gateway answers, backing off between retransmissions.
+async fn exchange(socket: &UdpSocket, request: &[u8], timeout: Duration) -> Result<Vec<u8>, Error> {
+    let attempts = async {
+        let mut wait = INITIAL_RETRANSMIT;
+        let mut response = [0u8; 1100];
+        loop {
+            socket.send(request).await?;
+            if let Ok(received) = time::timeout(wait, socket.recv(&mut response)).await {
+                return Ok::<_, Error>(response[..received?].to_vec());
+            }
+            wait *= 2;
+        }
+    };
+    time::timeout(timeout, attempts)
+        .await
+        .map_err(|_| anyhow!("The gateway did not answer within {timeout:?}."))?
+}
+
+fn check_result(code: u16) -> Result<(), Error> {
+    match code {
+        0 => Ok(()),
+        code => Err(anyhow!(
+            "The gateway refused the request with result code {code}."
+        )),
+    }
+}
+
+/// The IPv4 default gateway from the kernel's routing table.
+fn default_gateway() -> Result<Ipv4Addr, Error> {
+    let routes = std::fs::read_to_string("/proc/net/route")
+        .context("Can't read the default gateway, set --nat-gateway")?;
+    routes
+        .lines()
+        .skip(1)
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .find(|fields| fields.get(1) == Some(&"00000000"))
+        .and_then(|fields| u32::from_str_radix(fields.get(2)?, 16).ok())
+        // The kernel prints the address in network byte order as a native integer.
+        .map(|gateway| Ipv4Addr::from(gateway.to_ne_bytes()))
+        .ok_or(anyhow!("No default gateway found, set --nat-gateway."))
+}
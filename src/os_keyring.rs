@@ -0,0 +1,100 @@
+//! AWS keys kept in the platform keyring: the Secret Service on Linux, the Keychain on
+//! macOS and the Credential Manager on Windows.
+
+use anyhow::{anyhow, Context, Error};
+use aws_credential_types::{
+    provider::{self, error::CredentialsError, future, ProvideCredentials},
+    Credentials,
+};
+use keyring::Entry;
+use serde_json::json;
+
+use crate::credential_provider::AwsCredentials;
+
+/// The keyring service every entry is stored under.
+pub const SERVICE: &str = "route53_dd";
+
+fn entry(name: &str) -> Result<Entry, Error> {
+    Entry::new(SERVICE, name).with_context(|| format!("Failed to open keyring entry {name}"))
+}
+
+pub fn store(name: &str, credentials: &AwsCredentials) -> Result<(), Error> {
+    let value = json!({
+        "access_key_id": credentials.access_key_id,
+        "secret_access_key": credentials.secret_access_key,
+        "session_token": credentials.session_token,
+    });
+    entry(name)?
+        .set_password(&value.to_string())
+        .with_context(|| format!("Failed to store keyring entry {name}"))
+}
+
+pub fn load(name: &str) -> Result<AwsCredentials, Error> {
+    let value = entry(name)?.get_password().map_err(|err| match err {
+        keyring::Error::NoEntry => anyhow!(
+            "The keyring has no AWS credentials named {name}. Store them with `route53_dd credentials set`."
+        ),
+        err => Error::new(err).context(format!("Failed to read keyring entry {name}")),
+    })?;
+    serde_json::from_str(&value)
+        .with_context(|| format!("Keyring entry {name} is not a set of AWS keys"))
+}
+
+pub fn delete(name: &str) -> Result<(), Error> {
+    entry(name)?
+        .delete_credential()
+        .with_context(|| format!("Failed to delete keyring entry {name}"))
+}
+
+/// The entry in the `credential_process` format, so the AWS CLI and SDKs can read it too.
+pub fn credential_process_json(credentials: &AwsCredentials) -> String {
+    let mut value = json!({
+        "Version": 1,
+        "AccessKeyId": credentials.access_key_id,
+        "SecretAccessKey": credentials.secret_access_key,
+    });
+    if let Some(session_token) = &credentials.session_token {
+        value["SessionToken"] = json!(session_token);
+    }
+    value.to_string()
+}
+
+/// Reads the zone's keys from the keyring entry whenever the SDK's credential cache
+/// wants fresh ones.
+#[derive(Debug)]
+pub struct KeyringCredentials {
+    name: String,
+}
+
+impl KeyringCredentials {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+        }
+    }
+
+    async fn provide(&self) -> provider::Result {
+        let name = self.name.clone();
+        // The platform keyrings block, and the Secret Service may wait for an unlock.
+        let credentials = tokio::task::spawn_blocking(move || load(&name))
+            .await
+            .map_err(CredentialsError::provider_error)?
+            .map_err(CredentialsError::provider_error)?;
+        Ok(Credentials::new(
+            credentials.access_key_id,
+            credentials.secret_access_key,
+            credentials.session_token,
+            None,
+            "Keyring",
+        ))
+    }
+}
+
+impl ProvideCredentials for KeyringCredentials {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(self.provide())
+    }
+}
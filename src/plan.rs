@@ -0,0 +1,169 @@
+use std::fmt::Display;
+
+use anyhow::Error;
+
+use crate::{
+    detect_publishable, primary_provider,
+    providers::{values_match, DnsProvider, RecordSpec, RecordType},
+    secondary_providers, HostedZoneConfig, UpdateContext,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlanStatus {
+    InSync,
+    Change,
+    Missing,
+}
+
+/// The desired value of one record next to what is currently published.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlannedRecord {
+    pub name: String,
+    pub record_type: RecordType,
+    pub current: Option<Vec<String>>,
+    pub desired: String,
+    /// The backend mirroring the record, `None` for the primary one.
+    pub secondary: Option<String>,
+}
+
+impl PlannedRecord {
+    pub fn status(&self) -> PlanStatus {
+        match &self.current {
+            None => PlanStatus::Missing,
+            Some(current) if current.len() == 1 && values_match(&current[0], &self.desired) => {
+                PlanStatus::InSync
+            }
+            Some(_) => PlanStatus::Change,
+        }
+    }
+}
+
+impl Display for PlannedRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (name, record_type, desired) = (&self.name, self.record_type, &self.desired);
+        match (self.status(), &self.current) {
+            (PlanStatus::InSync, _) => write!(f, "  in sync  {name} {record_type} {desired}")?,
+            (PlanStatus::Change, Some(current)) => write!(
+                f,
+                "~ change   {name} {record_type} {} -> {desired}",
+                current.join(",")
+            )?,
+            _ => write!(f, "+ missing  {name} {record_type} -> {desired}")?,
+        }
+        match &self.secondary {
+            Some(backend) => write!(f, " (at {backend})"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Detects the desired values for the zone's record and reads the published ones,
+/// without writing anything.
+pub async fn plan(
+    zone: &HostedZoneConfig,
+    ctx: &UpdateContext,
+) -> Result<Vec<PlannedRecord>, Error> {
+    let provider = primary_provider(zone, ctx).await?;
+    let secondaries = secondary_providers(zone, &ctx.clients);
+    let names = zone.record_names()?;
+    let (addresses, _) = detect_publishable(zone, ctx).await?;
+    let records: Vec<RecordSpec> = addresses
+        .into_iter()
+        .flat_map(|ip| {
+            names
+                .iter()
+                .map(move |name| RecordSpec::for_ip(name, ip, zone.ttl_seconds))
+        })
+        .collect();
+    let secondaries: Vec<&dyn DnsProvider> = secondaries.iter().map(|p| p.as_ref()).collect();
+    plan_records(provider.as_ref(), &secondaries, &records).await
+}
+
+/// Reads what `primary` and every secondary backend publish for `records`.
+pub(crate) async fn plan_records(
+    primary: &dyn DnsProvider,
+    secondaries: &[&dyn DnsProvider],
+    records: &[RecordSpec],
+) -> Result<Vec<PlannedRecord>, Error> {
+    let mut planned = Vec::new();
+    let backends = std::iter::once((primary, None))
+        .chain(secondaries.iter().map(|p| (*p, Some(p.name().to_owned()))));
+    for (provider, secondary) in backends {
+        for record in records {
+            let current = provider
+                .current_values(&record.name, record.record_type)
+                .await?;
+            planned.push(PlannedRecord {
+                name: record.name.clone(),
+                record_type: record.record_type,
+                current,
+                desired: record.value.clone(),
+                secondary: secondary.clone(),
+            });
+        }
+    }
+    Ok(planned)
+}
+
+/// Prints the plan for every zone and returns whether anything would change. Each zone
+/// is planned with its own context, so zones in different AWS accounts are read with
+/// their own credentials.
+pub async fn run_diff(
+    zones: &[HostedZoneConfig],
+    contexts: &[UpdateContext],
+) -> Result<bool, Error> {
+    let mut planned = Vec::new();
+    for (zone, ctx) in zones.iter().zip(contexts) {
+        planned.extend(plan(zone, ctx).await?);
+    }
+    for record in &planned {
+        println!("{record}");
+    }
+    Ok(planned
+        .iter()
+        .any(|record| record.status() != PlanStatus::InSync))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn planned(current: Option<&[&str]>, desired: &str) -> PlannedRecord {
+        PlannedRecord {
+            name: "home.example.com".to_owned(),
+            record_type: RecordType::Aaaa,
+            current: current.map(|values| values.iter().map(|v| v.to_string()).collect()),
+            desired: desired.to_owned(),
+            secondary: None,
+        }
+    }
+
+    #[test]
+    fn status_compares_parsed_values() {
+        let record = planned(Some(&["2001:DB8:0::1"]), "2001:db8::1");
+        assert_eq!(record.status(), PlanStatus::InSync);
+        assert_eq!(
+            planned(Some(&["2001:db8::2"]), "2001:db8::1").status(),
+            PlanStatus::Change
+        );
+        assert_eq!(planned(None, "2001:db8::1").status(), PlanStatus::Missing);
+    }
+
+    #[test]
+    fn extra_values_are_a_change() {
+        let record = planned(Some(&["2001:db8::1", "2001:db8::2"]), "2001:db8::1");
+        assert_eq!(record.status(), PlanStatus::Change);
+        assert_eq!(
+            record.to_string(),
+            "~ change   home.example.com AAAA 2001:db8::1,2001:db8::2 -> 2001:db8::1"
+        );
+        let mirrored = PlannedRecord {
+            secondary: Some("cloudflare".to_owned()),
+            ..planned(None, "2001:db8::1")
+        };
+        assert_eq!(
+            mirrored.to_string(),
+            "+ missing  home.example.com AAAA -> 2001:db8::1 (at cloudflare)"
+        );
+    }
+}
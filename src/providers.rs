@@ -0,0 +1,182 @@
+use std::{fmt::Display, net::IpAddr, str::FromStr};
+
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+
+pub mod cloudflare;
+pub mod rfc2136;
+pub mod route53;
+pub mod zone_file;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RecordType {
+    A,
+    Aaaa,
+    Txt,
+}
+
+impl RecordType {
+    pub fn for_ip(ip: &IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(_) => RecordType::A,
+            IpAddr::V6(_) => RecordType::Aaaa,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecordType::A => "A",
+            RecordType::Aaaa => "AAAA",
+            RecordType::Txt => "TXT",
+        }
+    }
+}
+
+impl FromStr for RecordType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "A" => Ok(RecordType::A),
+            "AAAA" => Ok(RecordType::Aaaa),
+            "TXT" => Ok(RecordType::Txt),
+            _ => Err(anyhow!(
+                "Unsupported record type {s:?}, expected A, AAAA or TXT."
+            )),
+        }
+    }
+}
+
+impl Display for RecordType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A single record value to publish, independent of the DNS backend.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordSpec {
+    pub name: String,
+    pub record_type: RecordType,
+    pub value: String,
+    pub ttl: i64,
+}
+
+impl RecordSpec {
+    pub fn for_ip(name: &str, ip: IpAddr, ttl: i64) -> Self {
+        Self {
+            name: name.to_owned(),
+            record_type: RecordType::for_ip(&ip),
+            value: ip.to_string(),
+            ttl,
+        }
+    }
+
+    /// A TXT record holding `text` as a single quoted string.
+    pub fn txt(name: &str, text: &str, ttl: i64) -> Self {
+        Self {
+            name: name.to_owned(),
+            record_type: RecordType::Txt,
+            value: format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\"")),
+            ttl,
+        }
+    }
+}
+
+/// Whether a published value equals the desired one. Addresses are compared by value
+/// and host names, such as CNAME targets, ignore case and a trailing dot.
+pub fn values_match(current: &str, desired: &str) -> bool {
+    match (current.parse::<IpAddr>(), desired.parse::<IpAddr>()) {
+        (Ok(current), Ok(desired)) => current == desired,
+        _ => current
+            .trim_end_matches('.')
+            .eq_ignore_ascii_case(desired.trim_end_matches('.')),
+    }
+}
+
+/// Groups records sharing a name and type, so they can be written as one multi-value set.
+pub fn group_record_sets(records: &[RecordSpec]) -> Vec<(&RecordSpec, Vec<&str>)> {
+    let mut sets: Vec<(&RecordSpec, Vec<&str>)> = Vec::new();
+    for record in records {
+        match sets
+            .iter_mut()
+            .find(|(first, _)| first.name == record.name && first.record_type == record.record_type)
+        {
+            Some((_, values)) => values.push(&record.value),
+            None => sets.push((record, vec![&record.value])),
+        }
+    }
+    sets
+}
+
+/// A DNS backend that records can be written to.
+#[async_trait]
+pub trait DnsProvider: Send + Sync {
+    /// Short name used in logs.
+    fn name(&self) -> &str;
+
+    /// Reads the values currently published for `name`, or `None` if the record does not exist.
+    async fn current_values(
+        &self,
+        name: &str,
+        record_type: RecordType,
+    ) -> Result<Option<Vec<String>>, Error> {
+        Err(anyhow!("{} can't read {name} {record_type}.", self.name()))
+    }
+
+    /// Looks up the zone the records are written to and returns how the backend knows
+    /// it, e.g. the Route53 hosted zone id. Fails when there is no such zone.
+    async fn find_zone(&self) -> Result<String, Error>;
+
+    /// Refuses to write next to record types that conflict with `records` and logs the
+    /// ones left alone.
+    async fn check_other_types(&self, _records: &[RecordSpec]) -> Result<(), Error> {
+        Err(anyhow!("{} can't list other record types.", self.name()))
+    }
+
+    /// Replaces each record's TTL with the one already published.
+    async fn preserve_ttls(&self, _records: &mut [RecordSpec]) -> Result<(), Error> {
+        Err(anyhow!("{} can't read published TTLs.", self.name()))
+    }
+
+    async fn upsert(&self, record: &RecordSpec) -> Result<(), Error>;
+
+    /// Writes several records. Backends that support batching override this.
+    async fn upsert_all(&self, records: &[RecordSpec]) -> Result<(), Error> {
+        for record in records {
+            self.upsert(record).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes several records like [`upsert_all`](Self::upsert_all), without waiting for
+    /// the write to be served. Returns the ID to pass to
+    /// [`wait_for_sync`](Self::wait_for_sync) when the backend propagates changes later.
+    async fn submit_all(&self, records: &[RecordSpec]) -> Result<Option<String>, Error> {
+        self.upsert_all(records).await?;
+        Ok(None)
+    }
+
+    /// Waits until the change returned by [`submit_all`](Self::submit_all) is served.
+    async fn wait_for_sync(&self, _change_id: &str) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_names_ignore_case_and_trailing_dot() {
+        assert!(values_match("Target.Example.com.", "target.example.com"));
+        assert!(!values_match("target.example.com", "other.example.com"));
+    }
+
+    #[test]
+    fn addresses_are_compared_parsed() {
+        assert!(values_match("2001:DB8:0:0::1", "2001:db8::1"));
+        assert!(!values_match("2001:db8::1", "2001:db8::2"));
+        assert!(values_match("8.8.8.8", "8.8.8.8"));
+    }
+}
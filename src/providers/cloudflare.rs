@@ -0,0 +1,285 @@
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use log::info;
+use serde::{Deserialize, Serialize};
+use tokio::sync::OnceCell;
+
+use super::{values_match, DnsProvider, RecordSpec, RecordType};
+
+const API_BASE: &str = "https://api.cloudflare.com/client/v4";
+
+#[derive(Deserialize, Clone)]
+pub struct CloudflareConfig {
+    pub api_token: String,
+    /// Looked up from the zone name when unset.
+    #[serde(default)]
+    pub zone_id: Option<String>,
+    /// Keep the record only on Cloudflare instead of mirroring Route53.
+    #[serde(default)]
+    pub primary: bool,
+}
+
+impl std::fmt::Debug for CloudflareConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CloudflareConfig")
+            .field("api_token", &"********")
+            .field("zone_id", &self.zone_id)
+            .field("primary", &self.primary)
+            .finish()
+    }
+}
+
+#[derive(Deserialize)]
+struct ApiResponse<T> {
+    success: bool,
+    #[serde(default)]
+    errors: Vec<ApiError>,
+    result: Option<T>,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    code: i64,
+    message: String,
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+#[derive(Deserialize)]
+struct Zone {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct DnsRecord {
+    id: String,
+    content: String,
+    ttl: i64,
+}
+
+#[derive(Serialize)]
+struct DnsRecordBody<'a> {
+    #[serde(rename = "type")]
+    record_type: &'a str,
+    name: &'a str,
+    content: &'a str,
+    ttl: i64,
+    proxied: bool,
+}
+
+/// Writes records through the Cloudflare v4 API using an API token.
+#[derive(Clone, Debug)]
+pub struct CloudflareProvider {
+    config: CloudflareConfig,
+    zone_name: String,
+    zone_id: OnceCell<String>,
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl CloudflareProvider {
+    pub fn new(config: CloudflareConfig, zone_name: &str) -> Self {
+        Self {
+            zone_id: OnceCell::new_with(config.zone_id.clone()),
+            config,
+            zone_name: zone_name.trim_end_matches('.').to_ascii_lowercase(),
+            client: reqwest::Client::new(),
+            base_url: API_BASE.to_owned(),
+        }
+    }
+
+    /// The configured zone id, or the id of the zone named like ours.
+    async fn zone_id(&self) -> Result<&str, Error> {
+        let id = self
+            .zone_id
+            .get_or_try_init(|| async {
+                let request = self
+                    .client
+                    .get(format!("{}/zones", self.base_url))
+                    .query(&[("name", self.zone_name.as_str())]);
+                let zones: Option<Vec<Zone>> = self.call(request).await?;
+                let zone = zones.unwrap_or_default().into_iter().next().ok_or(anyhow!(
+                    "No Cloudflare zone named {} is visible to the API token.",
+                    self.zone_name
+                ))?;
+                info!("Found Cloudflare zone id {}", zone.id);
+                Ok::<_, Error>(zone.id)
+            })
+            .await?;
+        Ok(id)
+    }
+
+    async fn records_url(&self) -> Result<String, Error> {
+        Ok(format!(
+            "{}/zones/{}/dns_records",
+            self.base_url,
+            self.zone_id().await?
+        ))
+    }
+
+    async fn call<T: for<'de> Deserialize<'de>>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<Option<T>, Error> {
+        let body = request
+            .bearer_auth(&self.config.api_token)
+            .send()
+            .await?
+            .text()
+            .await?;
+        let response: ApiResponse<T> = serde_json::from_str(&body)?;
+        if !response.success {
+            let errors: Vec<String> = response.errors.iter().map(|e| e.to_string()).collect();
+            return Err(anyhow!("Cloudflare API error: {}", errors.join(", ")));
+        }
+        Ok(response.result)
+    }
+
+    async fn find_records(
+        &self,
+        name: &str,
+        record_type: RecordType,
+    ) -> Result<Vec<DnsRecord>, Error> {
+        let request = self
+            .client
+            .get(self.records_url().await?)
+            .query(&[("type", record_type.as_str()), ("name", name)]);
+        let records: Option<Vec<DnsRecord>> = self.call(request).await?;
+        Ok(records.unwrap_or_default())
+    }
+}
+
+#[async_trait]
+impl DnsProvider for CloudflareProvider {
+    fn name(&self) -> &str {
+        "cloudflare"
+    }
+
+    async fn find_zone(&self) -> Result<String, Error> {
+        Ok(self.zone_id().await?.to_owned())
+    }
+
+    async fn current_values(
+        &self,
+        name: &str,
+        record_type: RecordType,
+    ) -> Result<Option<Vec<String>>, Error> {
+        let records = self.find_records(name, record_type).await?;
+        Ok((!records.is_empty()).then(|| records.into_iter().map(|r| r.content).collect()))
+    }
+
+    async fn upsert(&self, record: &RecordSpec) -> Result<(), Error> {
+        let body = DnsRecordBody {
+            record_type: record.record_type.as_str(),
+            name: &record.name,
+            content: &record.value,
+            ttl: record.ttl,
+            proxied: false,
+        };
+        let existing = self
+            .find_records(&record.name, record.record_type)
+            .await?
+            .into_iter()
+            .next();
+        let records_url = self.records_url().await?;
+        let request = match existing {
+            // Addresses are compared parsed, so other spellings of the same IPv6 address
+            // don't count as a change.
+            Some(existing)
+                if values_match(&existing.content, &record.value) && existing.ttl == record.ttl =>
+            {
+                return Ok(());
+            }
+            Some(existing) => self.client.put(format!("{records_url}/{}", existing.id)),
+            None => self.client.post(records_url),
+        };
+        self.call::<serde_json::Value>(request.json(&body)).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::http_server;
+
+    fn provider(base_url: String) -> CloudflareProvider {
+        let config = CloudflareConfig {
+            api_token: "token".to_owned(),
+            zone_id: None,
+            primary: false,
+        };
+        CloudflareProvider {
+            base_url,
+            ..CloudflareProvider::new(config, "Example.com.")
+        }
+    }
+
+    #[tokio::test]
+    async fn creates_a_missing_record_in_the_zone_found_by_name() {
+        let (url, requests) = http_server(|request| {
+            let result = match (request.method.as_str(), request.target.as_str()) {
+                ("GET", "/zones?name=example.com") => r#"[{"id": "zone1"}]"#,
+                ("GET", _) => "[]",
+                _ => "{}",
+            };
+            (200, format!(r#"{{"success": true, "result": {result}}}"#))
+        })
+        .await;
+        let record = RecordSpec::for_ip("home.example.com", "8.8.8.8".parse().unwrap(), 300);
+        provider(url).upsert(&record).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        let create = requests.last().unwrap();
+        assert_eq!(create.method, "POST");
+        assert_eq!(create.target, "/zones/zone1/dns_records");
+        assert!(
+            create.body.contains(r#""content":"8.8.8.8""#),
+            "{}",
+            create.body
+        );
+    }
+
+    #[tokio::test]
+    async fn leaves_an_equal_record_alone() {
+        let (url, requests) = http_server(|request| {
+            let result = match request.method.as_str() {
+                "GET" => r#"[{"id": "r1", "content": "2001:4860:0:0::8888", "ttl": 300}]"#,
+                _ => "{}",
+            };
+            (200, format!(r#"{{"success": true, "result": {result}}}"#))
+        })
+        .await;
+        let mut provider = provider(url);
+        provider.zone_id = OnceCell::new_with(Some("zone1".to_owned()));
+        let ip = "2001:4860::8888".parse().unwrap();
+        provider
+            .upsert(&RecordSpec::for_ip("home.example.com", ip, 300))
+            .await
+            .unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert!(requests.iter().all(|request| request.method == "GET"));
+    }
+
+    #[tokio::test]
+    async fn reports_api_errors() {
+        let (url, _) = http_server(|_| {
+            let body = r#"{"success": false, "errors": [{"code": 9109, "message": "Invalid access token"}]}"#;
+            (403, body.to_owned())
+        })
+        .await;
+        let err = provider(url)
+            .current_values("home.example.com", RecordType::A)
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("9109: Invalid access token"),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,265 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::ValueEnum;
+use hickory_client::{
+    client::{AsyncClient, ClientHandle},
+    op::{Message, MessageType, OpCode, Query, ResponseCode, UpdateMessage},
+    proto::{
+        rr::dnssec::{rdata::tsig::TsigAlgorithm, tsig::TSigner},
+        xfer::{DnsHandle, FirstAnswer},
+    },
+    rr::{
+        rdata::{A, AAAA, NULL},
+        DNSClass, Name, RData, Record, RecordSet,
+    },
+    udp::UdpClientStream,
+};
+use serde::Deserialize;
+use tokio::net::UdpSocket;
+
+use super::{group_record_sets, DnsProvider, RecordSpec, RecordType};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Allowed clock skew between us and the server when validating TSIG signatures.
+const TSIG_FUDGE: u16 = 300;
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TsigAlgorithmName {
+    #[default]
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Rfc2136Config {
+    pub server: SocketAddr,
+    pub tsig_key_name: String,
+    /// Base64 encoded shared secret, as found in BIND's `key` statement.
+    pub tsig_secret: String,
+    #[serde(default)]
+    pub tsig_algorithm: TsigAlgorithmName,
+}
+
+impl std::fmt::Debug for Rfc2136Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Rfc2136Config")
+            .field("server", &self.server)
+            .field("tsig_key_name", &self.tsig_key_name)
+            .field("tsig_secret", &"********")
+            .field("tsig_algorithm", &self.tsig_algorithm)
+            .finish()
+    }
+}
+
+impl Rfc2136Config {
+    fn signer(&self) -> Result<TSigner, Error> {
+        let key = STANDARD
+            .decode(self.tsig_secret.trim())
+            .map_err(|e| anyhow!("TSIG secret is not valid base64: {e}"))?;
+        let algorithm = match self.tsig_algorithm {
+            TsigAlgorithmName::HmacSha256 => TsigAlgorithm::HmacSha256,
+            TsigAlgorithmName::HmacSha384 => TsigAlgorithm::HmacSha384,
+            TsigAlgorithmName::HmacSha512 => TsigAlgorithm::HmacSha512,
+        };
+        Ok(TSigner::new(
+            key,
+            algorithm,
+            Name::from_ascii(&self.tsig_key_name)?,
+            TSIG_FUDGE,
+        )?)
+    }
+}
+
+/// Sends TSIG signed RFC 2136 dynamic updates to a standards-compliant name server,
+/// such as BIND, Knot or PowerDNS.
+#[derive(Clone, Debug)]
+pub struct Rfc2136Provider {
+    config: Rfc2136Config,
+    zone: String,
+}
+
+impl Rfc2136Provider {
+    pub fn new(config: Rfc2136Config, zone: &str) -> Self {
+        Self {
+            config,
+            zone: zone.to_owned(),
+        }
+    }
+
+    async fn client(&self) -> Result<AsyncClient, Error> {
+        let stream = UdpClientStream::<UdpSocket, TSigner>::with_timeout_and_signer(
+            self.config.server,
+            TIMEOUT,
+            Some(Arc::new(self.config.signer()?)),
+        );
+        let (client, background) = AsyncClient::connect(stream).await?;
+        tokio::spawn(background);
+        Ok(client)
+    }
+}
+
+fn absolute(name: &str) -> Result<Name, Error> {
+    Ok(Name::from_ascii(format!(
+        "{}.",
+        name.trim_end_matches('.')
+    ))?)
+}
+
+fn hickory_type(record_type: RecordType) -> hickory_client::rr::RecordType {
+    match record_type {
+        RecordType::A => hickory_client::rr::RecordType::A,
+        RecordType::Aaaa => hickory_client::rr::RecordType::AAAA,
+        RecordType::Txt => hickory_client::rr::RecordType::TXT,
+    }
+}
+
+/// One UPDATE message replacing every record set in `records`, so the server applies all
+/// of them or none (RFC 2136 section 3.7).
+fn replace_message(origin: Name, records: &[RecordSpec]) -> Result<Message, Error> {
+    let mut zone = Query::new();
+    zone.set_name(origin)
+        .set_query_class(DNSClass::IN)
+        .set_query_type(hickory_client::rr::RecordType::SOA);
+    let mut message = Message::new();
+    message
+        .set_id(fastrand::u16(..))
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Update)
+        .set_recursion_desired(false);
+    message.add_zone(zone);
+    for (record, values) in group_record_sets(records) {
+        let name = absolute(&record.name)?;
+        let record_type = hickory_type(record.record_type);
+        let mut set = RecordSet::with_ttl(name.clone(), record_type, record.ttl.try_into()?);
+        for value in values {
+            let rdata = match value.parse()? {
+                IpAddr::V4(ip) => RData::A(A(ip)),
+                IpAddr::V6(ip) => RData::AAAA(AAAA(ip)),
+            };
+            set.add_rdata(rdata);
+        }
+        // Deleting the whole set first replaces it, whatever values it held (section 2.5.2).
+        let mut delete = Record::with(name, record_type, 0);
+        delete
+            .set_dns_class(DNSClass::ANY)
+            .set_data(Some(RData::NULL(NULL::new())));
+        message.add_update(delete);
+        message.add_updates(set);
+    }
+    Ok(message)
+}
+
+fn check_response(code: ResponseCode, action: &str) -> Result<(), Error> {
+    if code != ResponseCode::NoError {
+        return Err(anyhow!("Server refused to {action}: {code}"));
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl DnsProvider for Rfc2136Provider {
+    fn name(&self) -> &str {
+        "rfc2136"
+    }
+
+    async fn find_zone(&self) -> Result<String, Error> {
+        let mut client = self.client().await?;
+        let origin = absolute(&self.zone)?;
+        let response = client
+            .query(
+                origin.clone(),
+                DNSClass::IN,
+                hickory_client::rr::RecordType::SOA,
+            )
+            .await?;
+        if !response
+            .answers()
+            .iter()
+            .any(|answer| answer.record_type() == hickory_client::rr::RecordType::SOA)
+        {
+            return Err(anyhow!(
+                "{} is not authoritative for {origin}.",
+                self.config.server
+            ));
+        }
+        Ok(origin.to_string())
+    }
+
+    async fn current_values(
+        &self,
+        name: &str,
+        record_type: RecordType,
+    ) -> Result<Option<Vec<String>>, Error> {
+        let mut client = self.client().await?;
+        let response = client
+            .query(absolute(name)?, DNSClass::IN, hickory_type(record_type))
+            .await?;
+        let values: Vec<String> = response
+            .answers()
+            .iter()
+            .filter(|answer| answer.record_type() == hickory_type(record_type))
+            .filter_map(|answer| answer.data().map(|data| data.to_string()))
+            .collect();
+        Ok((!values.is_empty()).then_some(values))
+    }
+
+    async fn upsert(&self, record: &RecordSpec) -> Result<(), Error> {
+        self.upsert_all(std::slice::from_ref(record)).await
+    }
+
+    async fn upsert_all(&self, records: &[RecordSpec]) -> Result<(), Error> {
+        let message = replace_message(absolute(&self.zone)?, records)?;
+        let client = self.client().await?;
+        let response = client.send(message).first_answer().await?;
+        let names: Vec<&str> = records.iter().map(|record| record.name.as_str()).collect();
+        check_response(
+            response.response_code(),
+            &format!("update {}", names.join(", ")),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_every_set_in_one_message() {
+        let records = [
+            RecordSpec::for_ip("home.example.com", "8.8.8.8".parse().unwrap(), 300),
+            RecordSpec::for_ip("home.example.com", "8.8.4.4".parse().unwrap(), 300),
+            RecordSpec::for_ip("www.example.com", "2001:4860::8888".parse().unwrap(), 60),
+        ];
+        let message = replace_message(absolute("example.com").unwrap(), &records).unwrap();
+
+        assert_eq!(message.op_code(), OpCode::Update);
+        assert_eq!(
+            message.queries()[0].name(),
+            &absolute("example.com").unwrap()
+        );
+        let updates = message.updates();
+        let summary: Vec<(String, DNSClass, u32)> = updates
+            .iter()
+            .map(|update| (update.name().to_string(), update.dns_class(), update.ttl()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("home.example.com.".to_owned(), DNSClass::ANY, 0),
+                ("home.example.com.".to_owned(), DNSClass::IN, 300),
+                ("home.example.com.".to_owned(), DNSClass::IN, 300),
+                ("www.example.com.".to_owned(), DNSClass::ANY, 0),
+                ("www.example.com.".to_owned(), DNSClass::IN, 60),
+            ]
+        );
+    }
+}
@@ -0,0 +1,539 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use aws_sdk_route53::{
+    types::{
+        Change, ChangeAction, ChangeBatch, ChangeStatus, GeoLocation, ResourceRecord,
+        ResourceRecordSet, RrType,
+    },
+    Client,
+};
+use clap::ValueEnum;
+use log::info;
+use serde::Deserialize;
+use tokio::time;
+
+use super::{group_record_sets, DnsProvider, RecordSpec, RecordType};
+use crate::{
+    aws_errors,
+    sync_tracker::{self, SyncTracker},
+};
+
+const CONTINENT_CODES: [&str; 7] = ["AF", "AN", "AS", "EU", "NA", "OC", "SA"];
+
+/// Makes the managed record one member of a geolocation routing policy. Exactly one of
+/// `continent` or `country` is set; country `*` is Route53's default location.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GeolocationRouting {
+    #[serde(default)]
+    pub continent: Option<String>,
+    #[serde(default)]
+    pub country: Option<String>,
+    pub set_identifier: String,
+}
+
+impl GeolocationRouting {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.set_identifier.is_empty() || self.set_identifier.len() > 128 {
+            return Err(anyhow!(
+                "Set identifier must be between 1 and 128 characters."
+            ));
+        }
+        match (&self.continent, &self.country) {
+            (Some(continent), None) if CONTINENT_CODES.contains(&continent.as_str()) => Ok(()),
+            (Some(continent), None) => Err(anyhow!(
+                "Unknown continent code {continent:?}, expected one of {}.",
+                CONTINENT_CODES.join(", ")
+            )),
+            (None, Some(country))
+                if country == "*"
+                    || (country.len() == 2 && country.chars().all(|c| c.is_ascii_uppercase())) =>
+            {
+                Ok(())
+            }
+            (None, Some(country)) => Err(anyhow!(
+                "Country code {country:?} must be two uppercase letters or *."
+            )),
+            _ => Err(anyhow!(
+                "Geolocation routing needs exactly one of a continent or a country."
+            )),
+        }
+    }
+
+    fn geo_location(&self) -> GeoLocation {
+        GeoLocation::builder()
+            .set_continent_code(self.continent.clone())
+            .set_country_code(self.country.clone())
+            .build()
+    }
+}
+
+/// Which hosted zones may be picked when a public and a private zone share a name.
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ZoneVisibility {
+    Public,
+    Private,
+    #[default]
+    Any,
+}
+
+impl ZoneVisibility {
+    pub fn allows(self, private_zone: bool) -> bool {
+        match self {
+            ZoneVisibility::Public => !private_zone,
+            ZoneVisibility::Private => private_zone,
+            ZoneVisibility::Any => true,
+        }
+    }
+}
+
+/// The values and TTL of a record as Route53 currently serves it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PublishedRecord {
+    pub values: Vec<String>,
+    pub ttl: Option<i64>,
+}
+
+/// Writes records to a Route53 hosted zone, batching them into a single change.
+#[derive(Clone, Debug)]
+pub struct Route53Provider {
+    client: Client,
+    hosted_zone_id: String,
+    geolocation: Option<GeolocationRouting>,
+    comment: Option<String>,
+    sync_tracker: Option<SyncTracker>,
+    wait_for_sync: Option<Duration>,
+}
+
+impl Route53Provider {
+    pub fn new(client: Client, hosted_zone_id: String) -> Self {
+        Self {
+            client,
+            hosted_zone_id,
+            geolocation: None,
+            comment: None,
+            sync_tracker: None,
+            wait_for_sync: None,
+        }
+    }
+
+    /// Makes every write wait until its change is INSYNC, failing it after `timeout`.
+    pub fn with_wait_for_sync(mut self, timeout: Option<Duration>) -> Self {
+        self.wait_for_sync = timeout;
+        self
+    }
+
+    /// Follows every submitted change to INSYNC with `sync_tracker`.
+    pub fn with_sync_tracker(mut self, sync_tracker: Option<SyncTracker>) -> Self {
+        self.sync_tracker = sync_tracker;
+        self
+    }
+
+    /// Attaches `comment` to every change batch this provider submits.
+    pub fn with_comment(mut self, comment: Option<String>) -> Self {
+        self.comment = comment;
+        self
+    }
+
+    /// Manages only the geolocation member identified by the routing's set identifier.
+    pub fn with_geolocation(mut self, geolocation: Option<GeolocationRouting>) -> Self {
+        self.geolocation = geolocation;
+        self
+    }
+
+    fn set_identifier(&self) -> Option<&str> {
+        self.geolocation
+            .as_ref()
+            .map(|geolocation| geolocation.set_identifier.as_str())
+    }
+
+    /// Reads the record currently published for `name`, or `None` if it does not exist.
+    pub async fn current_record(
+        &self,
+        name: &str,
+        record_type: RecordType,
+    ) -> Result<Option<PublishedRecord>, Error> {
+        let output = self
+            .client
+            .list_resource_record_sets()
+            .hosted_zone_id(&self.hosted_zone_id)
+            .start_record_name(name)
+            .start_record_type(rr_type(record_type))
+            .set_start_record_identifier(self.set_identifier().map(str::to_owned))
+            .max_items(1)
+            .send()
+            .await
+            .map_err(aws_errors::explain_denied)?;
+        Ok(output
+            .resource_record_sets
+            .into_iter()
+            .find(|set| {
+                set.r#type == rr_type(record_type)
+                    && canonical_record_name(&set.name) == canonical_record_name(name)
+                    && set.set_identifier.as_deref() == self.set_identifier()
+            })
+            .map(|set| PublishedRecord {
+                values: set
+                    .resource_records
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|record| record.value)
+                    .collect(),
+                ttl: set.ttl,
+            }))
+    }
+
+    /// Submits `records` as one change batch and returns the change's ID, or `None` when
+    /// there was nothing to submit. Throttled submissions are retried by the SDK, see
+    /// [`aws_config_loader`](crate::aws_config_loader).
+    pub async fn submit(&self, records: &[RecordSpec]) -> Result<Option<String>, Error> {
+        if records.is_empty() {
+            return Ok(None);
+        }
+        let changes = group_record_sets(records)
+            .iter()
+            .map(|(record, values)| upsert_change(record, values, self.geolocation.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let change_batch = ChangeBatch::builder()
+            .set_comment(self.comment.clone())
+            .set_changes(Some(changes))
+            .build()?;
+        let output = self
+            .client
+            .change_resource_record_sets()
+            .hosted_zone_id(&self.hosted_zone_id)
+            .change_batch(change_batch)
+            .send()
+            .await
+            .map_err(aws_errors::explain_denied)?;
+        Ok(output.change_info.map(|info| info.id))
+    }
+
+    /// Whether the change has reached every Route53 name server yet.
+    pub async fn change_status(&self, change_id: &str) -> Result<ChangeStatus, Error> {
+        let output = self
+            .client
+            .get_change()
+            .id(change_id)
+            .send()
+            .await
+            .map_err(aws_errors::explain_denied)?;
+        output.change_info.map(|info| info.status).ok_or(anyhow!(
+            "Route53 returned no status for change {change_id}."
+        ))
+    }
+}
+
+/// Route53 returns names with a trailing dot and `*` escaped as `\052`.
+pub fn canonical_record_name(name: &str) -> String {
+    name.trim_end_matches('.')
+        .replace("\\052", "*")
+        .to_ascii_lowercase()
+}
+
+fn rr_type(record_type: RecordType) -> RrType {
+    match record_type {
+        RecordType::A => RrType::A,
+        RecordType::Aaaa => RrType::Aaaa,
+        RecordType::Txt => RrType::Txt,
+    }
+}
+
+/// Builds one upsert for `record`, serving all of `values` from a single record set.
+fn upsert_change(
+    record: &RecordSpec,
+    values: &[&str],
+    geolocation: Option<&GeolocationRouting>,
+) -> Result<Change, Error> {
+    let resource_records = values
+        .iter()
+        .map(|value| ResourceRecord::builder().value(*value).build())
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Change::builder()
+        .action(ChangeAction::Upsert)
+        .resource_record_set(
+            ResourceRecordSet::builder()
+                .name(&record.name)
+                .r#type(rr_type(record.record_type))
+                .ttl(record.ttl)
+                .set_set_identifier(geolocation.map(|g| g.set_identifier.clone()))
+                .set_geo_location(geolocation.map(GeolocationRouting::geo_location))
+                .set_resource_records(Some(resource_records))
+                .build()?,
+        )
+        .build()?)
+}
+
+#[async_trait]
+impl DnsProvider for Route53Provider {
+    fn name(&self) -> &str {
+        "route53"
+    }
+
+    async fn find_zone(&self) -> Result<String, Error> {
+        Ok(self.hosted_zone_id.clone())
+    }
+
+    async fn current_values(
+        &self,
+        name: &str,
+        record_type: RecordType,
+    ) -> Result<Option<Vec<String>>, Error> {
+        Ok(self
+            .current_record(name, record_type)
+            .await?
+            .map(|record| record.values))
+    }
+
+    /// Logs the other record types at each record's name, which an upsert leaves alone,
+    /// and refuses to write next to a CNAME, which can't coexist with A or AAAA records.
+    async fn check_other_types(&self, records: &[RecordSpec]) -> Result<(), Error> {
+        let mut names: Vec<&str> = records.iter().map(|record| record.name.as_str()).collect();
+        names.sort_unstable();
+        names.dedup();
+        for name in names {
+            let output = self
+                .client
+                .list_resource_record_sets()
+                .hosted_zone_id(&self.hosted_zone_id)
+                .start_record_name(name)
+                .max_items(100)
+                .send()
+                .await
+                .map_err(aws_errors::explain_denied)?;
+            let mut types: Vec<RrType> = output
+                .resource_record_sets
+                .into_iter()
+                .take_while(|set| canonical_record_name(&set.name) == canonical_record_name(name))
+                .map(|set| set.r#type)
+                .collect();
+            types.sort_unstable();
+            types.dedup();
+            if types.contains(&RrType::Cname) {
+                return Err(anyhow!(
+                    "{name} has a CNAME record, which can't coexist with A or AAAA records."
+                ));
+            }
+            let others: Vec<&str> = types
+                .iter()
+                .filter(|existing| {
+                    !records
+                        .iter()
+                        .any(|record| rr_type(record.record_type) == **existing)
+                })
+                .map(RrType::as_str)
+                .collect();
+            if !others.is_empty() {
+                info!(
+                    "{name} also has {} records, which are preserved.",
+                    others.join(", ")
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces each record's TTL with the one already published, so TTLs set out of
+    /// band survive value updates. Records that don't exist yet keep their TTL.
+    async fn preserve_ttls(&self, records: &mut [RecordSpec]) -> Result<(), Error> {
+        for record in records {
+            let published = self
+                .current_record(&record.name, record.record_type)
+                .await?;
+            if let Some(ttl) = published.and_then(|published| published.ttl) {
+                record.ttl = ttl;
+            }
+        }
+        Ok(())
+    }
+
+    async fn upsert(&self, record: &RecordSpec) -> Result<(), Error> {
+        self.upsert_all(std::slice::from_ref(record)).await
+    }
+
+    async fn upsert_all(&self, records: &[RecordSpec]) -> Result<(), Error> {
+        match self.submit_all(records).await? {
+            Some(change_id) => self.wait_for_sync(&change_id).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn submit_all(&self, records: &[RecordSpec]) -> Result<Option<String>, Error> {
+        self.submit(records).await
+    }
+
+    /// Waits for the change with `--wait-for-sync-seconds`, otherwise hands it to the
+    /// sync tracker, if any, and returns right away.
+    async fn wait_for_sync(&self, change_id: &str) -> Result<(), Error> {
+        if let Some(timeout) = self.wait_for_sync {
+            let started = Instant::now();
+            time::timeout(timeout, sync_tracker::wait_for_insync(self, change_id))
+                .await
+                .map_err(|_| anyhow!("Change {change_id} was not INSYNC after {timeout:?}."))??;
+            info!(
+                "Change {change_id} is INSYNC after {}s.",
+                started.elapsed().as_secs()
+            );
+        } else if let Some(tracker) = &self.sync_tracker {
+            tracker.track(self.clone(), change_id.to_owned());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::*;
+    use crate::test_util::{http_server, route53_client};
+
+    /// Answers `ListResourceRecordSets` with `sets`, each a name and type with a TTL of
+    /// an hour.
+    fn record_sets(sets: &'static [(&'static str, &'static str)]) -> String {
+        let sets: String = sets
+            .iter()
+            .map(|(name, r#type)| {
+                format!(
+                    "<ResourceRecordSet><Name>{name}</Name><Type>{type}</Type><TTL>3600</TTL>\
+                     <ResourceRecords><ResourceRecord><Value>x</Value></ResourceRecord>\
+                     </ResourceRecords></ResourceRecordSet>"
+                )
+            })
+            .collect();
+        format!(
+            "<ListResourceRecordSetsResponse xmlns=\"https://route53.amazonaws.com/doc/2013-04-01/\">\
+             <ResourceRecordSets>{sets}</ResourceRecordSets><IsTruncated>false</IsTruncated>\
+             <MaxItems>100</MaxItems></ListResourceRecordSetsResponse>"
+        )
+    }
+
+    fn records(names: &[&str]) -> Vec<RecordSpec> {
+        let ips: [IpAddr; 2] = ["192.0.2.1".parse().unwrap(), "2001:db8::1".parse().unwrap()];
+        ips.iter()
+            .flat_map(|ip| names.iter().map(|name| RecordSpec::for_ip(name, *ip, 300)))
+            .collect()
+    }
+
+    fn routing(continent: Option<&str>, country: Option<&str>) -> GeolocationRouting {
+        GeolocationRouting {
+            continent: continent.map(str::to_owned),
+            country: country.map(str::to_owned),
+            set_identifier: "home-de".to_owned(),
+        }
+    }
+
+    #[test]
+    fn upserts_a_geolocation_member() {
+        let record = RecordSpec::for_ip("home.example.com", "192.0.2.1".parse().unwrap(), 60);
+        let geolocation = routing(None, Some("DE"));
+
+        let change = upsert_change(&record, &["192.0.2.1"], Some(&geolocation)).unwrap();
+
+        let set = change.resource_record_set.unwrap();
+        assert_eq!(set.set_identifier.as_deref(), Some("home-de"));
+        let location = set.geo_location.unwrap();
+        assert_eq!(location.country_code.as_deref(), Some("DE"));
+        assert_eq!(location.continent_code, None);
+        assert_eq!(set.ttl, Some(60));
+
+        let plain = upsert_change(&record, &["192.0.2.1"], None).unwrap();
+        let set = plain.resource_record_set.unwrap();
+        assert_eq!((set.set_identifier, set.geo_location), (None, None));
+    }
+
+    #[test]
+    fn validates_the_geolocation() {
+        assert!(routing(Some("EU"), None).validate().is_ok());
+        assert!(routing(None, Some("DE")).validate().is_ok());
+        assert!(routing(None, Some("*")).validate().is_ok());
+
+        for invalid in [
+            routing(Some("EU"), Some("DE")),
+            routing(None, None),
+            routing(Some("XX"), None),
+            routing(None, Some("de")),
+            routing(None, Some("DEU")),
+        ] {
+            assert!(invalid.validate().is_err(), "{invalid:?}");
+        }
+        let unnamed = GeolocationRouting {
+            set_identifier: String::new(),
+            ..routing(Some("EU"), None)
+        };
+        assert!(unnamed.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn keeps_the_published_ttl_on_a_value_change() {
+        let (url, requests) = http_server(|request| match request.method.as_str() {
+            "POST" => (
+                200,
+                "<ChangeResourceRecordSetsResponse xmlns=\"https://route53.amazonaws.com/doc/2013-04-01/\">\
+                 <ChangeInfo><Id>/change/C1</Id><Status>PENDING</Status>\
+                 <SubmittedAt>2024-01-01T00:00:00Z</SubmittedAt></ChangeInfo>\
+                 </ChangeResourceRecordSetsResponse>"
+                    .to_owned(),
+            ),
+            _ => (200, record_sets(&[("home.example.com.", "A")])),
+        })
+        .await;
+        let provider = Route53Provider::new(route53_client(&url), "Z1".to_owned());
+        let mut records = vec![
+            RecordSpec::for_ip("home.example.com", "192.0.2.1".parse().unwrap(), 300),
+            RecordSpec::for_ip("www.example.com", "192.0.2.1".parse().unwrap(), 300),
+        ];
+
+        provider.preserve_ttls(&mut records).await.unwrap();
+        let ttls: Vec<i64> = records.iter().map(|record| record.ttl).collect();
+        // www doesn't exist yet, so it gets the configured TTL.
+        assert_eq!(ttls, [3600, 300]);
+
+        provider.submit_all(&records[..1]).await.unwrap();
+        let requests = requests.lock().unwrap();
+        let change = &requests.last().unwrap().body;
+        assert!(change.contains("<TTL>3600</TTL>"), "{change}");
+        assert!(change.contains("<Value>192.0.2.1</Value>"), "{change}");
+    }
+
+    #[tokio::test]
+    async fn refuses_to_write_next_to_a_cname() {
+        let (url, _) = http_server(|_| {
+            (
+                200,
+                record_sets(&[("home.example.com.", "CNAME"), ("www.example.com.", "A")]),
+            )
+        })
+        .await;
+        let provider = Route53Provider::new(route53_client(&url), "Z1".to_owned());
+
+        let err = provider
+            .check_other_types(&records(&["home.example.com"]))
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().contains("has a CNAME record"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn looks_up_each_name_once() {
+        let (url, requests) = http_server(|_| {
+            (
+                200,
+                record_sets(&[("home.example.com.", "A"), ("home.example.com.", "TXT")]),
+            )
+        })
+        .await;
+        let provider = Route53Provider::new(route53_client(&url), "Z1".to_owned());
+
+        // A and AAAA records for both names, so the names aren't adjacent.
+        provider
+            .check_other_types(&records(&["home.example.com", "www.example.com"]))
+            .await
+            .unwrap();
+
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,423 @@
+use std::{fs, path::PathBuf, time::SystemTime};
+
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+
+use super::{group_record_sets, values_match, DnsProvider, RecordSpec, RecordType};
+
+/// Keeps the managed record in an RFC 1035 zone file served by e.g. BIND or Knot.
+/// Only the managed record's lines are rewritten and the SOA serial is incremented;
+/// reloading the zone is left to the name server.
+#[derive(Clone, Debug)]
+pub struct ZoneFileProvider {
+    path: PathBuf,
+    origin: String,
+}
+
+/// A single-line resource record with its owner made absolute.
+struct ZoneRecord {
+    owner: String,
+    /// In seconds, from the record itself or else from the `$TTL` directive before it.
+    ttl: Option<u32>,
+    record_type: String,
+    value: String,
+}
+
+impl ZoneFileProvider {
+    /// `origin` is the zone name, used for relative owners until a `$ORIGIN` directive.
+    pub fn new(path: PathBuf, origin: &str) -> Self {
+        Self {
+            path,
+            origin: canonical(origin),
+        }
+    }
+}
+
+fn canonical(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn absolute_name(name: &str, origin: &str) -> String {
+    match name {
+        "@" => origin.to_owned(),
+        name if name.ends_with('.') => canonical(name),
+        name => format!("{}.{origin}", name.to_ascii_lowercase()),
+    }
+}
+
+/// Parses a TTL in seconds or with units, e.g. `3600`, `1h` or `1h30m` (BIND syntax).
+fn parse_ttl(token: &str) -> Option<u32> {
+    if let Ok(seconds) = token.parse() {
+        return Some(seconds);
+    }
+    let mut total: u32 = 0;
+    let mut number = String::new();
+    for c in token.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            'w' => 604_800,
+            'd' => 86_400,
+            'h' => 3_600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        let value: u32 = std::mem::take(&mut number).parse().ok()?;
+        total = total.checked_add(value.checked_mul(unit)?)?;
+    }
+    number.is_empty().then_some(total)
+}
+
+/// Parses each line into a record, or `None` for directives, blank lines and the
+/// continuation lines of parenthesized records such as the SOA.
+fn parse_records(lines: &[String], origin: &str) -> Vec<Option<ZoneRecord>> {
+    let mut origin = origin.to_owned();
+    let mut default_ttl = None;
+    let mut owner: Option<String> = None;
+    let mut depth = 0;
+    lines
+        .iter()
+        .map(|line| {
+            let code = line.split(';').next().unwrap_or_default();
+            let continuation = depth > 0;
+            depth += code.matches('(').count() as i64 - code.matches(')').count() as i64;
+            if continuation || code.trim().is_empty() {
+                return None;
+            }
+            let mut tokens = code.split_whitespace();
+            if code.starts_with('$') {
+                if let (Some(directive), Some(value)) = (tokens.next(), tokens.next()) {
+                    if directive.eq_ignore_ascii_case("$ORIGIN") {
+                        origin = absolute_name(value, &origin);
+                    } else if directive.eq_ignore_ascii_case("$TTL") {
+                        default_ttl = parse_ttl(value);
+                    }
+                }
+                return None;
+            }
+            if !code.starts_with(char::is_whitespace) {
+                owner = Some(absolute_name(tokens.next()?, &origin));
+            }
+            // The optional TTL and class come in front of the type.
+            let mut ttl = default_ttl;
+            let record_type = tokens.find(|token| {
+                if token.starts_with(|c: char| c.is_ascii_digit()) {
+                    ttl = parse_ttl(token);
+                    return false;
+                }
+                !["IN", "CH", "HS"].contains(&token.to_ascii_uppercase().as_str())
+            })?;
+            Some(ZoneRecord {
+                owner: owner.clone()?,
+                ttl,
+                record_type: record_type.to_ascii_uppercase(),
+                value: tokens.collect::<Vec<_>>().join(" "),
+            })
+        })
+        .collect()
+}
+
+/// Whether `a` comes after `b` in RFC 1982 serial number arithmetic.
+fn serial_gt(a: u32, b: u32) -> bool {
+    a != b && a.wrapping_sub(b) < 1 << 31
+}
+
+/// Whether `serial` reads as a YYYYMMDDnn date serial.
+fn is_date_serial(serial: u32) -> bool {
+    let date = serial / 100;
+    (1970..=2100).contains(&(date / 10000))
+        && (1..=12).contains(&(date / 100 % 100))
+        && (1..=31).contains(&(date % 100))
+}
+
+/// The serial following `serial`. A YYYYMMDDnn date serial jumps to `today`'s first
+/// serial once that is newer, anything else counts up. Counting wraps around as RFC 1982
+/// allows, skipping 0, which some name servers take as an unset serial.
+fn next_serial(serial: u32, today: u32) -> u32 {
+    let first_of_today = today.saturating_mul(100);
+    if is_date_serial(serial) && serial_gt(first_of_today, serial) {
+        return first_of_today;
+    }
+    match serial.wrapping_add(1) {
+        0 => 1,
+        next => next,
+    }
+}
+
+/// Today's date as YYYYMMDD, in UTC.
+fn today() -> u32 {
+    let now = humantime::format_rfc3339(SystemTime::now()).to_string();
+    now[..10].replace('-', "").parse().unwrap_or_default()
+}
+
+/// Moves the serial of the zone's SOA record forward in place.
+fn bump_soa_serial(text: &str, today: u32) -> Result<String, Error> {
+    let mut tokens = text
+        .lines()
+        .flat_map(|line| {
+            line.split(';')
+                .next()
+                .unwrap_or_default()
+                .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        })
+        .filter(|token| !token.is_empty());
+    tokens
+        .by_ref()
+        .find(|token| token.eq_ignore_ascii_case("SOA"))
+        .ok_or(anyhow!("Zone file has no SOA record."))?;
+    // The serial follows the primary name server and the responsible mailbox.
+    let serial = tokens
+        .nth(2)
+        .ok_or(anyhow!("Zone file SOA record has no serial."))?;
+    let current = serial
+        .parse::<u32>()
+        .map_err(|e| anyhow!("Invalid SOA serial {serial:?}: {e}"))?;
+    let next = next_serial(current, today);
+    let start = serial.as_ptr() as usize - text.as_ptr() as usize;
+    Ok(format!(
+        "{}{next}{}",
+        &text[..start],
+        &text[start + serial.len()..]
+    ))
+}
+
+#[async_trait]
+impl DnsProvider for ZoneFileProvider {
+    fn name(&self) -> &str {
+        "zone-file"
+    }
+
+    async fn find_zone(&self) -> Result<String, Error> {
+        let lines: Vec<String> = fs::read_to_string(&self.path)?
+            .lines()
+            .map(str::to_owned)
+            .collect();
+        let has_soa = parse_records(&lines, &self.origin)
+            .into_iter()
+            .flatten()
+            .any(|record| record.owner == self.origin && record.record_type == "SOA");
+        if !has_soa {
+            return Err(anyhow!(
+                "{} has no SOA record for {}.",
+                self.path.display(),
+                self.origin
+            ));
+        }
+        Ok(self.origin.clone())
+    }
+
+    async fn current_values(
+        &self,
+        name: &str,
+        record_type: RecordType,
+    ) -> Result<Option<Vec<String>>, Error> {
+        let lines: Vec<String> = fs::read_to_string(&self.path)?
+            .lines()
+            .map(str::to_owned)
+            .collect();
+        let values: Vec<String> = parse_records(&lines, &self.origin)
+            .into_iter()
+            .flatten()
+            .filter(|record| {
+                record.owner == canonical(name) && record.record_type == record_type.as_str()
+            })
+            .map(|record| record.value)
+            .collect();
+        Ok((!values.is_empty()).then_some(values))
+    }
+
+    async fn upsert(&self, record: &RecordSpec) -> Result<(), Error> {
+        self.upsert_all(std::slice::from_ref(record)).await
+    }
+
+    async fn upsert_all(&self, records: &[RecordSpec]) -> Result<(), Error> {
+        let original = fs::read_to_string(&self.path)?;
+        let mut lines: Vec<String> = original.lines().map(str::to_owned).collect();
+        for (record, values) in group_record_sets(records) {
+            let name = canonical(&record.name);
+            let parsed = parse_records(&lines, &self.origin);
+            let matching: Vec<usize> = parsed
+                .iter()
+                .enumerate()
+                .filter(|(_, parsed)| {
+                    parsed.as_ref().is_some_and(|parsed| {
+                        parsed.owner == name && parsed.record_type == record.record_type.as_str()
+                    })
+                })
+                .map(|(index, _)| index)
+                .collect();
+            // Leave equivalent lines alone, e.g. `2001:DB8::1` for `2001:db8::1`, the same
+            // values in another order or `1h` for 3600, so the serial only moves on real
+            // changes.
+            let existing: Vec<&ZoneRecord> = matching
+                .iter()
+                .filter_map(|index| parsed[*index].as_ref())
+                .collect();
+            let unchanged = existing.len() == values.len()
+                && existing
+                    .iter()
+                    .all(|parsed| u32::try_from(record.ttl).ok() == parsed.ttl)
+                && existing.iter().all(|parsed| {
+                    values
+                        .iter()
+                        .any(|value| values_match(&parsed.value, value))
+                })
+                && values.iter().all(|value| {
+                    existing
+                        .iter()
+                        .any(|parsed| values_match(&parsed.value, value))
+                });
+            if unchanged {
+                continue;
+            }
+            let at = matching.first().copied().unwrap_or(lines.len());
+            for &index in matching.iter().rev() {
+                // A record without an owner of its own belongs to the record before it,
+                // so it keeps that owner spelled out once that record is gone.
+                let next = (index + 1..lines.len())
+                    .find(|next| parsed[*next].is_some())
+                    .filter(|next| !matching.contains(next));
+                if let Some(next) =
+                    next.filter(|next| lines[*next].starts_with(char::is_whitespace))
+                {
+                    let owner = &parsed[next].as_ref().unwrap().owner;
+                    lines[next] = format!("{owner}.{}", lines[next]);
+                }
+                lines.remove(index);
+            }
+            let updated = values
+                .iter()
+                .map(|value| format!("{name}. {} IN {} {value}", record.ttl, record.record_type));
+            lines.splice(at..at, updated);
+        }
+
+        let mut text = lines.join("\n");
+        text.push('\n');
+        if text == original {
+            return Ok(());
+        }
+        let text = bump_soa_serial(&text, today())?;
+        // Write next to the zone file and rename, so the name server never reads a partial file.
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        fs::write(&temp, text)?;
+        fs::rename(&temp, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn serial(text: &str) -> &str {
+        let after = text.split_once("( ").unwrap().1;
+        after.split_whitespace().next().unwrap()
+    }
+
+    #[test]
+    fn counts_up_plain_serials_skipping_zero() {
+        assert_eq!(next_serial(41, 20261016), 42);
+        assert_eq!(next_serial(u32::MAX, 20261016), 1);
+    }
+
+    #[test]
+    fn moves_date_serials_to_today() {
+        assert_eq!(next_serial(2024010101, 20261016), 2026101600);
+        assert_eq!(next_serial(2026101600, 20261016), 2026101601);
+        // A serial already ahead of today keeps counting up.
+        assert_eq!(next_serial(2026101799, 20261016), 2026101800);
+        assert!(serial_gt(1, u32::MAX));
+        assert!(!serial_gt(u32::MAX, 1));
+    }
+
+    #[tokio::test]
+    async fn replaces_the_record_and_bumps_the_serial() {
+        let path = test_util::zone_file("replace.zone", "home 300 IN A 8.8.4.4\n");
+        let provider = ZoneFileProvider::new(path.clone(), "example.com");
+        let record = RecordSpec::for_ip("home.example.com", "8.8.8.8".parse().unwrap(), 300);
+        provider.upsert(&record).await.unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(
+            text.contains("home.example.com. 300 IN A 8.8.8.8\n"),
+            "{text}"
+        );
+        assert!(!text.contains("8.8.4.4"), "{text}");
+        assert_ne!(serial(&text), "2024010101");
+        let values = provider
+            .current_values("home.example.com", RecordType::A)
+            .await
+            .unwrap();
+        assert_eq!(values, Some(vec!["8.8.8.8".to_owned()]));
+    }
+
+    #[test]
+    fn parses_ttls_with_units() {
+        assert_eq!(parse_ttl("3600"), Some(3600));
+        assert_eq!(parse_ttl("1h"), Some(3600));
+        assert_eq!(parse_ttl("1H30m"), Some(5400));
+        assert_eq!(parse_ttl("1w2d"), Some(777_600));
+        assert_eq!(parse_ttl("1h30"), None);
+        assert_eq!(parse_ttl("1x"), None);
+    }
+
+    #[tokio::test]
+    async fn finds_the_zone_by_its_soa_record() {
+        let path = test_util::zone_file("find.zone", "");
+        let provider = ZoneFileProvider::new(path, "Example.com.");
+        assert_eq!(provider.find_zone().await.unwrap(), "example.com");
+
+        let path = test_util::temp_path("no-soa.zone");
+        fs::write(&path, "$ORIGIN example.com.\nhome 300 IN A 8.8.8.8\n").unwrap();
+        let err = ZoneFileProvider::new(path, "example.com")
+            .find_zone()
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("no SOA record"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn leaves_an_equivalent_record_set_alone() {
+        // `$TTL 1h` applies to the record without a TTL of its own.
+        let records =
+            "$TTL 1h\nhome IN AAAA 2001:4860:0:0::8844\nhome 60m IN AAAA 2001:4860::8888\n";
+        let path = test_util::zone_file("equivalent.zone", records);
+        let before = fs::read_to_string(&path).unwrap();
+        let provider = ZoneFileProvider::new(path.clone(), "example.com");
+        let records = ["2001:4860::8888", "2001:4860::8844"]
+            .map(|ip| RecordSpec::for_ip("home.example.com", ip.parse().unwrap(), 3600));
+        provider.upsert_all(&records).await.unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), before);
+
+        let records = records.map(|record| RecordSpec { ttl: 300, ..record });
+        provider.upsert_all(&records).await.unwrap();
+        assert_ne!(fs::read_to_string(&path).unwrap(), before);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_owner_of_records_that_followed_a_removed_line() {
+        let path = test_util::zone_file(
+            "owner.zone",
+            "home 300 IN A 8.8.4.4\nwww 300 IN A 8.8.4.4\nhome 300 IN A 1.0.0.1\n     300 IN TXT \"home\"\n",
+        );
+        let provider = ZoneFileProvider::new(path.clone(), "example.com");
+        let record = RecordSpec::for_ip("home.example.com", "8.8.8.8".parse().unwrap(), 300);
+        provider.upsert(&record).await.unwrap();
+
+        let txt = provider
+            .current_values("home.example.com", RecordType::Txt)
+            .await
+            .unwrap();
+        assert_eq!(txt, Some(vec!["\"home\"".to_owned()]));
+        let www_txt = provider
+            .current_values("www.example.com", RecordType::Txt)
+            .await
+            .unwrap();
+        assert_eq!(www_txt, None);
+    }
+}
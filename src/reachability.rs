@@ -0,0 +1,111 @@
+use std::{
+    fmt::Display,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Error};
+use serde::Deserialize;
+use tokio::{net::TcpStream, time};
+
+use crate::template::{Template, TemplateValues};
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How to confirm a freshly detected address actually serves traffic before publishing it.
+/// Either a TCP port to connect to on the address, or a checker URL template in which
+/// `{ip}` is the address and which must answer with a success status.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum ReachabilityCheck {
+    Port(u16),
+    Url(Template),
+}
+
+impl ReachabilityCheck {
+    /// Runs the check against `ip`. Errors describe why the address is unreachable.
+    pub async fn check(&self, ip: IpAddr) -> Result<(), Error> {
+        match self {
+            Self::Port(port) => {
+                let addr = SocketAddr::new(ip, *port);
+                time::timeout(CHECK_TIMEOUT, TcpStream::connect(addr))
+                    .await
+                    .map_err(|_| anyhow!("Connecting to {addr} timed out."))??;
+            }
+            Self::Url(url) => {
+                let values = match ip {
+                    IpAddr::V4(_) => TemplateValues::new(Some(ip), None),
+                    IpAddr::V6(_) => TemplateValues::new(None, Some(ip)),
+                };
+                let url = url.render(&values);
+                reqwest::Client::builder()
+                    .timeout(CHECK_TIMEOUT)
+                    .build()?
+                    .get(&url)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ReachabilityCheck {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(port) = s.parse() {
+            return Ok(Self::Port(port));
+        }
+        if s.starts_with("http://") || s.starts_with("https://") {
+            return Ok(Self::Url(s.parse()?));
+        }
+        Err(anyhow!(
+            "Reachability check {s:?} must be a port number or an http(s) URL."
+        ))
+    }
+}
+
+impl TryFrom<String> for ReachabilityCheck {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for ReachabilityCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Port(port) => write!(f, "port {port}"),
+            Self::Url(url) => write!(f, "{url}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::http_server;
+
+    #[tokio::test]
+    async fn asks_the_checker_about_the_address() {
+        let (url, requests) = http_server(|_| (200, String::new())).await;
+        let check: ReachabilityCheck = format!("{url}/check?host={{ip}}&v={{ipv6}}")
+            .parse()
+            .unwrap();
+        check
+            .check("2001:4860::8888".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            requests.lock().unwrap()[0].target,
+            "/check?host=2001:4860::8888&v=2001:4860::8888"
+        );
+        assert!("https://check.example/{host}"
+            .parse::<ReachabilityCheck>()
+            .is_err());
+    }
+}
@@ -0,0 +1,205 @@
+//! Retries a failed step of an update within the same cycle, so a blip doesn't leave the
+//! record stale until the next interval.
+
+use std::{future::Future, io, time::Duration};
+
+use anyhow::Error;
+use log::warn;
+use tokio::{
+    select,
+    time::{self, Instant},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{aws_errors::TransientError, HostedZoneConfig, UpdateContext};
+
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+pub const DEFAULT_MAX_ELAPSED_SECONDS: u64 = 60;
+
+const INITIAL_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+pub fn default_max_attempts() -> u32 {
+    DEFAULT_MAX_ATTEMPTS
+}
+
+pub fn default_max_elapsed_seconds() -> u64 {
+    DEFAULT_MAX_ELAPSED_SECONDS
+}
+
+/// Whether `err` may go away on its own: a timeout, a failed connection, a server error
+/// or a request to slow down, as opposed to e.g. bad credentials or a rejected change.
+pub fn is_transient(err: &Error) -> bool {
+    err.chain().any(|cause| {
+        cause.is::<TransientError>()
+            || cause.is::<time::error::Elapsed>()
+            || cause
+                .downcast_ref::<io::Error>()
+                .is_some_and(|e| is_transient_io(e.kind()))
+            || cause.downcast_ref::<reqwest::Error>().is_some_and(|e| {
+                e.is_timeout()
+                    || e.is_connect()
+                    || e.status().is_some_and(|status| {
+                        status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                    })
+            })
+    })
+}
+
+/// The I/O failures worth another attempt. Anything else, like a missing file or a
+/// refused permission, fails the same way every time.
+fn is_transient_io(kind: io::ErrorKind) -> bool {
+    matches!(
+        kind,
+        io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+    )
+}
+
+/// How often and for how long a failed step is retried.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    max_elapsed: Duration,
+    /// Cuts a wait between attempts short, failing with the last error.
+    shutdown: CancellationToken,
+}
+
+impl RetryPolicy {
+    /// The zone's retry budget in daemon mode. A single run fails right away, as the
+    /// caller is there to run it again.
+    pub fn for_zone(zone: &HostedZoneConfig, ctx: &UpdateContext) -> Self {
+        Self {
+            max_attempts: if ctx.daemon {
+                zone.retry_max_attempts
+            } else {
+                1
+            },
+            max_elapsed: Duration::from_secs(zone.retry_max_elapsed_seconds),
+            shutdown: ctx.shutdown.clone(),
+        }
+    }
+
+    /// Runs `step` until it succeeds, the attempts are used up or the next wait would
+    /// exceed the elapsed budget. Waits grow exponentially with full jitter.
+    pub async fn run<T, F, Fut>(&self, what: &str, step: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        self.run_while(what, |_| true, step).await
+    }
+
+    /// Like [`run`](Self::run), but gives up on the first error that won't go away on its
+    /// own, see [`is_transient`].
+    pub async fn run_transient<T, F, Fut>(&self, what: &str, step: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        self.run_while(what, is_transient, step).await
+    }
+
+    async fn run_while<T, F, Fut>(
+        &self,
+        what: &str,
+        retryable: impl Fn(&Error) -> bool,
+        mut step: F,
+    ) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let started = Instant::now();
+        let mut ceiling = INITIAL_DELAY;
+        let mut attempt = 1;
+        loop {
+            match step().await {
+                Err(e) if attempt < self.max_attempts && retryable(&e) => {
+                    let delay = ceiling.mul_f64(fastrand::f64());
+                    if started.elapsed() + delay > self.max_elapsed {
+                        return Err(e);
+                    }
+                    warn!(
+                        "{what} failed on attempt {attempt} of {}: {e:?}. Retrying in {delay:?}.",
+                        self.max_attempts
+                    );
+                    select! {
+                        _ = time::sleep(delay) => {}
+                        _ = self.shutdown.cancelled() => return Err(e),
+                    }
+                    ceiling = (ceiling * 2).min(MAX_DELAY);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use anyhow::anyhow;
+
+    use super::*;
+    use crate::test_util;
+
+    fn policy() -> RetryPolicy {
+        let ctx = UpdateContext {
+            daemon: true,
+            ..test_util::context()
+        };
+        RetryPolicy::for_zone(&test_util::zone(""), &ctx)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_only_transient_errors() {
+        let attempts = AtomicU32::new(0);
+        let rejected = policy()
+            .run_transient("Updating", || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err::<(), _>(anyhow!("InvalidChangeBatch"))
+            })
+            .await;
+        assert!(rejected.is_err());
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 1);
+
+        let timed_out = policy()
+            .run_transient("Updating", || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err::<(), _>(io::Error::from(io::ErrorKind::TimedOut).into())
+            })
+            .await;
+        assert!(timed_out.is_err());
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), DEFAULT_MAX_ATTEMPTS);
+
+        let denied = policy()
+            .run_transient("Writing the state file", || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err::<(), _>(io::Error::from(io::ErrorKind::PermissionDenied).into())
+            })
+            .await;
+        assert!(denied.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_ends_the_wait() {
+        let policy = policy();
+        policy.shutdown.cancel();
+        let attempts = AtomicU32::new(0);
+        let result = policy
+            .run("Detection", || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err::<(), _>(anyhow!("no answer"))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,6 @@
+//! IP sources that read the WAN address straight from the router instead of asking a
+//! service on the internet.
+
+pub mod firewall;
+pub mod fritzbox;
+pub mod mikrotik;
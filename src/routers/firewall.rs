@@ -0,0 +1,159 @@
+use std::{collections::HashMap, net::IpAddr, time::Duration};
+
+use anyhow::{anyhow, Error};
+use serde::Deserialize;
+
+use crate::{ip_validation, providers::RecordType};
+
+/// An OPNsense or pfSense firewall whose HTTP API is asked for the address of the WAN
+/// interface.
+#[derive(Deserialize, Clone)]
+pub struct FirewallConfig {
+    /// Base URL of the firewall's web GUI, e.g. `https://192.168.1.1`.
+    pub url: String,
+    pub api_key: String,
+    /// OPNsense's API secret. pfSense's REST API authenticates with the key alone.
+    #[serde(default)]
+    pub api_secret: String,
+    /// The WAN interface: its device on OPNsense, e.g. `igb0` or `pppoe0`, and its name,
+    /// description or device on pfSense, e.g. `wan`.
+    pub interface: String,
+}
+
+impl std::fmt::Debug for FirewallConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FirewallConfig")
+            .field("url", &self.url)
+            .field("api_key", &"********")
+            .field("api_secret", &"********")
+            .field("interface", &self.interface)
+            .finish()
+    }
+}
+
+/// One interface of OPNsense's `/api/diagnostics/interface/getInterfaceConfig`.
+#[derive(Deserialize)]
+struct OpnsenseInterface {
+    #[serde(default)]
+    ipv4: Vec<OpnsenseAddress>,
+    #[serde(default)]
+    ipv6: Vec<OpnsenseAddress>,
+}
+
+#[derive(Deserialize)]
+struct OpnsenseAddress {
+    ipaddr: String,
+}
+
+/// The response of the pfSense REST API's `/api/v2/status/interfaces`.
+#[derive(Deserialize)]
+struct PfsenseInterfaces {
+    data: Vec<PfsenseInterface>,
+}
+
+#[derive(Deserialize)]
+struct PfsenseInterface {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    descr: String,
+    #[serde(default)]
+    hwif: String,
+    #[serde(default)]
+    ipaddr: Option<String>,
+    #[serde(default)]
+    ipaddrv6: Option<String>,
+}
+
+impl FirewallConfig {
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}{path}", self.url.trim_end_matches('/'))
+    }
+
+    /// Reads the first global address of `family` on the WAN interface of an OPNsense
+    /// firewall.
+    pub async fn opnsense_address(
+        &self,
+        family: RecordType,
+        timeout: Duration,
+    ) -> Result<IpAddr, Error> {
+        let url = self.endpoint("/api/diagnostics/interface/getInterfaceConfig");
+        let mut interfaces: HashMap<String, OpnsenseInterface> = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()?
+            .get(&url)
+            .basic_auth(&self.api_key, Some(&self.api_secret))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let interface = interfaces.remove(&self.interface).ok_or(anyhow!(
+            "OPNsense at {url} has no interface {}.",
+            self.interface
+        ))?;
+        let addresses = match family {
+            RecordType::Aaaa => interface.ipv6,
+            _ => interface.ipv4,
+        };
+        first_usable(addresses.iter().map(|address| address.ipaddr.as_str())).ok_or(anyhow!(
+            "{} has no {family} address on {url}.",
+            self.interface
+        ))
+    }
+
+    /// Reads the address of `family` on the WAN interface of a pfSense firewall running
+    /// the pfSense REST API package.
+    pub async fn pfsense_address(
+        &self,
+        family: RecordType,
+        timeout: Duration,
+    ) -> Result<IpAddr, Error> {
+        let url = self.endpoint("/api/v2/status/interfaces");
+        let interfaces: PfsenseInterfaces = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()?
+            .get(&url)
+            .header("X-API-Key", &self.api_key)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let interface = interfaces
+            .data
+            .into_iter()
+            .find(|interface| {
+                [&interface.name, &interface.descr, &interface.hwif]
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(&self.interface))
+            })
+            .ok_or(anyhow!(
+                "pfSense at {url} has no interface {}.",
+                self.interface
+            ))?;
+        let address = match family {
+            RecordType::Aaaa => interface.ipaddrv6,
+            _ => interface.ipaddr,
+        };
+        first_usable(address.as_deref()).ok_or(anyhow!(
+            "{} has no {family} address on {url}.",
+            self.interface
+        ))
+    }
+}
+
+/// Parses the first address, dropping any `/prefix`, that isn't IPv6 link-local.
+fn first_usable<'a>(addresses: impl IntoIterator<Item = &'a str>) -> Option<IpAddr> {
+    addresses
+        .into_iter()
+        .filter_map(|address| {
+            let address = address.split('/').next()?;
+            // OPNsense appends the scope to link-local addresses, e.g. `fe80::1%igb0`.
+            address.split('%').next()?.parse().ok()
+        })
+        .find(|ip: &IpAddr| match ip {
+            IpAddr::V6(ip) => !ip_validation::is_link_local(ip),
+            IpAddr::V4(_) => true,
+        })
+}
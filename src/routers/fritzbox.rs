@@ -0,0 +1,149 @@
+use std::{net::IpAddr, time::Duration};
+
+use anyhow::{anyhow, Context, Error};
+use md5::{Digest, Md5};
+use reqwest::{header::WWW_AUTHENTICATE, StatusCode};
+use serde::Deserialize;
+
+use crate::providers::RecordType;
+
+pub const DEFAULT_URL: &str = "http://fritz.box:49000";
+
+/// The IGD endpoint the box serves without a login when "transmit status information
+/// over UPnP" is enabled.
+const IGD_CONTROL: (&str, &str) = (
+    "/igdupnp/control/WANIPConn1",
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+);
+/// The TR-064 endpoint, which needs the box's credentials.
+const TR064_CONTROL: (&str, &str) = (
+    "/upnp/control/wanipconnection1",
+    "urn:dslforum-org:service:WANIPConnection:1",
+);
+
+/// Where to reach the FRITZ!Box. Without a username the unauthenticated IGD endpoint
+/// is used, otherwise TR-064 with HTTP digest authentication.
+#[derive(Deserialize, Clone)]
+pub struct FritzBoxConfig {
+    #[serde(default = "default_url")]
+    pub url: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+impl std::fmt::Debug for FritzBoxConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FritzBoxConfig")
+            .field("url", &self.url)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "********"))
+            .finish()
+    }
+}
+
+pub fn default_url() -> String {
+    DEFAULT_URL.to_owned()
+}
+
+impl Default for FritzBoxConfig {
+    fn default() -> Self {
+        Self {
+            url: default_url(),
+            username: None,
+            password: None,
+        }
+    }
+}
+
+impl FritzBoxConfig {
+    /// Reads the WAN address of `family` with `GetExternalIPAddress` or
+    /// `X_AVM_DE_GetExternalIPv6Address`.
+    pub async fn external_address(
+        &self,
+        family: RecordType,
+        timeout: Duration,
+    ) -> Result<IpAddr, Error> {
+        let (action, field) = match family {
+            RecordType::Aaaa => ("X_AVM_DE_GetExternalIPv6Address", "NewExternalIPv6Address"),
+            _ => ("GetExternalIPAddress", "NewExternalIPAddress"),
+        };
+        let (path, service) = match self.username {
+            Some(_) => TR064_CONTROL,
+            None => IGD_CONTROL,
+        };
+        let url = format!("{}{path}", self.url.trim_end_matches('/'));
+        let body = format!(
+            "<?xml version=\"1.0\"?>\
+             <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+             s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+             <s:Body><u:{action} xmlns:u=\"{service}\"/></s:Body></s:Envelope>"
+        );
+        let client = reqwest::Client::builder().timeout(timeout).build()?;
+        let request = || {
+            client
+                .post(&url)
+                .header("Content-Type", "text/xml; charset=\"utf-8\"")
+                .header("SOAPAction", format!("{service}#{action}"))
+                .body(body.clone())
+        };
+        let mut response = request().send().await?;
+        if let (StatusCode::UNAUTHORIZED, Some(username)) = (response.status(), &self.username) {
+            let challenge = response
+                .headers()
+                .get(WWW_AUTHENTICATE)
+                .ok_or(anyhow!(
+                    "{url} asked for a login without a digest challenge."
+                ))?
+                .to_str()?
+                .to_owned();
+            let password = self.password.as_deref().unwrap_or_default();
+            let authorization = digest_authorization(&challenge, username, password, path)?;
+            response = request()
+                .header("Authorization", authorization)
+                .send()
+                .await?;
+        }
+        let text = response.error_for_status()?.text().await?;
+        xml_value(&text, field)
+            .ok_or(anyhow!("{url} returned no {field}."))?
+            .parse()
+            .with_context(|| format!("{url} returned an invalid {field}"))
+    }
+}
+
+/// Answers an HTTP digest challenge (RFC 7616, MD5 with `qop=auth`) for a POST to `uri`.
+fn digest_authorization(
+    challenge: &str,
+    username: &str,
+    password: &str,
+    uri: &str,
+) -> Result<String, Error> {
+    let param = |name: &str| {
+        challenge
+            .trim_start_matches("Digest")
+            .split(',')
+            .filter_map(|part| part.trim().split_once('='))
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim_matches('"').to_owned())
+    };
+    let realm = param("realm").ok_or(anyhow!("Digest challenge has no realm."))?;
+    let nonce = param("nonce").ok_or(anyhow!("Digest challenge has no nonce."))?;
+    let md5 = |text: String| format!("{:x}", Md5::digest(text.as_bytes()));
+    let cnonce = format!("{:016x}", fastrand::u64(..));
+    let ha1 = md5(format!("{username}:{realm}:{password}"));
+    let ha2 = md5(format!("POST:{uri}"));
+    let response = md5(format!("{ha1}:{nonce}:00000001:{cnonce}:auth:{ha2}"));
+    Ok(format!(
+        "Digest username=\"{username}\", realm=\"{realm}\", nonce=\"{nonce}\", uri=\"{uri}\", \
+         response=\"{response}\", qop=auth, nc=00000001, cnonce=\"{cnonce}\""
+    ))
+}
+
+/// The text of the first `<field>` element, which is all a SOAP response needs here.
+fn xml_value<'a>(xml: &'a str, field: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{field}>"))? + field.len() + 2;
+    let end = start + xml[start..].find(&format!("</{field}>"))?;
+    Some(xml[start..end].trim())
+}
@@ -0,0 +1,80 @@
+use std::{net::IpAddr, time::Duration};
+
+use anyhow::{anyhow, Error};
+use serde::Deserialize;
+
+use crate::{ip_validation, providers::RecordType};
+
+/// A MikroTik router running RouterOS 7, whose REST API is asked for the address of
+/// the WAN interface.
+#[derive(Deserialize, Clone)]
+pub struct MikrotikConfig {
+    /// Base URL of the router's web service, e.g. `https://192.168.88.1`.
+    pub url: String,
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    /// The WAN interface, e.g. `ether1` or `pppoe-out1`.
+    pub interface: String,
+}
+
+impl std::fmt::Debug for MikrotikConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MikrotikConfig")
+            .field("url", &self.url)
+            .field("username", &self.username)
+            .field("password", &"********")
+            .field("interface", &self.interface)
+            .finish()
+    }
+}
+
+/// An entry of `/ip/address` or `/ipv6/address`. RouterOS returns every value as a
+/// string, including booleans.
+#[derive(Deserialize)]
+struct InterfaceAddress {
+    address: String,
+    #[serde(default)]
+    disabled: String,
+    #[serde(default)]
+    invalid: String,
+}
+
+impl MikrotikConfig {
+    /// Reads the first active address of `family` on the WAN interface, skipping
+    /// link-local IPv6 addresses.
+    pub async fn external_address(
+        &self,
+        family: RecordType,
+        timeout: Duration,
+    ) -> Result<IpAddr, Error> {
+        let path = match family {
+            RecordType::Aaaa => "ipv6/address",
+            _ => "ip/address",
+        };
+        let url = format!("{}/rest/{path}", self.url.trim_end_matches('/'));
+        let addresses: Vec<InterfaceAddress> = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()?
+            .get(&url)
+            .query(&[("interface", &self.interface)])
+            .basic_auth(&self.username, Some(&self.password))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        addresses
+            .iter()
+            .filter(|entry| entry.disabled != "true" && entry.invalid != "true")
+            .filter_map(|entry| entry.address.split('/').next()?.parse().ok())
+            .find(|ip: &IpAddr| match ip {
+                IpAddr::V6(ip) => !ip_validation::is_link_local(ip),
+                IpAddr::V4(_) => true,
+            })
+            .ok_or(anyhow!(
+                "{} has no active {family} address on {url}.",
+                self.interface
+            ))
+    }
+}
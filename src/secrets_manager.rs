@@ -0,0 +1,85 @@
+use anyhow::{anyhow, Context, Error};
+use aws_config::{Region, SdkConfig};
+use aws_credential_types::{
+    provider::{self, error::CredentialsError, future, ProvideCredentials},
+    Credentials,
+};
+use aws_sdk_secretsmanager::Client;
+use log::info;
+
+use crate::credential_provider::AwsCredentials;
+
+/// Reads the keys used for Route53 from a Secrets Manager secret holding
+/// `{"access_key_id", "secret_access_key", "session_token"}`, with the credentials of the
+/// SDK's default chain, e.g. the instance role. The secret is read again every time the
+/// SDK's credential cache wants fresh credentials, so rotating it needs no restart.
+#[derive(Debug)]
+pub struct SecretsManagerCredentials {
+    client: Client,
+    secret_id: String,
+}
+
+impl SecretsManagerCredentials {
+    pub fn new(config: &SdkConfig, secret_id: &str) -> Self {
+        let config = aws_sdk_secretsmanager::config::Builder::from(config);
+        // Secrets Manager only answers for secrets in the client's own region.
+        let config = match secret_region(secret_id) {
+            Some(region) => config.region(Region::new(region.to_owned())),
+            None => config,
+        };
+        Self {
+            client: Client::from_conf(config.build()),
+            secret_id: secret_id.to_owned(),
+        }
+    }
+
+    async fn credentials(&self) -> Result<Credentials, Error> {
+        let output = self
+            .client
+            .get_secret_value()
+            .secret_id(&self.secret_id)
+            .send()
+            .await
+            .with_context(|| format!("Failed to read secret {}", self.secret_id))?;
+        let text = output
+            .secret_string()
+            .ok_or(anyhow!("Secret {} has no string value.", self.secret_id))?;
+        let credentials: AwsCredentials = serde_json::from_str(text)
+            .with_context(|| format!("Secret {} is not a set of AWS keys", self.secret_id))?;
+        info!(
+            "Read AWS credentials from secret {} version {}",
+            self.secret_id,
+            output.version_id().unwrap_or("unknown")
+        );
+        Ok(Credentials::new(
+            credentials.access_key_id,
+            credentials.secret_access_key,
+            credentials.session_token,
+            credentials.expires_after,
+            "SecretsManager",
+        ))
+    }
+
+    async fn provide(&self) -> provider::Result {
+        self.credentials()
+            .await
+            .map_err(CredentialsError::provider_error)
+    }
+}
+
+impl ProvideCredentials for SecretsManagerCredentials {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(self.provide())
+    }
+}
+
+/// The region in a secret ARN, `arn:aws:secretsmanager:<region>:<account>:secret:<name>`.
+/// Plain secret names are looked up in the configured region.
+fn secret_region(secret_id: &str) -> Option<&str> {
+    let mut parts = secret_id.strip_prefix("arn:")?.split(':');
+    let (_partition, service, region) = (parts.next()?, parts.next()?, parts.next()?);
+    (service == "secretsmanager" && !region.is_empty()).then_some(region)
+}
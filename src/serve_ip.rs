@@ -0,0 +1,121 @@
+use std::net::{IpAddr, SocketAddr};
+
+use anyhow::Error;
+use axum::{
+    extract::{ConnectInfo, State},
+    http::HeaderMap,
+    routing::get,
+    Router,
+};
+use log::info;
+use tokio_util::sync::CancellationToken;
+
+use crate::http;
+
+/// Serves the caller's address as plain text on `/` and `/ip`.
+pub async fn serve_ip(
+    bind: SocketAddr,
+    trusted_proxies: Vec<IpAddr>,
+    shutdown_token: CancellationToken,
+) -> Result<(), Error> {
+    let listener = tokio::net::TcpListener::bind(bind).await?;
+    info!("Serving client IPs on {bind}");
+    http::serve(listener, router(trusted_proxies), shutdown_token).await
+}
+
+fn router(trusted_proxies: Vec<IpAddr>) -> Router {
+    Router::new()
+        .route("/", get(client_ip))
+        .route("/ip", get(client_ip))
+        .with_state(trusted_proxies)
+}
+
+async fn client_ip(
+    State(trusted_proxies): State<Vec<IpAddr>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> String {
+    let forwarded_for = headers
+        .get("x-forwarded-for")
+        .and_then(|value| value.to_str().ok());
+    resolve_client_ip(peer.ip(), forwarded_for, &trusted_proxies).to_string()
+}
+
+/// The peer address, unless the peer is a trusted proxy, in which case the rightmost
+/// `X-Forwarded-For` entry that is not itself a trusted proxy.
+pub fn resolve_client_ip(
+    peer: IpAddr,
+    forwarded_for: Option<&str>,
+    trusted_proxies: &[IpAddr],
+) -> IpAddr {
+    if !trusted_proxies.contains(&peer) {
+        return peer;
+    }
+    forwarded_for
+        .into_iter()
+        .flat_map(|value| value.split(','))
+        .rev()
+        .filter_map(|entry| entry.trim().parse::<IpAddr>().ok())
+        .find(|ip| !trusted_proxies.contains(ip))
+        .unwrap_or(peer)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn trusts_forwarded_for_only_from_a_trusted_proxy() {
+        let proxies = [ip("10.0.0.2"), ip("10.0.0.3")];
+        let forwarded = Some("192.0.2.9, 198.51.100.7, 10.0.0.3");
+
+        assert_eq!(
+            resolve_client_ip(ip("203.0.113.5"), forwarded, &proxies),
+            ip("203.0.113.5")
+        );
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.2"), forwarded, &proxies),
+            ip("198.51.100.7")
+        );
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.2"), Some("unknown, 10.0.0.3"), &proxies),
+            ip("10.0.0.2")
+        );
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.2"), None, &proxies),
+            ip("10.0.0.2")
+        );
+    }
+
+    #[tokio::test]
+    async fn answers_with_the_callers_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let shutdown = CancellationToken::new();
+        let server = tokio::spawn(http::serve(
+            listener,
+            router(vec![ip("127.0.0.1")]),
+            shutdown.clone(),
+        ));
+        let client = reqwest::Client::new();
+
+        let direct = client.get(format!("{url}/ip")).send().await.unwrap();
+        assert_eq!(direct.text().await.unwrap(), "127.0.0.1");
+        let proxied = client
+            .get(&url)
+            .header("X-Forwarded-For", "198.51.100.7")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(proxied.text().await.unwrap(), "198.51.100.7");
+
+        shutdown.cancel();
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Context, Error};
+use aws_config::Region;
+use aws_sdk_ssm::Client;
+use log::info;
+use toml::Value;
+
+use crate::aws_config_loader;
+
+/// Config values starting with this are read from SSM Parameter Store, e.g.
+/// `ssm://route53_dd/home/secret_access_key` for the parameter
+/// `/route53_dd/home/secret_access_key`.
+pub const REFERENCE_PREFIX: &str = "ssm://";
+
+/// Replaces every string in `config` that references an SSM parameter with the
+/// parameter's value, decrypting `SecureString` parameters. The parameters are read with
+/// the default credential chain, in `region` or else the chain's region.
+pub async fn resolve_references(config: &mut Value, region: Option<&str>) -> Result<(), Error> {
+    let mut resolved = BTreeMap::new();
+    collect(config, &mut resolved);
+    if resolved.is_empty() {
+        return Ok(());
+    }
+    let loader = aws_config_loader(&[]);
+    let loader = match region {
+        Some(region) => loader.region(Region::new(region.to_owned())),
+        None => loader,
+    };
+    let client = Client::new(&loader.load().await);
+    for (reference, value) in &mut resolved {
+        *value = parameter(&client, reference).await?;
+    }
+    replace(config, &resolved);
+    Ok(())
+}
+
+fn collect(value: &Value, references: &mut BTreeMap<String, String>) {
+    match value {
+        Value::String(s) if s.starts_with(REFERENCE_PREFIX) => {
+            references.insert(s.clone(), String::new());
+        }
+        Value::Array(values) => values.iter().for_each(|value| collect(value, references)),
+        Value::Table(table) => table.values().for_each(|value| collect(value, references)),
+        _ => {}
+    }
+}
+
+fn replace(value: &mut Value, resolved: &BTreeMap<String, String>) {
+    match value {
+        Value::String(s) => {
+            if let Some(parameter) = resolved.get(s.as_str()) {
+                *s = parameter.clone();
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(|value| replace(value, resolved)),
+        Value::Table(table) => table
+            .iter_mut()
+            .for_each(|(_, value)| replace(value, resolved)),
+        _ => {}
+    }
+}
+
+/// The parameter a reference names. Hierarchical names get back the leading slash that
+/// `ssm://` swallows, while plain names and ARNs are used as they are.
+fn parameter_name(reference: &str) -> String {
+    let name = &reference[REFERENCE_PREFIX.len()..];
+    if name.contains('/') && !name.starts_with('/') && !name.starts_with("arn:") {
+        format!("/{name}")
+    } else {
+        name.to_owned()
+    }
+}
+
+async fn parameter(client: &Client, reference: &str) -> Result<String, Error> {
+    let name = parameter_name(reference);
+    let output = client
+        .get_parameter()
+        .name(&name)
+        .with_decryption(true)
+        .send()
+        .await
+        .with_context(|| format!("Failed to read SSM parameter {name}"))?;
+    let value = output
+        .parameter()
+        .and_then(|parameter| parameter.value())
+        .ok_or(anyhow!("SSM parameter {name} has no value."))?;
+    info!("Read a config value from SSM parameter {name}");
+    Ok(value.to_owned())
+}
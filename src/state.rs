@@ -0,0 +1,335 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    net::IpAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Error};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{change_limiter::ChangeLimiter, providers::RecordType};
+
+pub type SharedState = Arc<Mutex<DaemonState>>;
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct ProviderHealth {
+    pub successes: u64,
+    pub failures: u64,
+    pub last_error: Option<String>,
+}
+
+/// In-memory view of what the daemon has done so far. Timestamps are unix seconds.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct DaemonState {
+    pub last_ipv4: Option<IpAddr>,
+    pub last_ipv6: Option<IpAddr>,
+    pub last_success: Option<u64>,
+    pub last_failure: Option<u64>,
+    pub last_error: Option<String>,
+    pub last_ip_change: Option<u64>,
+    pub change_count: u64,
+    /// Calls to the DNS backend that failed, counting every retry, for the failure budget.
+    pub failed_calls: u64,
+    pub providers: BTreeMap<String, ProviderHealth>,
+    /// Every value known to be served, keyed by record name and type.
+    #[serde(deserialize_with = "published_sets")]
+    pub published: BTreeMap<String, BTreeSet<IpAddr>>,
+    /// The IP source last used successfully, keyed by record type.
+    pub active_ip_sources: BTreeMap<String, String>,
+    /// Route53 changes still propagating, keyed by change ID, with their submit time.
+    /// Nobody tracks them after a restart, so they aren't restored.
+    #[serde(skip_deserializing)]
+    pub pending_changes: BTreeMap<String, u64>,
+    /// The address last found behind carrier-grade NAT, so that is only warned about
+    /// once per run.
+    #[serde(skip_deserializing)]
+    pub cgnat_address: Option<IpAddr>,
+    /// The plan a dry run printed last, so the same one isn't printed every interval.
+    #[serde(skip)]
+    pub dry_run_plan: Vec<String>,
+    /// When each record was last written, for the minimum change interval.
+    #[serde(skip)]
+    pub change_limiter: ChangeLimiter,
+}
+
+impl DaemonState {
+    pub fn shared() -> SharedState {
+        Arc::new(Mutex::new(DaemonState::default()))
+    }
+
+    pub fn record_ip(&mut self, ip: IpAddr) {
+        match ip {
+            IpAddr::V4(_) => self.last_ipv4 = Some(ip),
+            IpAddr::V6(_) => self.last_ipv6 = Some(ip),
+        }
+    }
+
+    pub fn record_change(&mut self) {
+        self.change_count += 1;
+    }
+
+    pub fn record_ip_change(&mut self) {
+        self.last_ip_change = Some(unix_now());
+    }
+
+    /// Remembers `ip` as being behind carrier-grade NAT, returning whether it differs
+    /// from the address found there before.
+    pub fn record_cgnat_address(&mut self, ip: IpAddr) -> bool {
+        self.cgnat_address.replace(ip) != Some(ip)
+    }
+
+    pub fn record_success(&mut self) {
+        self.last_success = Some(unix_now());
+    }
+
+    pub fn record_failure(&mut self, error: &impl Display) {
+        self.last_failure = Some(unix_now());
+        self.last_error = Some(error.to_string());
+    }
+
+    pub fn record_provider_success(&mut self, provider: &str) {
+        self.providers
+            .entry(provider.to_owned())
+            .or_default()
+            .successes += 1;
+    }
+
+    pub fn record_provider_failure(&mut self, provider: &str, error: &impl Display) {
+        let health = self.providers.entry(provider.to_owned()).or_default();
+        health.failures += 1;
+        health.last_error = Some(error.to_string());
+    }
+
+    /// Remembers `ips` as the whole set of values of their type currently served for
+    /// `record`.
+    pub fn record_published(&mut self, record: &str, ips: BTreeSet<IpAddr>) {
+        let Some(ip) = ips.first() else {
+            return;
+        };
+        self.published
+            .insert(published_key(record, RecordType::for_ip(ip)), ips);
+    }
+
+    pub fn published(&self, record: &str, record_type: RecordType) -> Option<&BTreeSet<IpAddr>> {
+        self.published.get(&published_key(record, record_type))
+    }
+
+    /// Whether `record` serves exactly `ips` for their type, nothing more or less.
+    pub fn is_published(&self, record: &str, ips: &BTreeSet<IpAddr>) -> bool {
+        ips.first()
+            .is_some_and(|ip| self.published(record, RecordType::for_ip(ip)) == Some(ips))
+    }
+}
+
+/// Pretty JSON of the state of a single zone as is, or of several zones keyed by
+/// record name, for state dumps.
+pub fn snapshot(states: &[(String, SharedState)]) -> Result<String, Error> {
+    if let [(_, state)] = states {
+        return Ok(serde_json::to_string_pretty(&*state.lock().unwrap())?);
+    }
+    let snapshot: BTreeMap<&str, DaemonState> = states
+        .iter()
+        .map(|(record, state)| (record.as_str(), state.lock().unwrap().clone()))
+        .collect();
+    Ok(serde_json::to_string_pretty(&snapshot)?)
+}
+
+/// The state of every record, kept on disk with `--state-file` so restarts and
+/// single-shot runs know what was already pushed.
+#[derive(Clone, Debug)]
+pub struct StateFile {
+    path: PathBuf,
+    records: Arc<Mutex<Records>>,
+    /// The version of the records last written, held while writing so a slow write
+    /// never replaces a newer one.
+    written: Arc<Mutex<u64>>,
+}
+
+#[derive(Debug, Default)]
+struct Records {
+    states: BTreeMap<String, DaemonState>,
+    /// Counts the changes, so writes can tell which snapshot is newer.
+    version: u64,
+}
+
+impl StateFile {
+    /// Reads the file, starting empty when it doesn't exist yet.
+    pub fn load(path: PathBuf) -> Result<Self, Error> {
+        let states = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text)
+                .with_context(|| format!("Invalid state file {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path,
+            records: Arc::new(Mutex::new(Records { states, version: 0 })),
+            written: Arc::default(),
+        })
+    }
+
+    /// The stored state of `record`, or a fresh one.
+    pub fn restore(&self, record: &str) -> SharedState {
+        let state = self.records.lock().unwrap().states.get(record).cloned();
+        Arc::new(Mutex::new(state.unwrap_or_default()))
+    }
+
+    /// Stores the state of `record` and rewrites the file atomically. The file is written
+    /// outside the lock, so other zones can record their state meanwhile.
+    pub fn save(&self, record: &str, state: &DaemonState) -> Result<(), Error> {
+        let (version, json) = {
+            let mut records = self.records.lock().unwrap();
+            records.states.insert(record.to_owned(), state.clone());
+            records.version += 1;
+            (
+                records.version,
+                serde_json::to_string_pretty(&records.states)?,
+            )
+        };
+        let mut written = self.written.lock().unwrap();
+        if *written > version {
+            return Ok(());
+        }
+        let file_name = self
+            .path
+            .file_name()
+            .context("The state file path has no file name")?
+            .to_string_lossy();
+        let tmp = self
+            .path
+            .with_file_name(format!(".{file_name}.{}.{version}.tmp", std::process::id()));
+        std::fs::write(&tmp, json)?;
+        std::fs::rename(&tmp, &self.path)?;
+        *written = version;
+        Ok(())
+    }
+}
+
+/// Also reads state files from before whole sets were kept, which hold a single value.
+fn published_sets<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, BTreeSet<IpAddr>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(IpAddr),
+        Many(BTreeSet<IpAddr>),
+    }
+    Ok(BTreeMap::<String, OneOrMany>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(key, values)| match values {
+            OneOrMany::One(ip) => (key, BTreeSet::from([ip])),
+            OneOrMany::Many(ips) => (key, ips),
+        })
+        .collect())
+}
+
+fn published_key(record: &str, record_type: RecordType) -> String {
+    format!("{record} {record_type}")
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ips(ips: &[&str]) -> BTreeSet<IpAddr> {
+        ips.iter().map(|ip| ip.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn saves_every_record_without_leaving_temp_files() {
+        let dir = crate::test_util::temp_path("state-dir");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("state.json");
+        let file = StateFile::load(path.clone()).unwrap();
+        let mut state = DaemonState::default();
+        state.record_published("home.example.com", ips(&["8.8.8.8"]));
+        file.save("home.example.com", &state).unwrap();
+        file.save("www.example.com", &DaemonState::default())
+            .unwrap();
+
+        let restored = StateFile::load(path).unwrap();
+        assert!(restored
+            .restore("home.example.com")
+            .lock()
+            .unwrap()
+            .is_published("home.example.com", &ips(&["8.8.8.8"])));
+        let files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, ["state.json"]);
+    }
+
+    #[test]
+    fn keeps_the_whole_set_per_type() {
+        let mut state = DaemonState::default();
+        state.record_published("home.example.com", ips(&["8.8.8.8", "8.8.4.4"]));
+        state.record_published("home.example.com", ips(&["2001:4860::8888"]));
+
+        assert!(state.is_published("home.example.com", &ips(&["8.8.4.4", "8.8.8.8"])));
+        assert!(!state.is_published("home.example.com", &ips(&["8.8.8.8"])));
+        assert!(state.is_published("home.example.com", &ips(&["2001:4860::8888"])));
+        assert!(!state.is_published("www.example.com", &ips(&["8.8.8.8"])));
+    }
+
+    #[test]
+    fn dumps_the_state_of_every_zone() {
+        let home = DaemonState::shared();
+        {
+            let mut state = home.lock().unwrap();
+            state.record_ip("8.8.8.8".parse().unwrap());
+            state.record_change();
+            state.record_published("home.example.com", ips(&["8.8.8.8"]));
+            state.record_provider_failure("route53", &"Throttling");
+            state.pending_changes.insert("C1".to_owned(), 1_700_000_000);
+            state.dry_run_plan = vec!["+ create".to_owned()];
+        }
+        let www = DaemonState::shared();
+
+        let single: serde_json::Value = serde_json::from_str(
+            &snapshot(&[("home.example.com".to_owned(), home.clone())]).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(single["last_ipv4"], "8.8.8.8");
+        assert_eq!(single["last_ipv6"], serde_json::Value::Null);
+        assert_eq!(single["change_count"], 1);
+        assert_eq!(single["published"]["home.example.com A"][0], "8.8.8.8");
+        assert_eq!(single["providers"]["route53"]["failures"], 1);
+        assert_eq!(single["providers"]["route53"]["last_error"], "Throttling");
+        assert_eq!(single["pending_changes"]["C1"], 1_700_000_000);
+        assert!(single.get("dry_run_plan").is_none());
+
+        let both: serde_json::Value = serde_json::from_str(
+            &snapshot(&[
+                ("home.example.com".to_owned(), home),
+                ("www.example.com".to_owned(), www),
+            ])
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(both["home.example.com"], single);
+        assert_eq!(both["www.example.com"]["change_count"], 0);
+    }
+
+    #[test]
+    fn reads_single_values_from_older_state_files() {
+        let state: DaemonState = serde_json::from_str(
+            r#"{"published": {"home.example.com A": "8.8.8.8", "home.example.com AAAA": ["2001:4860::8888"]}}"#,
+        )
+        .unwrap();
+        assert!(state.is_published("home.example.com", &ips(&["8.8.8.8"])));
+        assert!(state.is_published("home.example.com", &ips(&["2001:4860::8888"])));
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    collections::VecDeque,
+    io::ErrorKind,
+    net::{ToSocketAddrs, UdpSocket},
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, Error};
+use log::debug;
+
+const PREFIX: &str = "route53_dd";
+
+/// Metrics kept while the socket's send buffer is full, the oldest are dropped first.
+const MAX_UNSENT: usize = 1000;
+
+/// Pushes DogStatsD formatted metrics over UDP. Send failures are only logged at debug.
+#[derive(Debug)]
+pub struct StatsdClient {
+    socket: UdpSocket,
+    /// Metrics the socket had no room for yet, sent before the next one and on flush.
+    unsent: Mutex<VecDeque<String>>,
+}
+
+impl StatsdClient {
+    pub fn new(addr: &str) -> Result<Self, Error> {
+        let target = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or(anyhow!("StatsD address {addr} did not resolve."))?;
+        let bind = if target.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(bind)?;
+        socket.connect(target)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            unsent: Mutex::default(),
+        })
+    }
+
+    pub fn count(&self, name: &str, value: u64, tags: &[(&str, &str)]) {
+        self.send(format_metric(name, &value.to_string(), "c", tags));
+    }
+
+    pub fn gauge(&self, name: &str, value: u64, tags: &[(&str, &str)]) {
+        self.send(format_metric(name, &value.to_string(), "g", tags));
+    }
+
+    /// Sends the metrics the socket had no room for, dropping what still doesn't fit.
+    pub fn flush(&self) {
+        let mut unsent = self.unsent.lock().unwrap();
+        self.send_unsent(&mut unsent);
+        for metric in unsent.drain(..) {
+            debug!("Dropping metric {metric:?}, the socket is still full.");
+        }
+    }
+
+    fn send(&self, metric: String) {
+        let mut unsent = self.unsent.lock().unwrap();
+        if unsent.len() == MAX_UNSENT {
+            unsent.pop_front();
+        }
+        unsent.push_back(metric);
+        self.send_unsent(&mut unsent);
+    }
+
+    fn send_unsent(&self, unsent: &mut VecDeque<String>) {
+        while let Some(metric) = unsent.front() {
+            match self.socket.send(metric.as_bytes()) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => debug!("Failed to send metric {metric:?}: {e:?}"),
+                Ok(_) => {}
+            }
+            unsent.pop_front();
+        }
+    }
+}
+
+pub fn format_metric(name: &str, value: &str, kind: &str, tags: &[(&str, &str)]) -> String {
+    let mut metric = format!("{PREFIX}.{name}:{value}|{kind}");
+    if !tags.is_empty() {
+        let tags: Vec<String> = tags.iter().map(|(k, v)| format!("{k}:{v}")).collect();
+        metric.push_str("|#");
+        metric.push_str(&tags.join(","));
+    }
+    metric
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn formats_dogstatsd_lines() {
+        assert_eq!(
+            format_metric("updates", "1", "c", &[]),
+            "route53_dd.updates:1|c"
+        );
+        assert_eq!(
+            format_metric(
+                "last_success",
+                "1700000000",
+                "g",
+                &[("zone", "example.com"), ("record", "home.example.com")]
+            ),
+            "route53_dd.last_success:1700000000|g|#zone:example.com,record:home.example.com"
+        );
+    }
+
+    #[test]
+    fn needs_an_address_with_a_port() {
+        assert!(StatsdClient::new("127.0.0.1").is_err());
+        assert!(StatsdClient::new("not an address").is_err());
+        assert!(StatsdClient::new("127.0.0.1:8125").is_ok());
+    }
+
+    #[test]
+    fn drops_metrics_nobody_receives() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = collector.local_addr().unwrap();
+        drop(collector);
+        let client = StatsdClient::new(&addr.to_string()).unwrap();
+
+        // The first send may be refused by the closed port, later ones too.
+        for _ in 0..3 {
+            client.count("updates", 1, &[]);
+        }
+        client.flush();
+
+        assert!(client.unsent.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn sends_what_was_kept_before_new_metrics() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        collector
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let client = StatsdClient::new(&collector.local_addr().unwrap().to_string()).unwrap();
+        client
+            .unsent
+            .lock()
+            .unwrap()
+            .push_back("route53_dd.failures:1|c".to_owned());
+
+        client.gauge("ip_changes", 2, &[]);
+
+        let mut buf = [0; 256];
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            let n = collector.recv(&mut buf).unwrap();
+            received.push(String::from_utf8_lossy(&buf[..n]).into_owned());
+        }
+        assert_eq!(
+            received,
+            ["route53_dd.failures:1|c", "route53_dd.ip_changes:2|g"]
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Error};
+use log::warn;
+use tokio::{
+    net::{lookup_host, UdpSocket},
+    time,
+};
+
+use crate::providers::RecordType;
+
+pub const DEFAULT_STUN_SERVERS: [&str; 2] = ["stun.l.google.com:19302", "stun.cloudflare.com:3478"];
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const MAGIC_COOKIE: u32 = 0x2112_a442;
+const MAPPED_ADDRESS: u16 = 0x0001;
+const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const HEADER_LEN: usize = 20;
+
+pub fn default_stun_servers() -> Vec<String> {
+    DEFAULT_STUN_SERVERS.map(str::to_owned).to_vec()
+}
+
+/// Asks the STUN servers in order for the address our binding request came from and
+/// returns the first answer.
+pub async fn external_address(
+    servers: &[String],
+    family: RecordType,
+    timeout: Duration,
+) -> Result<IpAddr, Error> {
+    let mut last_error = anyhow!("No STUN server configured.");
+    for server in servers {
+        match time::timeout(timeout, binding_request(server, family)).await {
+            Ok(Ok(ip)) => return Ok(ip),
+            Ok(Err(e)) => last_error = e,
+            Err(_) => last_error = anyhow!("{server} did not answer within {timeout:?}."),
+        }
+        warn!("STUN server {server} failed: {last_error:?}");
+    }
+    Err(last_error.context("Every STUN server failed"))
+}
+
+async fn binding_request(server: &str, family: RecordType) -> Result<IpAddr, Error> {
+    let want_v4 = family != RecordType::Aaaa;
+    let target = lookup_host(server)
+        .await
+        .with_context(|| format!("Failed to resolve STUN server {server}"))?
+        .find(|addr| addr.is_ipv4() == want_v4)
+        .ok_or(anyhow!("STUN server {server} has no {family} address."))?;
+    let bind: SocketAddr = if want_v4 { "0.0.0.0:0" } else { "[::]:0" }.parse()?;
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(target).await?;
+
+    let transaction_id: [u8; 12] = std::array::from_fn(|_| fastrand::u8(..));
+    let mut request = Vec::with_capacity(HEADER_LEN);
+    request.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes());
+    request.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    request.extend_from_slice(&transaction_id);
+    socket.send(&request).await?;
+
+    let mut response = [0u8; 512];
+    let len = socket.recv(&mut response).await?;
+    parse_response(&response[..len], &transaction_id)
+        .with_context(|| format!("Invalid response from STUN server {server}"))
+}
+
+/// Reads the mapped address from a binding success response, preferring
+/// XOR-MAPPED-ADDRESS over the legacy MAPPED-ADDRESS.
+fn parse_response(response: &[u8], transaction_id: &[u8; 12]) -> Result<IpAddr, Error> {
+    if response.len() < HEADER_LEN {
+        return Err(anyhow!("Response is too short."));
+    }
+    let message_type = u16::from_be_bytes([response[0], response[1]]);
+    if message_type != BINDING_SUCCESS {
+        return Err(anyhow!("Unexpected message type {message_type:#06x}."));
+    }
+    if response[4..8] != MAGIC_COOKIE.to_be_bytes() || &response[8..20] != transaction_id {
+        return Err(anyhow!("Response doesn't match the request."));
+    }
+    let length = u16::from_be_bytes([response[2], response[3]]) as usize;
+    let mut attributes = response
+        .get(HEADER_LEN..HEADER_LEN + length)
+        .ok_or(anyhow!("Response is truncated."))?;
+    let mut mapped = None;
+    while attributes.len() >= 4 {
+        let kind = u16::from_be_bytes([attributes[0], attributes[1]]);
+        let len = u16::from_be_bytes([attributes[2], attributes[3]]) as usize;
+        let value = attributes
+            .get(4..4 + len)
+            .ok_or(anyhow!("Attribute {kind:#06x} is truncated."))?;
+        match kind {
+            XOR_MAPPED_ADDRESS => return parse_address(value, Some(transaction_id)),
+            MAPPED_ADDRESS => mapped = Some(parse_address(value, None)?),
+            _ => {}
+        }
+        // Attributes are padded to a multiple of four bytes.
+        let padded = (4 + len).next_multiple_of(4);
+        attributes = attributes.get(padded..).unwrap_or_default();
+    }
+    mapped.ok_or(anyhow!("Response has no mapped address."))
+}
+
+/// Decodes a (XOR-)MAPPED-ADDRESS value, un-XORing it when `transaction_id` is given.
+fn parse_address(value: &[u8], transaction_id: Option<&[u8; 12]>) -> Result<IpAddr, Error> {
+    let mut mask = [0u8; 16];
+    if let Some(transaction_id) = transaction_id {
+        mask[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        mask[4..].copy_from_slice(transaction_id);
+    }
+    let address = |len: usize| -> Result<Vec<u8>, Error> {
+        let bytes = value
+            .get(4..4 + len)
+            .ok_or(anyhow!("Mapped address is truncated."))?;
+        Ok(bytes.iter().zip(mask).map(|(b, m)| b ^ m).collect())
+    };
+    match value.get(1) {
+        Some(0x01) => {
+            let octets: [u8; 4] = address(4)?.try_into().unwrap();
+            Ok(IpAddr::V4(Ipv4Addr::from(octets)))
+        }
+        Some(0x02) => {
+            let octets: [u8; 16] = address(16)?.try_into().unwrap();
+            Ok(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        family => Err(anyhow!("Unknown address family {family:?}.")),
+    }
+}
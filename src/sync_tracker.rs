@@ -0,0 +1,161 @@
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Error};
+use aws_sdk_route53::types::ChangeStatus;
+use log::{info, warn};
+use tokio::{select, task::JoinHandle, time};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    providers::route53::Route53Provider,
+    retry,
+    state::{self, SharedState},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Follows submitted Route53 changes in the background and logs when each one is
+/// INSYNC, so propagation is visible without slowing down the update loop.
+#[derive(Clone, Debug)]
+pub struct SyncTracker {
+    state: SharedState,
+    timeout: Duration,
+    shutdown_token: CancellationToken,
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl SyncTracker {
+    pub fn new(state: SharedState, timeout: Duration, shutdown_token: CancellationToken) -> Self {
+        Self {
+            state,
+            timeout,
+            shutdown_token,
+            tasks: Arc::default(),
+        }
+    }
+
+    /// Records `change_id` as pending and polls it until it is INSYNC, the timeout
+    /// passes or the daemon shuts down, in which case it is checked one last time.
+    pub fn track(&self, provider: Route53Provider, change_id: String) {
+        self.state
+            .lock()
+            .unwrap()
+            .pending_changes
+            .insert(change_id.clone(), state::unix_now());
+        let tracker = self.clone();
+        let task = tokio::spawn(async move {
+            let started = Instant::now();
+            let polling = time::timeout(tracker.timeout, wait_for_insync(&provider, &change_id));
+            let outcome = select! {
+                outcome = polling => outcome,
+                _ = tracker.shutdown_token.cancelled() => {
+                    Ok(final_check(&provider, &change_id).await)
+                }
+            };
+            match outcome {
+                Ok(Ok(())) => info!(
+                    "Change {change_id} is INSYNC after {}s.",
+                    started.elapsed().as_secs()
+                ),
+                Ok(Err(e)) => warn!("Stopped tracking change {change_id}: {e:?}"),
+                Err(_) => warn!(
+                    "Change {change_id} was not INSYNC after {:?}, no longer tracking it.",
+                    tracker.timeout
+                ),
+            }
+            tracker
+                .state
+                .lock()
+                .unwrap()
+                .pending_changes
+                .remove(&change_id);
+        });
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(task);
+    }
+
+    /// Waits until every tracked change has been settled, which after shutdown takes one
+    /// status check per change.
+    pub async fn flush(&self) {
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        for task in tasks {
+            let _ = task.await;
+        }
+    }
+}
+
+async fn final_check(provider: &Route53Provider, change_id: &str) -> Result<(), Error> {
+    match provider.change_status(change_id).await? {
+        ChangeStatus::Insync => Ok(()),
+        status => Err(anyhow!("still {} at shutdown", status.as_str())),
+    }
+}
+
+/// Polls `change_id` until it is INSYNC. Transient failures to read its status are
+/// retried, the caller bounds the wait with a timeout.
+pub(crate) async fn wait_for_insync(
+    provider: &Route53Provider,
+    change_id: &str,
+) -> Result<(), Error> {
+    poll_until_insync(change_id, || provider.change_status(change_id)).await
+}
+
+async fn poll_until_insync<F, Fut>(change_id: &str, mut status: F) -> Result<(), Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<ChangeStatus, Error>>,
+{
+    loop {
+        match status().await {
+            Ok(ChangeStatus::Insync) => return Ok(()),
+            Ok(_) => {}
+            Err(e) if retry::is_transient(&e) => {
+                warn!("Checking the status of change {change_id} failed, retrying: {e:?}")
+            }
+            Err(e) => return Err(e),
+        }
+        time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    fn statuses(
+        results: Vec<Result<ChangeStatus, Error>>,
+    ) -> impl FnMut() -> std::future::Ready<Result<ChangeStatus, Error>> {
+        let mut results = VecDeque::from(results);
+        move || std::future::ready(results.pop_front().expect("polled after the last status"))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn polls_through_transient_errors_until_insync() {
+        let timeout: Error = std::io::Error::from(std::io::ErrorKind::TimedOut).into();
+        let started = time::Instant::now();
+        let status = statuses(vec![
+            Ok(ChangeStatus::Pending),
+            Err(timeout.context("GetChange failed")),
+            Ok(ChangeStatus::Insync),
+        ]);
+        poll_until_insync("C1", status).await.unwrap();
+        assert_eq!(started.elapsed(), POLL_INTERVAL * 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_on_a_permanent_error() {
+        let status = statuses(vec![
+            Ok(ChangeStatus::Pending),
+            Err(anyhow!("NoSuchChange")),
+        ]);
+        let err = poll_until_insync("C1", status).await.unwrap_err();
+        assert_eq!(err.to_string(), "NoSuchChange");
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    fmt::Display,
+    net::IpAddr,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Error};
+use serde::Deserialize;
+
+/// A fact a template refers to by name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Placeholder {
+    /// The IPv4 address, or else the IPv6 one.
+    Ip,
+    Ipv4,
+    Ipv6,
+    Hostname,
+    Iso8601,
+    Epoch,
+}
+
+impl Placeholder {
+    const ALL: [Self; 6] = [
+        Self::Ip,
+        Self::Ipv4,
+        Self::Ipv6,
+        Self::Hostname,
+        Self::Iso8601,
+        Self::Epoch,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Ip => "ip",
+            Self::Ipv4 => "ipv4",
+            Self::Ipv6 => "ipv6",
+            Self::Hostname => "hostname",
+            Self::Iso8601 => "iso8601",
+            Self::Epoch => "epoch",
+        }
+    }
+}
+
+/// The facts a template can refer to. Addresses that weren't detected render as empty
+/// text.
+#[derive(Clone, Debug)]
+pub struct TemplateValues {
+    pub ipv4: Option<IpAddr>,
+    pub ipv6: Option<IpAddr>,
+    pub hostname: String,
+    pub now: SystemTime,
+}
+
+impl TemplateValues {
+    pub fn new(ipv4: Option<IpAddr>, ipv6: Option<IpAddr>) -> Self {
+        Self {
+            ipv4,
+            ipv6,
+            hostname: hostname::get()
+                .ok()
+                .and_then(|name| name.into_string().ok())
+                .unwrap_or_default(),
+            now: SystemTime::now(),
+        }
+    }
+
+    fn lookup(&self, placeholder: Placeholder) -> String {
+        let address = |ip: Option<IpAddr>| ip.map(|ip| ip.to_string()).unwrap_or_default();
+        match placeholder {
+            Placeholder::Ip => address(self.ipv4.or(self.ipv6)),
+            Placeholder::Ipv4 => address(self.ipv4),
+            Placeholder::Ipv6 => address(self.ipv6),
+            Placeholder::Hostname => self.hostname.clone(),
+            Placeholder::Iso8601 => humantime::format_rfc3339_seconds(self.now).to_string(),
+            Placeholder::Epoch => self
+                .now
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default()
+                .to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// Text with `{name}` placeholders for [`TemplateValues`], shared by every setting that
+/// renders detected facts. `{{` and `}}` stand for literal braces.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Template {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn render(&self, values: &TemplateValues) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(text) => text.clone(),
+                Segment::Placeholder(placeholder) => values.lookup(*placeholder),
+            })
+            .collect()
+    }
+}
+
+impl FromStr for Template {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(anyhow!("Unclosed {{ in template {s:?}.")),
+                        }
+                    }
+                    let placeholder = Placeholder::ALL
+                        .into_iter()
+                        .find(|placeholder| placeholder.name() == name)
+                        .ok_or_else(|| {
+                            anyhow!(
+                                "Unknown placeholder {{{name}}} in template {s:?}, expected one of {}.",
+                                Placeholder::ALL.map(|p| format!("{{{}}}", p.name())).join(", ")
+                            )
+                        })?;
+                    segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    segments.push(Segment::Placeholder(placeholder));
+                }
+                '}' => {
+                    return Err(anyhow!(
+                        "Unmatched }} in template {s:?}, write }}}} for a literal brace."
+                    ))
+                }
+                _ => literal.push(c),
+            }
+        }
+        segments.push(Segment::Literal(literal));
+        segments.retain(|segment| segment != &Segment::Literal(String::new()));
+        Ok(Self {
+            source: s.to_owned(),
+            segments,
+        })
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for Template {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn values() -> TemplateValues {
+        TemplateValues {
+            ipv4: None,
+            ipv6: Some("2001:4860::8888".parse().unwrap()),
+            hostname: "gateway".to_owned(),
+            now: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        }
+    }
+
+    #[test]
+    fn renders_every_placeholder() {
+        let template: Template = "{hostname} {ip} [{ipv4}] {iso8601} {epoch} {{literal}}"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            template.render(&values()),
+            "gateway 2001:4860::8888 [] 2023-11-14T22:13:20Z 1700000000 {literal}"
+        );
+        assert_eq!(
+            template.to_string(),
+            "{hostname} {ip} [{ipv4}] {iso8601} {epoch} {{literal}}"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_templates() {
+        for template in ["{timestamp}", "{ipv4", "ipv4}"] {
+            assert!(template.parse::<Template>().is_err(), "{template}");
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Helpers shared by the unit tests.
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use aws_config::{retry::RetryConfig, BehaviorVersion, ConfigLoader, Region};
+use aws_credential_types::Credentials;
+use aws_sdk_route53::config::SharedHttpClient;
+use aws_smithy_runtime::client::http::hyper_014::HyperClientBuilder;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    clients::ZoneClients, desktop::Notifier, state::DaemonState, HostedZoneConfig, UpdateContext,
+};
+
+/// One request received by [`http_server`].
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    /// The path with its query string.
+    pub target: String,
+    /// Header names are lowercase.
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+/// Serves HTTP on a local port, answering each request with the status and body
+/// `respond` returns for it. Returns the base URL and every request received so far.
+pub async fn http_server<F>(respond: F) -> (String, Arc<Mutex<Vec<Request>>>)
+where
+    F: Fn(&Request) -> (u16, String) + Send + Sync + 'static,
+{
+    http_server_with_headers(move |request| {
+        let (status, body) = respond(request);
+        (status, Vec::new(), body)
+    })
+    .await
+}
+
+/// Like [`http_server`], but `respond` also returns headers to add to the response.
+pub async fn http_server_with_headers<F>(respond: F) -> (String, Arc<Mutex<Vec<Request>>>)
+where
+    F: Fn(&Request) -> (u16, Vec<(String, String)>, String) + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let received = requests.clone();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let request = read_request(&mut stream).await;
+            let (status, headers, body) = respond(&request);
+            received.lock().unwrap().push(request);
+            let headers: String = headers
+                .iter()
+                .map(|(name, value)| format!("{name}: {value}\r\n"))
+                .collect();
+            let response = format!(
+                "HTTP/1.1 {status} X\r\n{headers}Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+    });
+    (url, requests)
+}
+
+async fn read_request(stream: &mut tokio::net::TcpStream) -> Request {
+    let mut data = Vec::new();
+    let mut buf = [0; 4096];
+    let header_end = loop {
+        let n = stream.read(&mut buf).await.unwrap();
+        data.extend_from_slice(&buf[..n]);
+        if let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end + 4;
+        }
+        assert!(n > 0, "connection closed inside the request headers");
+    };
+    let head = String::from_utf8_lossy(&data[..header_end]).into_owned();
+    let content_length = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .map_or(0, |(_, value)| value.trim().parse().unwrap());
+    while data.len() < header_end + content_length {
+        let n = stream.read(&mut buf).await.unwrap();
+        assert!(n > 0, "connection closed inside the request body");
+        data.extend_from_slice(&buf[..n]);
+    }
+    let mut request_line = head.split_whitespace();
+    Request {
+        method: request_line.next().unwrap().to_owned(),
+        target: request_line.next().unwrap().to_owned(),
+        headers: head
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.to_ascii_lowercase(), value.trim().to_owned()))
+            .collect(),
+        body: String::from_utf8_lossy(&data[header_end..]).into_owned(),
+    }
+}
+
+impl Request {
+    /// The value of the header called `name`, in lowercase.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A Route53 client that sends every request to `url` with static credentials and
+/// without retries.
+pub fn route53_client(url: &str) -> aws_sdk_route53::Client {
+    let config = aws_sdk_route53::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new("AKID", "secret", None, None, "test"))
+        .endpoint_url(url)
+        .http_client(plain_http_client())
+        .retry_config(RetryConfig::disabled())
+        .build();
+    aws_sdk_route53::Client::from_conf(config)
+}
+
+/// AWS settings like [`route53_client`]'s for every service: requests go to `url`,
+/// signed with the static access key `AKID`.
+pub fn aws_config_loader(url: &str) -> ConfigLoader {
+    aws_config::defaults(BehaviorVersion::latest())
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new("AKID", "secret", None, None, "test"))
+        .endpoint_url(url)
+        .http_client(plain_http_client())
+        .retry_config(RetryConfig::disabled())
+}
+
+/// An AWS HTTP client that also speaks plain HTTP, which the local servers use.
+fn plain_http_client() -> SharedHttpClient {
+    let connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_webpki_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    HyperClientBuilder::new().build(connector)
+}
+
+/// A zone for `home.example.com` read from the config file format, with `extra` lines
+/// added to the table.
+pub fn zone(extra: &str) -> HostedZoneConfig {
+    toml::from_str(&format!(
+        "update_frequency_minutes = 5\n\
+         zone_name = \"example.com\"\n\
+         record_name = \"home\"\n\
+         ipv4 = true\n\
+         ipv6 = false\n\
+         ttl_seconds = 300\n\
+         {extra}"
+    ))
+    .unwrap()
+}
+
+/// A single-run context with fresh state and no side effects.
+pub fn context() -> UpdateContext {
+    UpdateContext {
+        daemon: false,
+        dry_run: false,
+        state: DaemonState::shared(),
+        history: None,
+        statsd: None,
+        sync_tracker: None,
+        state_file: None,
+        clients: ZoneClients::default(),
+        notifier: Notifier::default(),
+        shutdown: CancellationToken::new(),
+    }
+}
+
+/// A path in the temp directory that no other test uses.
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("route53_dd-{}-{name}", std::process::id()))
+}
+
+/// Writes a small zone file for `example.com` to a fresh temp path, returning the path.
+pub fn zone_file(name: &str, records: &str) -> PathBuf {
+    let path = temp_path(name);
+    std::fs::write(
+        &path,
+        format!(
+            "$ORIGIN example.com.\n\
+             $TTL 3600\n\
+             @ IN SOA ns1.example.com. hostmaster.example.com. ( 2024010101 7200 3600 1209600 300 )\n\
+             @ IN NS ns1.example.com.\n\
+             {records}"
+        ),
+    )
+    .unwrap();
+    path
+}
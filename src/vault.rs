@@ -0,0 +1,219 @@
+//! Reads the keys used for Route53 from HashiCorp Vault, either minted by its AWS secrets
+//! engine or stored in a KV secret.
+
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Context, Error};
+use aws_credential_types::{
+    provider::{self, error::CredentialsError, future, ProvideCredentials},
+    Credentials,
+};
+use clap::ValueEnum;
+use log::info;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::credential_provider::AwsCredentials;
+
+const VAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How the secret at `path` holds the keys.
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VaultEngine {
+    /// Vault's AWS secrets engine, e.g. `aws/creds/route53`, which mints keys for a lease.
+    #[default]
+    Aws,
+    /// A KV version 2 secret, e.g. `secret/data/route53_dd`, holding `access_key_id`,
+    /// `secret_access_key` and optionally `session_token`.
+    Kv,
+}
+
+/// Where in Vault the keys are and how to log in: with a token, or with an AppRole's
+/// role and secret ids.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct VaultConfig {
+    /// e.g. `https://vault.example.com:8200`.
+    pub address: String,
+    #[serde(default)]
+    pub token: Option<String>,
+    #[serde(default)]
+    pub role_id: Option<String>,
+    #[serde(default)]
+    pub secret_id: Option<String>,
+    pub path: String,
+    #[serde(default)]
+    pub engine: VaultEngine,
+}
+
+impl std::fmt::Debug for VaultConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let blank = "********";
+        f.debug_struct("VaultConfig")
+            .field("address", &self.address)
+            .field("token", &self.token.as_ref().map(|_| blank))
+            .field("role_id", &self.role_id)
+            .field("secret_id", &self.secret_id.as_ref().map(|_| blank))
+            .field("path", &self.path)
+            .field("engine", &self.engine)
+            .finish()
+    }
+}
+
+impl VaultConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        match (&self.token, &self.role_id) {
+            (Some(_), None) => Ok(()),
+            (None, Some(_)) => Ok(()),
+            _ => Err(anyhow!(
+                "Vault needs either a token or an AppRole role_id, but not both."
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Login {
+    auth: LoginAuth,
+}
+
+#[derive(Deserialize)]
+struct LoginAuth {
+    client_token: String,
+}
+
+#[derive(Deserialize)]
+struct Secret<T> {
+    data: T,
+    #[serde(default)]
+    lease_duration: u64,
+}
+
+/// The `data` of an AWS secrets engine lease. `security_token` is only set for the
+/// STS credential types.
+#[derive(Deserialize)]
+struct AwsEngineKeys {
+    access_key: String,
+    secret_key: String,
+    #[serde(default)]
+    security_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct KvData {
+    data: AwsCredentials,
+}
+
+/// Fetches keys from Vault every time the SDK's credential cache wants fresh ones. With
+/// the AWS secrets engine, the credentials expire with their lease, so new keys are
+/// minted shortly before it ends. Prefer its `assumed_role` or `federation_token`
+/// credential types: keys of new IAM users take a few seconds before AWS accepts them.
+#[derive(Debug)]
+pub struct VaultCredentials {
+    config: VaultConfig,
+    client: reqwest::Client,
+}
+
+impl VaultCredentials {
+    pub fn new(config: VaultConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!(
+            "{}/v1/{}",
+            self.config.address.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        )
+    }
+
+    async fn token(&self) -> Result<String, Error> {
+        if let Some(token) = &self.config.token {
+            return Ok(token.clone());
+        }
+        let login: Login = self
+            .client
+            .post(self.url("auth/approle/login"))
+            .timeout(VAULT_TIMEOUT)
+            .json(&json!({
+                "role_id": self.config.role_id,
+                "secret_id": self.config.secret_id,
+            }))
+            .send()
+            .await?
+            .error_for_status()
+            .context("Vault AppRole login failed")?
+            .json()
+            .await?;
+        Ok(login.auth.client_token)
+    }
+
+    async fn read<T: for<'de> Deserialize<'de>>(&self) -> Result<Secret<T>, Error> {
+        let token = self.token().await?;
+        let secret = self
+            .client
+            .get(self.url(&self.config.path))
+            .timeout(VAULT_TIMEOUT)
+            .header("X-Vault-Token", token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(secret)
+    }
+
+    async fn credentials(&self) -> Result<Credentials, Error> {
+        let path = &self.config.path;
+        let credentials = match self.config.engine {
+            VaultEngine::Aws => {
+                let secret: Secret<AwsEngineKeys> = self
+                    .read()
+                    .await
+                    .with_context(|| format!("Failed to read AWS keys from Vault at {path}"))?;
+                let expiry = (secret.lease_duration > 0)
+                    .then(|| SystemTime::now() + Duration::from_secs(secret.lease_duration));
+                AwsCredentials {
+                    access_key_id: secret.data.access_key,
+                    secret_access_key: secret.data.secret_key,
+                    session_token: secret.data.security_token,
+                    expires_after: expiry,
+                }
+            }
+            VaultEngine::Kv => {
+                let secret: Secret<KvData> = self
+                    .read()
+                    .await
+                    .with_context(|| format!("Failed to read AWS keys from Vault at {path}"))?;
+                secret.data.data
+            }
+        };
+        info!("Read AWS credentials from Vault at {path}");
+        Ok(Credentials::new(
+            credentials.access_key_id,
+            credentials.secret_access_key,
+            credentials.session_token,
+            credentials.expires_after,
+            "Vault",
+        ))
+    }
+
+    async fn provide(&self) -> provider::Result {
+        self.credentials()
+            .await
+            .map_err(CredentialsError::provider_error)
+    }
+}
+
+impl ProvideCredentials for VaultCredentials {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(self.provide())
+    }
+}
@@ -0,0 +1,354 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+use aws_sdk_route53::{error::ProvideErrorMetadata, types::TagResourceType, Client};
+use log::{debug, warn};
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+
+use crate::{aws_errors, canonical_name, providers::route53::ZoneVisibility, HostedZoneConfig};
+
+/// Route53 only accepts ten resource ids per `ListTagsForResources` call.
+const TAG_BATCH_SIZE: usize = 10;
+
+/// Attempts made to resolve a zone in daemon mode before giving up until the next tick.
+const RESOLVE_ATTEMPTS: u32 = 4;
+
+/// Finds and validates the hosted zone. In daemon mode a failed resolution is retried
+/// with exponential backoff until `shutdown` is cancelled, since a just-created zone can
+/// briefly look incomplete. A configured `hosted_zone_id` is used as is, so it needs
+/// neither list nor get permissions.
+pub async fn resolve_hosted_zone(
+    client: &Client,
+    zone: &HostedZoneConfig,
+    daemon: bool,
+    shutdown: &CancellationToken,
+) -> Result<String, Error> {
+    if let Some(id) = &zone.hosted_zone_id {
+        return Ok(id.trim_start_matches("/hostedzone/").to_owned());
+    }
+    let attempts = if daemon { RESOLVE_ATTEMPTS } else { 1 };
+    let mut delay = Duration::from_secs(1);
+    let mut attempt = 1;
+    loop {
+        let resolved = match find_hosted_zone(client, zone).await {
+            Ok(id) => validate_hosted_zone(client, &id).await.map(|_| id),
+            Err(e) => Err(e),
+        };
+        match resolved {
+            Err(e) if attempt < attempts => {
+                warn!(
+                    "Resolving hosted zone {} failed: {e:?}. Retrying in {delay:?}.",
+                    zone.zone_name
+                );
+                select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = shutdown.cancelled() => return Err(e),
+                }
+                delay *= 2;
+                attempt += 1;
+            }
+            resolved => return resolved,
+        }
+    }
+}
+
+/// Checks that the zone looks usable: it has an id and, if public, name servers.
+/// Skipped when the credentials may not call `GetHostedZone`.
+async fn validate_hosted_zone(client: &Client, id: &str) -> Result<(), Error> {
+    if id.trim().is_empty() {
+        return Err(anyhow!("Route53 returned a hosted zone without an id."));
+    }
+    let output = match client.get_hosted_zone().id(id).send().await {
+        Ok(output) => output,
+        Err(e) if e.code() == Some("AccessDenied") => {
+            debug!("Not allowed to call GetHostedZone, skipping validation of {id}.");
+            return Ok(());
+        }
+        Err(e) => return Err(aws_errors::explain_denied(e)),
+    };
+    let hosted_zone = output
+        .hosted_zone
+        .ok_or(anyhow!("GetHostedZone returned no hosted zone for {id}."))?;
+    let private = hosted_zone
+        .config
+        .as_ref()
+        .is_some_and(|config| config.private_zone);
+    let name_servers = output
+        .delegation_set
+        .map(|set| set.name_servers)
+        .unwrap_or_default();
+    if !private && name_servers.is_empty() {
+        return Err(anyhow!(
+            "Hosted zone {} ({id}) has no name servers yet, it may still be being created.",
+            hosted_zone.name
+        ));
+    }
+    Ok(())
+}
+
+/// Resolves the id of the hosted zone the record should be written to.
+pub async fn find_hosted_zone(client: &Client, zone: &HostedZoneConfig) -> Result<String, Error> {
+    if let Some(id) = &zone.hosted_zone_id {
+        return Ok(id.trim_start_matches("/hostedzone/").to_owned());
+    }
+    if let Some(tag) = &zone.zone_tag {
+        let (key, value) = parse_tag(tag)?;
+        return find_hosted_zone_by_tag(client, key, value).await;
+    }
+
+    find_hosted_zone_by_name(client, &zone.zone_name, zone.zone_visibility).await
+}
+
+/// Finds the zone named exactly `zone_name` with the wanted visibility. Zones are listed
+/// in name order starting at `zone_name`, so once a page holds another name there are
+/// no more matches.
+async fn find_hosted_zone_by_name(
+    client: &Client,
+    zone_name: &str,
+    visibility: ZoneVisibility,
+) -> Result<String, Error> {
+    let wanted = canonical_name(zone_name);
+    let mut matching = Vec::new();
+    let (mut dns_name, mut hosted_zone_id) = (Some(wanted.clone()), None);
+    loop {
+        let page = client
+            .list_hosted_zones_by_name()
+            .set_dns_name(dns_name)
+            .set_hosted_zone_id(hosted_zone_id)
+            .send()
+            .await
+            .map_err(aws_errors::explain_denied)?;
+        let listed = page.hosted_zones.len();
+        let named: Vec<_> = page
+            .hosted_zones
+            .into_iter()
+            .filter(|zone| canonical_name(&zone.name) == wanted)
+            .collect();
+        let done = !page.is_truncated || named.len() < listed;
+        matching.extend(
+            named
+                .into_iter()
+                .filter(|zone| {
+                    let private = zone.config.as_ref().is_some_and(|c| c.private_zone);
+                    visibility.allows(private)
+                })
+                .map(|zone| zone.id),
+        );
+        if done {
+            break;
+        }
+        (dns_name, hosted_zone_id) = (page.next_dns_name, page.next_hosted_zone_id);
+    }
+    match matching.len() {
+        0 => Err(match visibility {
+            ZoneVisibility::Public => anyhow!("No public hosted zone is named {wanted}."),
+            ZoneVisibility::Private => anyhow!("No private hosted zone is named {wanted}."),
+            ZoneVisibility::Any => anyhow!("No hosted zone is named {wanted}."),
+        }),
+        1 => Ok(matching.remove(0)),
+        _ => Err(anyhow!(
+            "Multiple hosted zones are named {wanted}: {}. Select one with --zone-visibility, \
+             --hosted-zone-id or --zone-tag.",
+            matching.join(", ")
+        )),
+    }
+}
+
+/// Splits a `key=value` tag filter.
+pub fn parse_tag(tag: &str) -> Result<(&str, &str), Error> {
+    tag.split_once('=')
+        .filter(|(key, _)| !key.is_empty())
+        .ok_or(anyhow!("Zone tag {tag:?} must be in the form key=value."))
+}
+
+async fn find_hosted_zone_by_tag(client: &Client, key: &str, value: &str) -> Result<String, Error> {
+    let mut zone_ids = Vec::new();
+    let mut marker = None;
+    loop {
+        let page = client
+            .list_hosted_zones()
+            .set_marker(marker)
+            .send()
+            .await
+            .map_err(aws_errors::explain_denied)?;
+        zone_ids.extend(
+            page.hosted_zones
+                .into_iter()
+                .map(|zone| zone.id.trim_start_matches("/hostedzone/").to_owned()),
+        );
+        if !page.is_truncated {
+            break;
+        }
+        marker = page.next_marker;
+    }
+
+    let mut tagged = Vec::with_capacity(zone_ids.len());
+    for ids in zone_ids.chunks(TAG_BATCH_SIZE) {
+        let tags = client
+            .list_tags_for_resources()
+            .resource_type(TagResourceType::Hostedzone)
+            .set_resource_ids(Some(ids.to_vec()))
+            .send()
+            .await
+            .map_err(aws_errors::explain_denied)?;
+        for set in tags.resource_tag_sets {
+            let Some(id) = set.resource_id else {
+                continue;
+            };
+            let tags = set
+                .tags
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|tag| Some((tag.key?, tag.value.unwrap_or_default())))
+                .collect();
+            tagged.push((id, tags));
+        }
+    }
+
+    select_by_tag(tagged, key, value)
+}
+
+/// Picks the single zone carrying the `key=value` tag, erroring if none or several do.
+pub fn select_by_tag(
+    zones: Vec<(String, Vec<(String, String)>)>,
+    key: &str,
+    value: &str,
+) -> Result<String, Error> {
+    let mut matching: Vec<String> = zones
+        .into_iter()
+        .filter(|(_, tags)| tags.iter().any(|(k, v)| k == key && v == value))
+        .map(|(id, _)| id)
+        .collect();
+    match matching.len() {
+        0 => Err(anyhow!("No hosted zone is tagged {key}={value}.")),
+        1 => Ok(matching.remove(0)),
+        _ => Err(anyhow!(
+            "Multiple hosted zones are tagged {key}={value}: {}",
+            matching.join(", ")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{http_server, route53_client, zone};
+
+    const XMLNS: &str = "https://route53.amazonaws.com/doc/2013-04-01/";
+
+    /// Answers `ListHostedZonesByName` with `example.com` as zone `Z1`, and
+    /// `GetHostedZone` with that zone and no delegation set.
+    fn route53(request: &crate::test_util::Request) -> (u16, String) {
+        let zone = "<HostedZone><Id>/hostedzone/Z1</Id><Name>example.com.</Name>\
+                    <CallerReference>x</CallerReference>\
+                    <Config><PrivateZone>false</PrivateZone></Config></HostedZone>";
+        if request.target.starts_with("/2013-04-01/hostedzonesbyname") {
+            (
+                200,
+                format!(
+                    "<ListHostedZonesByNameResponse xmlns=\"{XMLNS}\"><HostedZones>{zone}</HostedZones>\
+                     <DNSName>example.com.</DNSName><IsTruncated>false</IsTruncated>\
+                     <MaxItems>100</MaxItems></ListHostedZonesByNameResponse>"
+                ),
+            )
+        } else {
+            (
+                200,
+                format!("<GetHostedZoneResponse xmlns=\"{XMLNS}\">{zone}</GetHostedZoneResponse>"),
+            )
+        }
+    }
+
+    fn tagged(id: &str, tags: &[(&str, &str)]) -> (String, Vec<(String, String)>) {
+        let tags = tags
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        (id.to_owned(), tags)
+    }
+
+    #[test]
+    fn selects_the_one_tagged_zone() {
+        let zones = vec![
+            tagged("Z1", &[("env", "staging")]),
+            tagged("Z2", &[("team", "home"), ("env", "prod")]),
+        ];
+        assert_eq!(select_by_tag(zones, "env", "prod").unwrap(), "Z2");
+    }
+
+    #[test]
+    fn fails_without_a_tagged_zone() {
+        let zones = vec![tagged("Z1", &[("env", "staging")]), tagged("Z2", &[])];
+        let err = select_by_tag(zones, "env", "prod").unwrap_err();
+        assert_eq!(err.to_string(), "No hosted zone is tagged env=prod.");
+    }
+
+    #[test]
+    fn fails_when_several_zones_are_tagged() {
+        let zones = vec![
+            tagged("Z1", &[("env", "prod")]),
+            tagged("Z2", &[("env", "staging")]),
+            tagged("Z3", &[("env", "prod")]),
+        ];
+        let err = select_by_tag(zones, "env", "prod").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Multiple hosted zones are tagged env=prod: Z1, Z3"
+        );
+    }
+
+    #[tokio::test]
+    async fn a_configured_zone_id_needs_no_lookup() {
+        let (url, requests) = http_server(route53).await;
+        let zone = zone("hosted_zone_id = \"/hostedzone/Z9\"");
+
+        let id = resolve_hosted_zone(
+            &route53_client(&url),
+            &zone,
+            true,
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(id, "Z9");
+        assert!(requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_public_zone_without_name_servers_is_rejected() {
+        let (url, requests) = http_server(route53).await;
+
+        let err = resolve_hosted_zone(
+            &route53_client(&url),
+            &zone(""),
+            false,
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(err.to_string().contains("no name servers"), "{err}");
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].target, "/2013-04-01/hostedzone/Z1");
+    }
+
+    #[tokio::test]
+    async fn shutdown_ends_the_backoff() {
+        let (url, requests) = http_server(route53).await;
+        let shutdown = CancellationToken::new();
+        shutdown.cancel();
+
+        let resolved = tokio::time::timeout(
+            Duration::from_millis(500),
+            resolve_hosted_zone(&route53_client(&url), &zone(""), true, &shutdown),
+        )
+        .await
+        .expect("the backoff ignored the shutdown");
+
+        assert!(resolved.is_err());
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+}